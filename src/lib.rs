@@ -0,0 +1,3336 @@
+//! Parsing and serialization of Emacs org-mode files.
+//!
+//! [`parse`] turns org text into a tree of [`OrgNote`]s and [`serialize`] writes such a
+//! tree back out as org text. The `rorg` binary builds its CLI and TUI on top of this.
+//!
+//! ```
+//! let notes = rorg::parse("* TODO Write docs :work:\nFirst draft.");
+//! assert_eq!(notes[0].status.as_deref(), Some("TODO"));
+//! assert_eq!(notes[0].title, "Write docs");
+//!
+//! let org = rorg::serialize(&notes);
+//! assert!(org.starts_with("* TODO Write docs :work:\n"));
+//! ```
+
+use chrono::{
+	Datelike, Days, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use unicode_width::UnicodeWidthStr;
+
+#[cfg(test)]
+mod tests;
+
+/// An org timestamp such as `<2024-01-20 Sat 09:00>` or `[2024-01-01 Mon]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgTimestamp {
+	pub year: u32,
+	pub month: u32,
+	pub day: u32,
+	pub hour: Option<u32>,
+	pub minute: Option<u32>,
+	pub day_name: Option<String>,
+	/// The timestamp exactly as written, brackets included.
+	pub raw: String,
+}
+
+/// The brackets of a timestamp: `<...>` for active timestamps, which show up in
+/// the agenda, and `[...]` for inactive ones such as clock times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampKind {
+	Active,
+	Inactive,
+}
+
+/// A `CLOCK:` line. `end` and `duration` are `None` while the clock is running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgClockEntry {
+	pub start: OrgTimestamp,
+	pub end: Option<OrgTimestamp>,
+	/// Duration text after `=>`, e.g. `"2:30"`.
+	pub duration: Option<String>,
+	/// The full line as written.
+	pub raw: String,
+}
+
+/// The contents of a `:LOGBOOK:` drawer, or a note's bare `CLOCK:` lines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgLogbook {
+	pub clock_entries: Vec<OrgClockEntry>,
+	pub raw_content: Vec<String>,
+	/// The clocks were written without a drawer, and are written back that way.
+	#[serde(default)]
+	pub inline: bool,
+}
+
+/// A named drawer other than `:LOGBOOK:`, e.g. `:PROPERTIES:` or `:NOTES:`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgDrawer {
+	pub name: String,
+	/// Lines between the `:NAME:` and `:END:` markers, as written.
+	pub lines: Vec<String>,
+}
+
+/// A link found in a note's content, either `[[target][description]]`,
+/// `[[target]]` or a bare `http(s)://` URL.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrgLink {
+	pub target: String,
+	pub description: Option<String>,
+}
+
+/// A table found in a note's content: consecutive lines starting with `|`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrgTable {
+	/// Trimmed cells of each row, without the separator rows.
+	pub rows: Vec<Vec<String>>,
+	/// Where the `|---+---|` separators are, as the number of rows above each.
+	pub separators: Vec<usize>,
+}
+
+/// The `SCHEDULED:`, `DEADLINE:` and `CLOSED:` timestamps of a heading.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrgPlanning {
+	pub scheduled: Option<OrgTimestamp>,
+	pub deadline: Option<OrgTimestamp>,
+	pub closed: Option<OrgTimestamp>,
+}
+
+/// A heading together with everything up to the next heading of the same or higher level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgNote {
+	/// Identifies the note while the program runs, so it can be found again after
+	/// the tree changes. Assigned on creation and kept by clones; not written out.
+	#[serde(skip, default = "next_note_id")]
+	pub id: u64,
+	/// Number of asterisks.
+	pub level: usize,
+	/// Status keyword such as `TODO` or `DONE`.
+	pub status: Option<String>,
+	/// Letter from a `[#A]` cookie after the status, which is not part of `title`.
+	pub priority: Option<char>,
+	pub title: String,
+	/// Tags from the end of the heading line.
+	pub labels: Vec<String>,
+	/// Body text, without planning lines and the logbook.
+	pub content: String,
+	pub children: Vec<OrgNote>,
+	pub planning: Option<OrgPlanning>,
+	pub logbook: Option<OrgLogbook>,
+	/// Drawers other than the logbook, in source order.
+	pub drawers: Vec<OrgDrawer>,
+	/// Links found in `content`, which is left as written.
+	pub links: Vec<OrgLink>,
+	/// Tables found in `content`, which is left as written.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub tables: Vec<OrgTable>,
+	/// The file the note was read from, set when notes from several files are
+	/// combined.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub source_file: Option<String>,
+	/// 1-based line of the heading in the parsed text. `None` for notes that
+	/// were not parsed.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub source_line: Option<usize>,
+	/// How many children [`prune_depth`] removed, when asked to count them.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub children_count: Option<usize>,
+	/// Indentation of the planning lines and drawer markers below the heading, as
+	/// parsed, so they are written back indented the same way. Content keeps its
+	/// own indentation line by line.
+	#[serde(skip)]
+	pub body_indent: String,
+	/// Source order of the blocks below the heading, so serializing keeps it.
+	/// `None` for notes that were not parsed, which are written in a fixed order.
+	#[serde(skip)]
+	pub layout: Option<Vec<OrgBlock>>,
+}
+
+static NEXT_NOTE_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_note_id() -> u64 {
+	NEXT_NOTE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+const PLANNING_KEYWORDS: [(&str, OrgBlock); 3] = [
+	("SCHEDULED:", OrgBlock::Scheduled),
+	("DEADLINE:", OrgBlock::Deadline),
+	("CLOSED:", OrgBlock::Closed),
+];
+
+/// A block below a heading, in the order it appeared in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrgBlock {
+	Scheduled,
+	Deadline,
+	Closed,
+	/// The next planning keyword shares the previous one's line, as in
+	/// `DEADLINE: <...> SCHEDULED: <...>`.
+	SameLine,
+	Logbook,
+	/// Index into [`OrgNote::drawers`].
+	Drawer(usize),
+	/// This many consecutive lines of [`OrgNote::content`].
+	Content(usize),
+}
+
+impl OrgNote {
+	pub fn new(level: usize, title: String) -> Self {
+		Self {
+			id: next_note_id(),
+			level,
+			status: None,
+			priority: None,
+			title,
+			labels: Vec::new(),
+			content: String::new(),
+			children: Vec::new(),
+			planning: None,
+			logbook: None,
+			drawers: Vec::new(),
+			links: Vec::new(),
+			tables: Vec::new(),
+			children_count: None,
+			body_indent: String::new(),
+			source_file: None,
+			source_line: None,
+			layout: None,
+		}
+	}
+
+	/// The estimate from the note's `EFFORT` property, written `H:MM` like `1:30`
+	/// or as plain minutes like `45`.
+	pub fn effort_minutes(&self) -> Option<u32> {
+		let effort = self.property("EFFORT")?;
+		match effort.split_once(':') {
+			Some((hours, minutes)) => {
+				let minutes = minutes
+					.parse::<u32>()
+					.ok()
+					.filter(|&minutes| minutes < 60)?;
+				Some(hours.parse::<u32>().ok()? * 60 + minutes)
+			},
+			None => effort.parse().ok(),
+		}
+	}
+
+	/// Sets `source_file` on the note and its whole subtree.
+	pub fn set_source_file(&mut self, path: &str) {
+		self.source_file = Some(path.to_string());
+		for child in &mut self.children {
+			child.set_source_file(path);
+		}
+	}
+
+	/// Starts building a note, e.g.
+	/// `OrgNote::builder(1, "Task").status("TODO").tag("work").build()`.
+	pub fn builder(level: usize, title: impl Into<String>) -> OrgNoteBuilder {
+		OrgNoteBuilder {
+			note: OrgNote::new(level, title.into()),
+		}
+	}
+
+	/// The status keyword, priority cookie and title as shown on the heading
+	/// line, without stray spaces when any is missing.
+	pub fn heading_text(&self) -> String {
+		let cookie = self.priority.map(|letter| format!("[#{}]", letter));
+		self.status
+			.iter()
+			.map(String::as_str)
+			.chain(cookie.as_deref())
+			.chain(Some(self.title.as_str()))
+			.filter(|part| !part.is_empty())
+			.collect::<Vec<_>>()
+			.join(" ")
+	}
+
+	/// Number of words in this note's content, not counting children.
+	pub fn word_count(&self) -> usize {
+		prose_lines(&self.content)
+			.flat_map(str::split_whitespace)
+			.filter(|word| word.chars().any(char::is_alphanumeric))
+			.count()
+	}
+
+	/// Number of characters in this note's content, not counting children
+	/// or line breaks.
+	pub fn char_count(&self) -> usize {
+		prose_lines(&self.content)
+			.map(|line| line.chars().count())
+			.sum()
+	}
+
+	/// [`word_count`](Self::word_count) of this note and all its descendants.
+	pub fn total_word_count(&self) -> usize {
+		self.word_count()
+			+ self
+				.children
+				.iter()
+				.map(OrgNote::total_word_count)
+				.sum::<usize>()
+	}
+
+	/// [`char_count`](Self::char_count) of this note and all its descendants.
+	pub fn total_char_count(&self) -> usize {
+		self.char_count()
+			+ self
+				.children
+				.iter()
+				.map(OrgNote::total_char_count)
+				.sum::<usize>()
+	}
+
+	/// Whether two notes have the same heading, planning times and children,
+	/// recursively: level, status, priority, title, tags in order, and each
+	/// planning timestamp's date and time. Raw text, day names, brackets,
+	/// indentation, content, drawers and clocks are not compared, so a tree
+	/// and its reformatted copy are structurally equal.
+	pub fn structural_eq(&self, other: &OrgNote) -> bool {
+		let instant = |timestamp: &OrgTimestamp| {
+			(
+				timestamp.year,
+				timestamp.month,
+				timestamp.day,
+				timestamp.hour,
+				timestamp.minute,
+			)
+		};
+		let planning = |note: &OrgNote| match &note.planning {
+			Some(planning) => [&planning.scheduled, &planning.deadline, &planning.closed]
+				.map(|timestamp| timestamp.as_ref().map(instant)),
+			None => [None; 3],
+		};
+		self.level == other.level
+			&& self.status == other.status
+			&& self.priority == other.priority
+			&& self.title == other.title
+			&& self.labels == other.labels
+			&& planning(self) == planning(other)
+			&& self.children.len() == other.children.len()
+			&& self
+				.children
+				.iter()
+				.zip(&other.children)
+				.all(|(child, other)| child.structural_eq(other))
+	}
+
+	/// Whether the heading has org's special `ARCHIVE` tag. Like org, summaries
+	/// skip archived subtrees, but they are still parsed and serialized.
+	pub fn is_archived(&self) -> bool {
+		self.labels.iter().any(|label| label == "ARCHIVE")
+	}
+
+	/// The value of a `:NAME: value` line in the note's `:PROPERTIES:` drawer.
+	/// Names match case-insensitively, like org's.
+	pub fn property(&self, name: &str) -> Option<&str> {
+		self.drawers
+			.iter()
+			.filter(|drawer| drawer.name.eq_ignore_ascii_case("PROPERTIES"))
+			.flat_map(|drawer| &drawer.lines)
+			.find_map(|line| {
+				let (key, value) = line.trim().strip_prefix(':')?.split_once(':')?;
+				key.eq_ignore_ascii_case(name).then(|| value.trim())
+			})
+	}
+
+	/// The note's `CATEGORY` property, or else `inherited`, the category of its
+	/// nearest ancestor that has one. Like org's, the category is inherited.
+	pub fn category(&self, inherited: Option<&str>) -> Option<String> {
+		self.property("CATEGORY").or(inherited).map(str::to_string)
+	}
+
+	/// Whether the note has an `ORDERED` property other than `nil`, so its
+	/// children are meant to be done in order; see [`blocked_by_order`].
+	pub fn is_ordered(&self) -> bool {
+		self.property("ORDERED")
+			.is_some_and(|value| !value.is_empty() && !value.eq_ignore_ascii_case("nil"))
+	}
+
+	/// Sets `:NAME: value` in the `:PROPERTIES:` drawer, replacing the line for
+	/// `name` if there is one. A missing drawer is added below the planning lines.
+	pub fn set_property(&mut self, name: &str, value: &str) {
+		let line = format!(":{}: {}", name, value);
+		let drawer = match self
+			.drawers
+			.iter()
+			.position(|drawer| drawer.name.eq_ignore_ascii_case("PROPERTIES"))
+		{
+			Some(i) => &mut self.drawers[i],
+			None => {
+				if let Some(layout) = &mut self.layout {
+					let after_planning = layout
+						.iter()
+						.rposition(|block| {
+							matches!(
+								block,
+								OrgBlock::Scheduled
+									| OrgBlock::Deadline | OrgBlock::Closed
+									| OrgBlock::SameLine
+							)
+						})
+						.map_or(0, |i| i + 1);
+					layout.insert(after_planning, OrgBlock::Drawer(self.drawers.len()));
+				}
+				self.drawers.push(OrgDrawer {
+					name: "PROPERTIES".to_string(),
+					lines: Vec::new(),
+				});
+				self.drawers.last_mut().unwrap()
+			},
+		};
+		let existing = drawer.lines.iter().position(|l| {
+			l.trim()
+				.strip_prefix(':')
+				.and_then(|rest| rest.split_once(':'))
+				.is_some_and(|(key, _)| key.eq_ignore_ascii_case(name))
+		});
+		match existing {
+			Some(i) => drawer.lines[i] = line,
+			None => drawer.lines.push(line),
+		}
+	}
+
+	/// For a task being marked done whose SCHEDULED or DEADLINE timestamp has a
+	/// repeater, moves each such timestamp forward (see
+	/// [`OrgTimestamp::advance_repeater`]) and records `now` in the
+	/// `LAST_REPEAT` property, as org does. Returns false, changing nothing, if
+	/// neither timestamp repeats. Resetting the status is left to the caller.
+	pub fn repeat_task(&mut self, now: NaiveDateTime) -> bool {
+		let Some(planning) = &mut self.planning else {
+			return false;
+		};
+		let mut repeated = false;
+		for timestamp in [&mut planning.scheduled, &mut planning.deadline]
+			.into_iter()
+			.flatten()
+		{
+			repeated |= timestamp.advance_repeater(now);
+		}
+		if repeated {
+			let done_at = OrgTimestamp::from_datetime(now, TimestampKind::Inactive);
+			self.set_property("LAST_REPEAT", &done_at.raw);
+		}
+		repeated
+	}
+}
+
+/// Fluent construction of an [`OrgNote`], from [`OrgNote::builder`]. Planning and
+/// the logbook are only created once something is put in them, and the note
+/// serializes in the fixed planning/drawers/logbook/content order.
+#[derive(Debug, Clone)]
+pub struct OrgNoteBuilder {
+	note: OrgNote,
+}
+
+impl OrgNoteBuilder {
+	pub fn status(mut self, status: impl Into<String>) -> Self {
+		self.note.status = Some(status.into());
+		self
+	}
+
+	pub fn priority(mut self, letter: char) -> Self {
+		self.note.priority = Some(letter);
+		self
+	}
+
+	/// Adds a tag, unless the note already has it.
+	pub fn tag(mut self, tag: impl Into<String>) -> Self {
+		let tag = tag.into();
+		if !self.note.labels.contains(&tag) {
+			self.note.labels.push(tag);
+		}
+		self
+	}
+
+	/// Sets the body text, and the links and tables found in it.
+	pub fn content(mut self, content: impl Into<String>) -> Self {
+		self.note.content = content.into();
+		self.note.links = extract_links(&self.note.content);
+		self.note.tables = extract_tables(&self.note.content);
+		self
+	}
+
+	pub fn scheduled(mut self, timestamp: OrgTimestamp) -> Self {
+		self.planning().scheduled = Some(timestamp);
+		self
+	}
+
+	pub fn deadline(mut self, timestamp: OrgTimestamp) -> Self {
+		self.planning().deadline = Some(timestamp);
+		self
+	}
+
+	pub fn closed(mut self, timestamp: OrgTimestamp) -> Self {
+		self.planning().closed = Some(timestamp);
+		self
+	}
+
+	/// Appends a clock entry to the logbook.
+	pub fn clock(mut self, entry: OrgClockEntry) -> Self {
+		let logbook = self.note.logbook.get_or_insert_with(|| OrgLogbook {
+			clock_entries: Vec::new(),
+			raw_content: Vec::new(),
+			inline: false,
+		});
+		logbook.raw_content.push(entry.raw.clone());
+		logbook.clock_entries.push(entry);
+		self
+	}
+
+	/// Appends a child, moving its subtree down to start one level below this
+	/// note if it is not already deeper.
+	pub fn child(mut self, mut child: OrgNote) -> Self {
+		if child.level <= self.note.level {
+			child.level = self.note.level + 1;
+			renumber_levels(std::slice::from_mut(&mut child));
+		}
+		self.note.children.push(child);
+		self
+	}
+
+	pub fn build(self) -> OrgNote {
+		self.note
+	}
+
+	fn planning(&mut self) -> &mut OrgPlanning {
+		self.note.planning.get_or_insert_with(OrgPlanning::default)
+	}
+}
+
+/// The letter of a `[#A]` priority cookie, or `None` if `word` is not one.
+fn parse_priority_cookie(word: &str) -> Option<char> {
+	let mut chars = word.strip_prefix("[#")?.strip_suffix(']')?.chars();
+	let letter = chars.next()?;
+	(chars.next().is_none() && letter.is_ascii_uppercase()).then_some(letter)
+}
+
+/// Whether `tag` can appear in a `:tag:` group: non-empty, letters, digits and `_@#%`.
+pub fn is_valid_tag(tag: &str) -> bool {
+	!tag.is_empty()
+		&& tag
+			.chars()
+			.all(|c| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '%'))
+}
+
+const DEFAULT_ACTIVE_KEYWORDS: [&str; 3] = ["TODO", "NEXT", "IN-PROGRESS"];
+const DEFAULT_DONE_KEYWORDS: [&str; 2] = ["DONE", "CANCELLED"];
+
+/// The status keywords that mark open and finished tasks. A status in neither
+/// list, such as a custom `WAITING`, is neither active nor done until it is
+/// added to one. The first active keyword is the one a
+/// [repeating task](OrgNote::repeat_task) goes back to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Keywords {
+	pub active: Vec<String>,
+	pub done: Vec<String>,
+}
+
+impl Default for Keywords {
+	/// `TODO`, `NEXT` and `IN-PROGRESS` open, `DONE` and `CANCELLED` finished.
+	fn default() -> Self {
+		Self {
+			active: DEFAULT_ACTIVE_KEYWORDS.map(String::from).to_vec(),
+			done: DEFAULT_DONE_KEYWORDS.map(String::from).to_vec(),
+		}
+	}
+}
+
+impl Keywords {
+	pub fn is_done(&self, status: &str) -> bool {
+		self.done.iter().any(|keyword| keyword == status)
+	}
+
+	pub fn is_active(&self, status: &str) -> bool {
+		self.active.iter().any(|keyword| keyword == status)
+	}
+
+	/// Whether the note has a status that is [active](Self::is_active).
+	pub fn is_active_task(&self, note: &OrgNote) -> bool {
+		note.status
+			.as_deref()
+			.is_some_and(|status| self.is_active(status))
+	}
+
+	/// Whether the note has a status that is [done](Self::is_done).
+	pub fn is_done_task(&self, note: &OrgNote) -> bool {
+		note.status
+			.as_deref()
+			.is_some_and(|status| self.is_done(status))
+	}
+
+	/// Whether the note is an [active task](Self::is_active_task) whose deadline
+	/// is before `now`. A deadline without a time passes at the end of its day.
+	pub fn is_overdue(&self, note: &OrgNote, now: NaiveDateTime) -> bool {
+		let Some(deadline) = note
+			.planning
+			.as_ref()
+			.and_then(|planning| planning.deadline.as_ref())
+		else {
+			return false;
+		};
+		self.is_active_task(note)
+			&& match deadline.hour {
+				Some(_) => deadline.to_naive_datetime().is_some_and(|due| due < now),
+				None => deadline
+					.to_naive_date()
+					.is_some_and(|date| date < now.date()),
+			}
+	}
+
+	/// The status a repeating task is reset to when no open one is known.
+	pub fn repeat_to(&self) -> &str {
+		self.active.first().map_or("TODO", String::as_str)
+	}
+}
+
+/// Finds the links in `text`, in the order they appear.
+pub fn extract_links(text: &str) -> Vec<OrgLink> {
+	let mut links = Vec::new();
+	let mut rest = text;
+
+	while let Some(c) = rest.chars().next() {
+		if let Some(inner) = rest.strip_prefix("[[")
+			&& let Some(end) = inner.find("]]")
+		{
+			let body = &inner[..end];
+			let (target, description) = match body.split_once("][") {
+				Some((target, description)) => (target, Some(description.to_string())),
+				None => (body, None),
+			};
+			links.push(OrgLink {
+				target: target.to_string(),
+				description,
+			});
+			rest = &inner[end + 2..];
+			continue;
+		}
+
+		let at_word_start = text.len() == rest.len()
+			|| text[..text.len() - rest.len()]
+				.chars()
+				.next_back()
+				.is_some_and(|prev| !prev.is_alphanumeric());
+		if at_word_start && (rest.starts_with("http://") || rest.starts_with("https://")) {
+			let end = rest
+				.find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '[' | ']'))
+				.unwrap_or(rest.len());
+			let url = rest[..end].trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+			links.push(OrgLink {
+				target: url.to_string(),
+				description: None,
+			});
+			rest = &rest[url.len()..];
+			continue;
+		}
+
+		rest = &rest[c.len_utf8()..];
+	}
+
+	links
+}
+
+/// Finds the tables in `text`, in the order they appear. Lines in a
+/// `#+BEGIN_X` ... `#+END_X` block are not tables.
+pub fn extract_tables(text: &str) -> Vec<OrgTable> {
+	let mut tables = Vec::new();
+	let mut current: Option<OrgTable> = None;
+
+	let lines: Vec<&str> = text.lines().collect();
+	for (line, is_table) in lines.iter().zip(table_line_flags(&lines)) {
+		let Some(row) = line.trim().strip_prefix('|').filter(|_| is_table) else {
+			tables.extend(current.take());
+			continue;
+		};
+		let table = current.get_or_insert_with(OrgTable::default);
+		if row.starts_with('-') {
+			table.separators.push(table.rows.len());
+		} else {
+			let row = row.strip_suffix('|').unwrap_or(row);
+			table
+				.rows
+				.push(row.split('|').map(|cell| cell.trim().to_string()).collect());
+		}
+	}
+	tables.extend(current);
+
+	tables
+}
+
+/// Whether each of `lines` is a table line, i.e. starts with `|` and is not in a
+/// closed `#+BEGIN_X` ... `#+END_X` block.
+fn table_line_flags(lines: &[&str]) -> Vec<bool> {
+	let mut flags = Vec::with_capacity(lines.len());
+	let mut block_end: Option<String> = None;
+	for (i, line) in lines.iter().enumerate() {
+		let trimmed = line.trim();
+		if let Some(end) = &block_end {
+			if trimmed.eq_ignore_ascii_case(end) {
+				block_end = None;
+			}
+			flags.push(false);
+			continue;
+		}
+		if let Some(kind) = OrgParser::block_kind(trimmed) {
+			let end = format!("#+END_{}", kind);
+			if lines[i + 1..]
+				.iter()
+				.any(|l| l.trim().eq_ignore_ascii_case(&end))
+			{
+				block_end = Some(end);
+				flags.push(false);
+				continue;
+			}
+		}
+		flags.push(trimmed.starts_with('|'));
+	}
+	flags
+}
+
+/// Lines up the columns of `table` the way org does: every row gets the same
+/// number of cells, and each cell is padded to its column's width. Columns
+/// where at least half the cells are numbers are right-aligned, others
+/// left-aligned.
+pub fn align_table(table: &mut OrgTable) {
+	let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+	for row in &mut table.rows {
+		row.resize(columns, String::new());
+		for cell in row.iter_mut() {
+			*cell = cell.trim().to_string();
+		}
+	}
+
+	for c in 0..columns {
+		let cells = || table.rows.iter().map(|row| row[c].as_str());
+		let width = cells().map(UnicodeWidthStr::width).max().unwrap_or(0);
+		let filled = cells().filter(|cell| !cell.is_empty()).count();
+		let numbers = cells().filter(|cell| cell.parse::<f64>().is_ok()).count();
+		let right = numbers > 0 && numbers * 2 >= filled;
+		for row in &mut table.rows {
+			let padding = " ".repeat(width - UnicodeWidthStr::width(row[c].as_str()));
+			row[c] = if right {
+				format!("{}{}", padding, row[c])
+			} else {
+				format!("{}{}", row[c], padding)
+			};
+		}
+	}
+}
+
+impl OrgTable {
+	/// The table as org text, with its cells as they are and a `|---+---|`
+	/// separator as wide as each column. See [`align_table`].
+	pub fn to_org(&self) -> String {
+		let columns = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+		let widths: Vec<usize> = (0..columns)
+			.map(|c| {
+				self.rows
+					.iter()
+					.filter_map(|row| row.get(c))
+					.map(|cell| UnicodeWidthStr::width(cell.as_str()))
+					.max()
+					.unwrap_or(0)
+			})
+			.collect();
+		let separator = format!(
+			"|{}|",
+			widths
+				.iter()
+				.map(|width| "-".repeat(width + 2))
+				.collect::<Vec<_>>()
+				.join("+")
+		);
+
+		let mut lines = Vec::new();
+		for i in 0..=self.rows.len() {
+			for _ in self.separators.iter().filter(|&&above| above == i) {
+				lines.push(separator.clone());
+			}
+			if let Some(row) = self.rows.get(i) {
+				lines.push(format!("| {} |", row.join(" | ")));
+			}
+		}
+		lines.join("\n")
+	}
+
+	/// The table as a Markdown table. Markdown needs a header, so the first row
+	/// is used as one even without a separator below it; other separators are
+	/// dropped. Short rows are padded with empty cells.
+	pub fn to_markdown(&self) -> String {
+		let columns = self.rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+		let mut lines = Vec::new();
+		for (i, row) in self.rows.iter().enumerate() {
+			let cells: Vec<String> = (0..columns)
+				.map(|c| {
+					row.get(c)
+						.map_or(String::new(), |cell| cell.replace('|', "\\|"))
+				})
+				.collect();
+			lines.push(format!("| {} |", cells.join(" | ")));
+			if i == 0 {
+				lines.push(format!("|{}", " --- |".repeat(columns)));
+			}
+		}
+		lines.join("\n")
+	}
+}
+
+/// Content lines that count as text, skipping `#+` keyword and block lines.
+fn prose_lines(content: &str) -> impl Iterator<Item = &str> {
+	content
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty() && !line.starts_with("#+"))
+}
+
+/// Something the parser skipped or could not make sense of.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParseWarning {
+	/// 1-based line number in the source.
+	pub line: usize,
+	pub message: String,
+}
+
+impl std::fmt::Display for ParseWarning {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+/// A line-based parser over the text of an org file.
+pub struct OrgParser {
+	lines: Vec<String>,
+	current_line: usize,
+	warnings: Vec<ParseWarning>,
+	recompute_durations: bool,
+}
+
+impl OrgParser {
+	pub fn new(content: &str) -> Self {
+		Self {
+			lines: content.lines().map(|s| s.to_string()).collect(),
+			current_line: 0,
+			warnings: Vec::new(),
+			recompute_durations: true,
+		}
+	}
+
+	/// Keeps the `=> H:MM` text of CLOCK lines as written. By default a duration that
+	/// disagrees with the timestamps is replaced by the computed one; either way a
+	/// warning is recorded for it.
+	pub fn preserve_clock_durations(mut self, preserve: bool) -> Self {
+		self.recompute_durations = !preserve;
+		self
+	}
+
+	/// Like [`OrgParser::parse`], but also returns warnings about input that was skipped.
+	pub fn parse_with_diagnostics(&mut self) -> (Vec<OrgNote>, Vec<ParseWarning>) {
+		let notes = self.parse();
+		(notes, std::mem::take(&mut self.warnings))
+	}
+
+	fn warn(&mut self, line: usize, message: String) {
+		self.warnings.push(ParseWarning { line, message });
+	}
+
+	/// Parses the remaining input into top-level notes.
+	pub fn parse(&mut self) -> Vec<OrgNote> {
+		let mut notes = Vec::new();
+
+		while self.current_line < self.lines.len() {
+			let line = &self.lines[self.current_line];
+
+			if let Some(end) = self.block_end_line(self.current_line) {
+				self.current_line = end + 1;
+			} else if let Some(level) = self.count_asterisks(line) {
+				if let Some(note) = self.parse_note(level) {
+					notes.push(note);
+				}
+			} else {
+				self.current_line += 1;
+			}
+		}
+
+		notes
+	}
+
+	/// If line `start` opens a `#+BEGIN_X` block that is closed later on, the index
+	/// of its `#+END_X` line. Lines in between are never headings, so a `* item` in
+	/// a source block stays in it.
+	fn block_end_line(&self, start: usize) -> Option<usize> {
+		let kind = Self::block_kind(self.lines[start].trim())?;
+		let end = format!("#+END_{}", kind);
+		self.lines[start + 1..]
+			.iter()
+			.position(|line| line.trim().eq_ignore_ascii_case(&end))
+			.map(|offset| start + 1 + offset)
+	}
+
+	fn count_asterisks(&self, line: &str) -> Option<usize> {
+		let trimmed = line.trim_start();
+		if trimmed.starts_with('*') {
+			let count = trimmed.chars().take_while(|&c| c == '*').count();
+			if count > 0 && trimmed.chars().nth(count) == Some(' ') {
+				return Some(count);
+			}
+		}
+		None
+	}
+
+	/// Parses the heading at the current line and everything below it. Like org, a
+	/// heading belongs to the nearest preceding heading with fewer stars, so after a
+	/// skipped level (`*` then `***`) a later `**` is still a child of the `*`, and
+	/// a sibling of the `***`. Each note keeps its own star count as `level`.
+	fn parse_note(&mut self, level: usize) -> Option<OrgNote> {
+		if self.current_line >= self.lines.len() {
+			return None;
+		}
+
+		let line = &self.lines[self.current_line];
+		let header_content = self.extract_header_content(line, level);
+
+		let (status, title, labels) = self.parse_header_parts(&header_content);
+		let (first_word, rest) = title.split_once(' ').unwrap_or((&title, ""));
+		let priority = parse_priority_cookie(first_word);
+		let title = match priority {
+			Some(_) => rest.to_string(),
+			None => title.clone(),
+		};
+
+		let mut note = OrgNote::new(level, title);
+		note.status = status;
+		note.priority = priority;
+		note.labels = labels;
+		note.source_line = Some(self.current_line + 1);
+
+		self.current_line += 1;
+		let content_start = self.current_line;
+
+		// Collect content until next heading of same or higher level
+		let mut content_lines = Vec::new();
+		let mut child_notes = Vec::new();
+
+		while self.current_line < self.lines.len() {
+			let line = &self.lines[self.current_line];
+
+			if let Some(end) = self.block_end_line(self.current_line) {
+				content_lines.extend_from_slice(&self.lines[self.current_line..=end]);
+				self.current_line = end + 1;
+			} else if let Some(next_level) = self.count_asterisks(line) {
+				if next_level <= level {
+					// Same or higher level heading, stop collecting content
+					break;
+				} else {
+					// Child heading, parse it as a child note
+					if let Some(child_note) = self.parse_note(next_level) {
+						child_notes.push(child_note);
+					}
+				}
+			} else {
+				// Regular content line
+				content_lines.push(line.clone());
+				self.current_line += 1;
+			}
+		}
+
+		self.parse_body(&mut note, &content_lines, content_start);
+		note.children = child_notes;
+
+		Some(note)
+	}
+
+	fn extract_header_content(&self, line: &str, level: usize) -> String {
+		let trimmed = line.trim_start();
+		// Skip the asterisks and the space after them
+		trimmed.chars().skip(level + 1).collect()
+	}
+
+	fn parse_header_parts(&self, header: &str) -> (Option<String>, String, Vec<String>) {
+		let mut words: Vec<&str> = header.split_whitespace().collect();
+
+		// Extract labels (org-mode tag groups at the end, e.g. `:a:b:` or `:a: :b:`),
+		// in order and keeping a repeated tag, so the heading writes back as parsed
+		let mut groups = Vec::new();
+		while let Some(tags) = words.last().and_then(|word| Self::parse_tag_group(word)) {
+			groups.push(tags);
+			words.pop();
+		}
+		let labels: Vec<String> = groups.into_iter().rev().flatten().collect();
+
+		// Extract status (first word if it's uppercase)
+		let mut status = None;
+		let mut title_start = 0;
+
+		if let Some(first_word) = words.first()
+			&& parse_priority_cookie(first_word).is_none()
+			&& first_word
+				.chars()
+				.all(|c| c.is_uppercase() || !c.is_alphabetic())
+			&& !first_word.is_empty()
+		{
+			status = Some(first_word.to_string());
+			title_start = 1;
+		}
+
+		// A header can be empty or just a status keyword, leaving no title words
+		let title = words.get(title_start..).unwrap_or_default().join(" ");
+
+		(status, title, labels)
+	}
+
+	/// Parses a `:tag1:tag2:` group, returning `None` unless the whole token is a valid group.
+	fn parse_tag_group(token: &str) -> Option<Vec<String>> {
+		let inner = token.strip_prefix(':')?.strip_suffix(':')?;
+		if inner.is_empty() {
+			return None;
+		}
+
+		inner
+			.split(':')
+			.map(|tag| is_valid_tag(tag).then(|| tag.to_string()))
+			.collect()
+	}
+
+	/// Splits the lines below a heading into planning, logbook, drawers and content,
+	/// recording their order in `note.layout`. `first_line` is the 0-based index of the
+	/// first of `lines`, used for warnings.
+	fn parse_body(&mut self, note: &mut OrgNote, lines: &[String], first_line: usize) {
+		let mut cleaned_lines = Vec::new();
+		let mut planning = OrgPlanning {
+			scheduled: None,
+			deadline: None,
+			closed: None,
+		};
+		let mut logbook = None;
+		let mut in_logbook = false;
+		let mut logbook_lines = Vec::new();
+		let mut clock_entries = Vec::new();
+		let mut logbook_start = 0;
+		let mut drawers = Vec::new();
+		let mut current_drawer: Option<OrgDrawer> = None;
+		let mut block_end: Option<String> = None;
+		let mut layout = Vec::new();
+		// Bare CLOCK lines before any content are the logbook, unless the note also
+		// has a drawer for it
+		let has_logbook_drawer = lines.iter().any(|line| line.trim() == ":LOGBOOK:");
+		let mut inline_clocks = Vec::new();
+		let mut inline_lines = Vec::new();
+		// Taken from the first planning line, drawer or clock
+		let mut body_indent: Option<String> = None;
+		let indent_of = |line: &str| line[..line.len() - line.trim_start().len()].to_string();
+
+		for (i, line) in lines.iter().enumerate() {
+			let trimmed = line.trim();
+			let line_number = first_line + i + 1;
+
+			// Inside a generic drawer everything is kept verbatim until `:END:`
+			if let Some(drawer) = &mut current_drawer {
+				if trimmed == ":END:" {
+					drawers.extend(current_drawer.take());
+				} else {
+					drawer.lines.push(line.to_string());
+				}
+				continue;
+			}
+
+			// `#+BEGIN_X` ... `#+END_X` blocks are content, so a CLOCK or planning line in an
+			// example is not parsed. The block only counts if it is closed later on.
+			let mut in_block = block_end.is_some();
+			if let Some(end) = &block_end {
+				if trimmed.eq_ignore_ascii_case(end) {
+					block_end = None;
+				}
+			} else if !in_logbook && let Some(kind) = Self::block_kind(trimmed) {
+				let end = format!("#+END_{}", kind);
+				if lines[i + 1..]
+					.iter()
+					.any(|l| l.trim().eq_ignore_ascii_case(&end))
+				{
+					block_end = Some(end);
+					in_block = true;
+				}
+			}
+			if in_block {
+				Self::push_content_line(&mut cleaned_lines, &mut layout, line);
+				continue;
+			}
+
+			// Check for logbook start/end
+			if trimmed == ":LOGBOOK:" {
+				body_indent.get_or_insert_with(|| indent_of(line));
+				in_logbook = true;
+				logbook_start = line_number;
+				if !layout.contains(&OrgBlock::Logbook) {
+					layout.push(OrgBlock::Logbook);
+				}
+				continue;
+			} else if trimmed == ":END:" && in_logbook {
+				in_logbook = false;
+				logbook = Some(OrgLogbook {
+					clock_entries: clock_entries.clone(),
+					raw_content: logbook_lines.clone(),
+					inline: false,
+				});
+				logbook_lines.clear();
+				continue;
+			}
+
+			// Only treat `:NAME:` as a drawer if it is closed later on
+			if !in_logbook
+				&& let Some(name) = Self::drawer_name(trimmed)
+				&& lines[i + 1..].iter().any(|l| l.trim() == ":END:")
+			{
+				body_indent.get_or_insert_with(|| indent_of(line));
+				layout.push(OrgBlock::Drawer(drawers.len()));
+				current_drawer = Some(OrgDrawer {
+					name: name.to_string(),
+					lines: Vec::new(),
+				});
+				continue;
+			}
+
+			if in_logbook {
+				logbook_lines.push(line.to_string());
+				if let Some(clock_entry) = self.parse_checked_clock_line(line, line_number) {
+					clock_entries.push(clock_entry);
+				} else if trimmed.starts_with("CLOCK:") {
+					self.warn(line_number, format!("unparseable CLOCK line: {}", trimmed));
+				}
+				continue;
+			}
+
+			if !has_logbook_drawer
+				&& cleaned_lines.is_empty()
+				&& let Some(clock_entry) = self.parse_checked_clock_line(line, line_number)
+			{
+				if inline_clocks.is_empty() {
+					layout.push(OrgBlock::Logbook);
+				}
+				body_indent.get_or_insert_with(|| indent_of(line));
+				inline_clocks.push(clock_entry);
+				inline_lines.push(line.to_string());
+				continue;
+			}
+
+			// Check for planning keywords, one or more to a line
+			if let Some(entries) = self.parse_planning_line(trimmed) {
+				body_indent.get_or_insert_with(|| indent_of(line));
+				for (i, (block, timestamp)) in entries.into_iter().enumerate() {
+					if i > 0 {
+						layout.push(OrgBlock::SameLine);
+					}
+					match block {
+						OrgBlock::Scheduled => planning.scheduled = Some(timestamp),
+						OrgBlock::Deadline => planning.deadline = Some(timestamp),
+						_ => planning.closed = Some(timestamp),
+					}
+					layout.push(block);
+				}
+				continue;
+			}
+
+			if let Some((keyword, _)) = PLANNING_KEYWORDS
+				.into_iter()
+				.find(|(keyword, _)| trimmed.starts_with(keyword))
+			{
+				self.warn(
+					line_number,
+					format!("unparseable {} timestamp: {}", keyword, trimmed),
+				);
+			}
+
+			Self::push_content_line(&mut cleaned_lines, &mut layout, line);
+		}
+
+		// Close a drawer left open at the end of the note, keeping what was collected
+		if in_logbook {
+			self.warn(logbook_start, "unterminated LOGBOOK drawer".to_string());
+			logbook = Some(OrgLogbook {
+				clock_entries,
+				raw_content: logbook_lines,
+				inline: false,
+			});
+		}
+		if !inline_clocks.is_empty() {
+			logbook = Some(OrgLogbook {
+				clock_entries: inline_clocks,
+				raw_content: inline_lines,
+				inline: true,
+			});
+		}
+
+		note.content = cleaned_lines.join("\n");
+		note.links = extract_links(&note.content);
+		note.tables = extract_tables(&note.content);
+		note.planning = if planning.is_empty() {
+			None
+		} else {
+			Some(planning)
+		};
+		note.logbook = logbook;
+		note.drawers = drawers;
+		note.body_indent = body_indent.unwrap_or_default();
+		note.layout = Some(layout);
+	}
+
+	/// Parses a `CLOCK:` line, warning about a duration that disagrees with its
+	/// timestamps (recomputing it unless durations are preserved) or an invalid entry.
+	fn parse_checked_clock_line(
+		&mut self,
+		line: &str,
+		line_number: usize,
+	) -> Option<OrgClockEntry> {
+		let mut clock_entry = self.parse_clock_line(line)?;
+		if let (Some(written), Some(elapsed)) =
+			(clock_entry.duration.clone(), clock_entry.elapsed_minutes())
+			&& clock_entry.is_valid()
+			&& clock_entry.parse_duration_minutes() != Some(elapsed as u32)
+		{
+			self.warn(
+				line_number,
+				format!(
+					"CLOCK duration {} does not match its timestamps ({})",
+					written,
+					format_clock_minutes(elapsed)
+				),
+			);
+			if self.recompute_durations {
+				clock_entry.recompute_duration();
+			}
+		}
+		if !clock_entry.is_valid() {
+			self.warn(
+				line_number,
+				format!("invalid CLOCK entry, excluded from totals: {}", line.trim()),
+			);
+		}
+		Some(clock_entry)
+	}
+
+	fn push_content_line<'a>(
+		content: &mut Vec<&'a str>,
+		layout: &mut Vec<OrgBlock>,
+		line: &'a str,
+	) {
+		content.push(line);
+		match layout.last_mut() {
+			Some(OrgBlock::Content(count)) => *count += 1,
+			_ => layout.push(OrgBlock::Content(1)),
+		}
+	}
+
+	/// Returns `SRC` for a `#+BEGIN_SRC rust` line, in either case.
+	fn block_kind(line: &str) -> Option<&str> {
+		let prefix = line.get(..8)?;
+		if !prefix.eq_ignore_ascii_case("#+BEGIN_") {
+			return None;
+		}
+		line[8..]
+			.split_whitespace()
+			.next()
+			.filter(|kind| !kind.is_empty())
+	}
+
+	/// Returns `NAME` if `line` is a `:NAME:` drawer opening (other than LOGBOOK and END).
+	fn drawer_name(line: &str) -> Option<&str> {
+		let name = line.strip_prefix(':')?.strip_suffix(':')?;
+		let valid = !name.is_empty()
+			&& name
+				.chars()
+				.all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+		(valid && name != "LOGBOOK" && name != "END").then_some(name)
+	}
+
+	/// Parses a planning line such as `DEADLINE: <...> SCHEDULED: <...>`, returning
+	/// the keywords in the order written. `None` unless the whole line is planning.
+	fn parse_planning_line(&self, line: &str) -> Option<Vec<(OrgBlock, OrgTimestamp)>> {
+		let mut entries = Vec::new();
+		let mut rest = line;
+
+		while !rest.is_empty() {
+			let (keyword, block) = PLANNING_KEYWORDS
+				.into_iter()
+				.find(|(keyword, _)| rest.starts_with(keyword))?;
+			let after_keyword = rest[keyword.len()..].trim_start();
+			let close = match after_keyword.chars().next()? {
+				'<' => '>',
+				'[' => ']',
+				_ => return None,
+			};
+			let end = after_keyword.find(close)? + 1;
+			entries.push((
+				block,
+				self.parse_timestamp_from_text(&after_keyword[..end])?,
+			));
+			rest = after_keyword[end..].trim_start();
+		}
+
+		(!entries.is_empty()).then_some(entries)
+	}
+
+	/// Parses a `CLOCK:` line, returning `None` if `line` is not one.
+	pub fn parse_clock_line(&self, line: &str) -> Option<OrgClockEntry> {
+		let trimmed = line.trim();
+		if !trimmed.starts_with("CLOCK:") {
+			return None;
+		}
+
+		let clock_content = &trimmed[6..].trim();
+
+		// Parse format: [start]--[end] => duration
+		if let Some(arrow_pos) = clock_content.find("=>") {
+			let time_part = &clock_content[..arrow_pos].trim();
+			let duration_part = clock_content[arrow_pos + 2..].trim();
+
+			if let Some(dash_pos) = time_part.find("--") {
+				let start_part = &time_part[..dash_pos].trim();
+				let end_part = &time_part[dash_pos + 2..].trim();
+
+				if let (Some(start), Some(end)) = (
+					self.parse_timestamp_from_text(start_part),
+					self.parse_timestamp_from_text(end_part),
+				) {
+					return Some(OrgClockEntry {
+						start,
+						end: Some(end),
+						duration: Some(duration_part.to_string()),
+						raw: line.to_string(),
+					});
+				}
+			}
+		} else if let Some(dash_pos) = clock_content.find("--") {
+			// Closed range without a duration yet
+			let start_part = &clock_content[..dash_pos].trim();
+			let end_part = &clock_content[dash_pos + 2..].trim();
+
+			if let (Some(start), Some(end)) = (
+				self.parse_timestamp_from_text(start_part),
+				self.parse_timestamp_from_text(end_part),
+			) {
+				return Some(OrgClockEntry {
+					start,
+					end: Some(end),
+					duration: None,
+					raw: line.to_string(),
+				});
+			}
+		} else if let Some(timestamp) = self.parse_timestamp_from_text(clock_content) {
+			// Single timestamp (clock in, no clock out yet)
+			return Some(OrgClockEntry {
+				start: timestamp,
+				end: None,
+				duration: None,
+				raw: line.to_string(),
+			});
+		}
+
+		None
+	}
+
+	/// Parses a timestamp in `[...]` or `<...>` brackets (or bare), e.g. `[2024-01-01 Mon 10:00]`.
+	pub fn parse_timestamp_from_text(&self, text: &str) -> Option<OrgTimestamp> {
+		text.parse().ok()
+	}
+}
+
+impl std::str::FromStr for OrgTimestamp {
+	type Err = String;
+
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		parse_timestamp(text).ok_or_else(|| format!("invalid timestamp '{}'", text))
+	}
+}
+
+/// Reads a date the way org's date prompt does, relative to `now`: `today`,
+/// `tomorrow`, `yesterday`, an offset such as `+3d`, `-1w`, `+2m` or `+1y` (a
+/// bare `+3` counts days), or a weekday name such as `fri` or `Friday`, which
+/// means the next such day, today included.
+pub fn parse_relative_date(input: &str, now: NaiveDateTime) -> Option<NaiveDate> {
+	let today = now.date();
+	let input = input.trim().to_ascii_lowercase();
+	match input.as_str() {
+		"today" => return Some(today),
+		"tomorrow" => return today.succ_opt(),
+		"yesterday" => return today.pred_opt(),
+		_ => {},
+	}
+
+	if let Some(sign) = input.chars().next().filter(|c| matches!(c, '+' | '-')) {
+		let offset = &input[1..];
+		let (count, unit) = match offset.find(|c: char| !c.is_ascii_digit()) {
+			Some(split) => offset.split_at(split),
+			None => (offset, "d"),
+		};
+		let count: u32 = count.parse().ok()?;
+		let (days, months) = match unit {
+			"d" => (count, 0),
+			"w" => (count.checked_mul(7)?, 0),
+			"m" => (0, count),
+			"y" => (0, count.checked_mul(12)?),
+			_ => return None,
+		};
+		return if sign == '+' {
+			today
+				.checked_add_days(Days::new(days.into()))?
+				.checked_add_months(Months::new(months))
+		} else {
+			today
+				.checked_sub_days(Days::new(days.into()))?
+				.checked_sub_months(Months::new(months))
+		};
+	}
+
+	let weekday = match input.as_str() {
+		"mon" | "monday" => Weekday::Mon,
+		"tue" | "tuesday" => Weekday::Tue,
+		"wed" | "wednesday" => Weekday::Wed,
+		"thu" | "thursday" => Weekday::Thu,
+		"fri" | "friday" => Weekday::Fri,
+		"sat" | "saturday" => Weekday::Sat,
+		"sun" | "sunday" => Weekday::Sun,
+		_ => return None,
+	};
+	let ahead = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+	today.checked_add_days(Days::new(ahead.into()))
+}
+
+impl OrgTimestamp {
+	fn sort_key(&self) -> (u32, u32, u32, Option<u32>, Option<u32>) {
+		(self.year, self.month, self.day, self.hour, self.minute)
+	}
+}
+
+/// Timestamps compare by the instant they name, ignoring brackets, day name and
+/// `raw`. A date without a time sorts before any time on that day.
+impl PartialEq for OrgTimestamp {
+	fn eq(&self, other: &Self) -> bool {
+		self.sort_key() == other.sort_key()
+	}
+}
+
+impl Eq for OrgTimestamp {}
+
+impl PartialOrd for OrgTimestamp {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OrgTimestamp {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+		self.sort_key().cmp(&other.sort_key())
+	}
+}
+
+impl std::fmt::Display for OrgTimestamp {
+	/// Writes the canonical active form, e.g. `<2024-01-01 Mon 10:00>`, whatever
+	/// brackets `raw` has. The day name is computed if the timestamp has none.
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let day_name = self.day_name.clone().or_else(|| {
+			self.to_naive_date()
+				.map(|date| date.format("%a").to_string())
+		});
+		write!(f, "<{}", self.to_date_string())?;
+		if let Some(day_name) = day_name {
+			write!(f, " {}", day_name)?;
+		}
+		if let (Some(hour), Some(minute)) = (self.hour, self.minute) {
+			write!(f, " {:02}:{:02}", hour, minute)?;
+		}
+		write!(f, ">")
+	}
+}
+
+fn parse_timestamp(text: &str) -> Option<OrgTimestamp> {
+	// Handle both [timestamp] and <timestamp> formats
+	let content = if (text.starts_with('[') && text.ends_with(']'))
+		|| (text.starts_with('<') && text.ends_with('>'))
+	{
+		&text[1..text.len() - 1]
+	} else {
+		text
+	};
+
+	// Parse format like: "2024-01-01 Mon 10:00" or "2023-03-29 Ср"
+	let parts: Vec<&str> = content.split_whitespace().collect();
+	if parts.len() < 2 {
+		return None;
+	}
+
+	// Parse date part (YYYY-MM-DD)
+	let date_parts: Vec<&str> = parts[0].split('-').collect();
+	if date_parts.len() != 3 {
+		return None;
+	}
+
+	let year = date_parts[0].parse::<u32>().ok()?;
+	let month = date_parts[1].parse::<u32>().ok()?;
+	let day = date_parts[2].parse::<u32>().ok()?;
+
+	let day_name = if parts.len() > 1 {
+		Some(parts[1].to_string())
+	} else {
+		None
+	};
+
+	// Parse time part if present (HH:MM)
+	let (hour, minute) = if parts.len() > 2 {
+		let time_parts: Vec<&str> = parts[2].split(':').collect();
+		if time_parts.len() == 2 {
+			let h = time_parts[0].parse::<u32>().ok();
+			let m = time_parts[1].parse::<u32>().ok();
+			(h, m)
+		} else {
+			(None, None)
+		}
+	} else {
+		(None, None)
+	};
+
+	Some(OrgTimestamp {
+		year,
+		month,
+		day,
+		hour,
+		minute,
+		day_name,
+		raw: text.to_string(),
+	})
+}
+
+/// How a [`Repeater`] moves a timestamp when its task is done.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatStyle {
+	/// `+1w`: one interval on from the timestamp, even if that is still past.
+	Cumulate,
+	/// `++1w`: as many intervals on from the timestamp as it takes to pass now.
+	CatchUp,
+	/// `.+1w`: one interval on from when the task is done.
+	Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatUnit {
+	Hour,
+	Day,
+	Week,
+	Month,
+	Year,
+}
+
+/// A repeater cookie on a timestamp, such as `+1w`, `++2d` or `.+1m`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Repeater {
+	pub style: RepeatStyle,
+	pub count: u32,
+	pub unit: RepeatUnit,
+}
+
+impl std::str::FromStr for Repeater {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid repeater '{}'", s);
+		let (style, rest) = if let Some(rest) = s.strip_prefix("++") {
+			(RepeatStyle::CatchUp, rest)
+		} else if let Some(rest) = s.strip_prefix(".+") {
+			(RepeatStyle::Restart, rest)
+		} else if let Some(rest) = s.strip_prefix('+') {
+			(RepeatStyle::Cumulate, rest)
+		} else {
+			return Err(invalid());
+		};
+		let unit = match rest.chars().last() {
+			Some('h') => RepeatUnit::Hour,
+			Some('d') => RepeatUnit::Day,
+			Some('w') => RepeatUnit::Week,
+			Some('m') => RepeatUnit::Month,
+			Some('y') => RepeatUnit::Year,
+			_ => return Err(invalid()),
+		};
+		let count = rest[..rest.len() - 1]
+			.parse()
+			.ok()
+			.filter(|&count| count > 0)
+			.ok_or_else(invalid)?;
+		Ok(Repeater { style, count, unit })
+	}
+}
+
+impl Repeater {
+	fn shift(&self, at: NaiveDateTime) -> Option<NaiveDateTime> {
+		match self.unit {
+			RepeatUnit::Hour => at.checked_add_signed(Duration::hours(self.count.into())),
+			RepeatUnit::Day => at.checked_add_signed(Duration::days(self.count.into())),
+			RepeatUnit::Week => at.checked_add_signed(Duration::weeks(self.count.into())),
+			RepeatUnit::Month => at.checked_add_months(Months::new(self.count)),
+			RepeatUnit::Year => at.checked_add_months(Months::new(self.count.checked_mul(12)?)),
+		}
+	}
+
+	/// The date a timestamp at `start` moves to when its task is done at `now`,
+	/// following the [`RepeatStyle`]. Without `has_time` only dates are compared,
+	/// so `++1d` on a date-only timestamp lands on tomorrow at the earliest.
+	pub fn next_after(
+		&self,
+		start: NaiveDateTime,
+		now: NaiveDateTime,
+		has_time: bool,
+	) -> Option<NaiveDateTime> {
+		match self.style {
+			RepeatStyle::Cumulate => self.shift(start),
+			RepeatStyle::CatchUp => {
+				let passed = |at: NaiveDateTime| {
+					if has_time {
+						at > now
+					} else {
+						at.date() > now.date()
+					}
+				};
+				let mut next = self.shift(start)?;
+				while !passed(next) {
+					next = self.shift(next)?;
+				}
+				Some(next)
+			},
+			RepeatStyle::Restart if self.unit == RepeatUnit::Hour => self.shift(now),
+			RepeatStyle::Restart => self.shift(now.date().and_time(start.time())),
+		}
+	}
+}
+
+impl OrgTimestamp {
+	/// Moves a timestamp with a repeater forward the way org does when the task is
+	/// done at `now`, following its [`RepeatStyle`]. `raw` keeps its brackets and
+	/// cookies, with the new date (and day name and time) written in. Returns
+	/// false without a repeater, or for an hourly one on a timestamp without a time.
+	pub fn advance_repeater(&mut self, now: NaiveDateTime) -> bool {
+		let Some(repeater) = self.repeater() else {
+			return false;
+		};
+		let has_time = self.hour.is_some() && self.minute.is_some();
+		if repeater.unit == RepeatUnit::Hour && !has_time {
+			return false;
+		}
+		let Some(next) = self
+			.to_naive_datetime()
+			.and_then(|start| repeater.next_after(start, now, has_time))
+		else {
+			return false;
+		};
+
+		let mut parts: Vec<&str> = self
+			.raw
+			.trim_matches(['<', '>', '[', ']'])
+			.split_whitespace()
+			.collect();
+		let date = next.format("%Y-%m-%d").to_string();
+		let day_name = next.format("%a").to_string();
+		let time = next.format("%H:%M").to_string();
+		parts[0] = &date;
+		self.year = next.year() as u32;
+		self.month = next.month();
+		self.day = next.day();
+		if self.day_name.is_some() && parts.len() > 1 {
+			parts[1] = &day_name;
+			self.day_name = Some(day_name.clone());
+		}
+		if has_time && parts.len() > 2 {
+			self.hour = Some(next.hour());
+			self.minute = Some(next.minute());
+			parts[2] = &time;
+		}
+		let open = &self.raw[..self.raw.len() - self.raw.trim_start_matches(['<', '[']).len()];
+		let close = &self.raw[self.raw.trim_end_matches(['>', ']']).len()..];
+		self.raw = format!("{}{}{}", open, parts.join(" "), close);
+		true
+	}
+
+	/// The repeater cookie after the date, if there is one.
+	pub fn repeater(&self) -> Option<Repeater> {
+		self.raw
+			.trim_matches(['<', '>', '[', ']'])
+			.split_whitespace()
+			.skip(1)
+			.find_map(|part| part.parse().ok())
+	}
+
+	pub fn to_date_string(&self) -> String {
+		format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+	}
+
+	/// A timestamp for `at`, to the minute, with `raw` written by [`OrgTimestamp::to_raw`].
+	pub fn from_datetime(at: NaiveDateTime, kind: TimestampKind) -> Self {
+		let mut timestamp = Self {
+			year: at.year() as u32,
+			month: at.month(),
+			day: at.day(),
+			hour: Some(at.hour()),
+			minute: Some(at.minute()),
+			day_name: Some(at.format("%a").to_string()),
+			raw: String::new(),
+		};
+		timestamp.raw = timestamp.to_raw(kind);
+		timestamp
+	}
+
+	/// A timestamp for the whole of `date`, with `raw` written by [`OrgTimestamp::to_raw`].
+	pub fn from_date(date: NaiveDate, kind: TimestampKind) -> Self {
+		let mut timestamp = Self {
+			year: date.year() as u32,
+			month: date.month(),
+			day: date.day(),
+			hour: None,
+			minute: None,
+			day_name: Some(date.format("%a").to_string()),
+			raw: String::new(),
+		};
+		timestamp.raw = timestamp.to_raw(kind);
+		timestamp
+	}
+
+	/// The timestamp written from its fields, e.g. `<2024-01-20 Sat 09:00>` or
+	/// `[2024-01-20 Sat]`. The day name always comes from the date, in English,
+	/// whatever `day_name` says. Repeaters and other cookies are not fields, so
+	/// they are only kept in `raw`.
+	pub fn to_raw(&self, kind: TimestampKind) -> String {
+		let (open, close) = match kind {
+			TimestampKind::Active => ('<', '>'),
+			TimestampKind::Inactive => ('[', ']'),
+		};
+		let mut inner = self.to_date_string();
+		if let Some(date) = self.to_naive_date() {
+			inner.push_str(&date.format(" %a").to_string());
+		}
+		if let (Some(hour), Some(minute)) = (self.hour, self.minute) {
+			inner.push_str(&format!(" {:02}:{:02}", hour, minute));
+		}
+		format!("{}{}{}", open, inner, close)
+	}
+
+	/// `raw` with `<...>` active brackets swapped for `[...]`, which org uses for clocks.
+	pub fn to_inactive_raw(&self) -> String {
+		match self
+			.raw
+			.strip_prefix('<')
+			.and_then(|raw| raw.strip_suffix('>'))
+		{
+			Some(inner) => format!("[{}]", inner),
+			None => self.raw.clone(),
+		}
+	}
+
+	pub fn to_naive_date(&self) -> Option<NaiveDate> {
+		NaiveDate::from_ymd_opt(self.year as i32, self.month, self.day)
+	}
+
+	pub fn to_naive_datetime(&self) -> Option<NaiveDateTime> {
+		self.to_naive_date()?
+			.and_hms_opt(self.hour.unwrap_or(0), self.minute.unwrap_or(0), 0)
+	}
+
+	pub fn to_datetime_string(&self) -> String {
+		if let (Some(hour), Some(minute)) = (self.hour, self.minute) {
+			format!("{} {:02}:{:02}", self.to_date_string(), hour, minute)
+		} else {
+			self.to_date_string()
+		}
+	}
+}
+
+impl OrgPlanning {
+	pub fn is_empty(&self) -> bool {
+		self.scheduled.is_none() && self.deadline.is_none() && self.closed.is_none()
+	}
+}
+
+impl OrgClockEntry {
+	/// Minutes between start and end, computed from the timestamps rather than the duration text.
+	pub fn elapsed_minutes(&self) -> Option<i64> {
+		let start = self.start.to_naive_datetime()?;
+		let end = self.end.as_ref()?.to_naive_datetime()?;
+		Some((end - start).num_minutes())
+	}
+
+	/// Whether the timestamps are real dates and a closed entry does not end before it starts.
+	/// Running entries only need a valid start.
+	pub fn is_valid(&self) -> bool {
+		let Some(start) = self.start.to_naive_datetime() else {
+			return false;
+		};
+		match &self.end {
+			Some(end) => end.to_naive_datetime().is_some_and(|end| end >= start),
+			None => true,
+		}
+	}
+
+	/// Rewrites `duration` and `raw` from the timestamps of a closed entry,
+	/// writing both timestamps with `[...]` brackets.
+	pub fn recompute_duration(&mut self) {
+		let (Some(minutes), Some(end)) = (self.elapsed_minutes(), &self.end) else {
+			return;
+		};
+		let duration = format_clock_minutes(minutes);
+		self.raw = format!(
+			"CLOCK: {}--{} =>  {}",
+			self.start.to_inactive_raw(),
+			end.to_inactive_raw(),
+			duration
+		);
+		self.duration = Some(duration);
+	}
+
+	/// Ends a running entry at `now`, writing its duration and CLOCK line. A clock
+	/// that started after `now` gets a zero duration. Returns false, changing
+	/// nothing, if the entry is already closed.
+	pub fn clock_out(&mut self, now: NaiveDateTime) -> bool {
+		if self.end.is_some() {
+			return false;
+		}
+		let end = OrgTimestamp::from_datetime(now, TimestampKind::Inactive);
+		self.raw = format!("CLOCK: {}--{}", self.start.to_inactive_raw(), end.raw);
+		self.end = Some(end);
+		let duration = format_clock_minutes(self.elapsed_minutes().unwrap_or(0).max(0));
+		self.raw = format!("{} =>  {}", self.raw, duration);
+		self.duration = Some(duration);
+		true
+	}
+
+	/// The written duration in whole minutes; see [`parse_duration`] for the forms read.
+	pub fn parse_duration_minutes(&self) -> Option<u32> {
+		self.duration.as_deref().and_then(parse_duration)
+	}
+
+	/// Minutes of a closed, [valid](Self::is_valid) entry on each day it covers,
+	/// in order. An entry within one day gives its written duration; one that
+	/// crosses midnight is split there using its timestamps, so `23:00` to
+	/// `01:00` is 60 minutes on each day. The entry itself is not changed.
+	pub fn minutes_by_day(&self) -> Vec<(NaiveDate, u32)> {
+		if !self.is_valid() {
+			return Vec::new();
+		}
+		let (Some(mut start), Some(end)) = (
+			self.start.to_naive_datetime(),
+			self.end.as_ref().and_then(OrgTimestamp::to_naive_datetime),
+		) else {
+			return Vec::new();
+		};
+		if start.date() == end.date() {
+			return self
+				.parse_duration_minutes()
+				.map(|minutes| vec![(start.date(), minutes)])
+				.unwrap_or_default();
+		}
+		let mut days = Vec::new();
+		while start < end {
+			let midnight = (start.date() + Days::new(1)).and_time(NaiveTime::MIN);
+			let piece_end = midnight.min(end);
+			days.push((start.date(), (piece_end - start).num_minutes() as u32));
+			start = piece_end;
+		}
+		days
+	}
+
+	pub fn format_duration(&self) -> String {
+		if let Some(duration) = &self.duration {
+			format!(
+				"{} ({})",
+				duration,
+				if let Some(mins) = self.parse_duration_minutes() {
+					format!("{} minutes", mins)
+				} else {
+					"duration".to_string()
+				}
+			)
+		} else {
+			"running".to_string()
+		}
+	}
+}
+
+/// Formats minutes the way CLOCK lines write durations, e.g. `1:05`.
+fn format_clock_minutes(minutes: i64) -> String {
+	format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// The CLOCK line for `entry` with its duration rounded, or `None` if that
+/// leaves it unchanged or the entry is running or not valid.
+fn rounded_clock_line(entry: &OrgClockEntry, rounding: &Rounding) -> Option<String> {
+	let end = entry.end.as_ref().filter(|_| entry.is_valid())?;
+	let minutes = entry.parse_duration_minutes()?;
+	let rounded = rounding.apply(minutes);
+	(rounded != minutes).then(|| {
+		format!(
+			"CLOCK: {}--{} =>  {}",
+			entry.start.to_inactive_raw(),
+			end.to_inactive_raw(),
+			format_clock_minutes(rounded as i64)
+		)
+	})
+}
+
+/// An inclusive range of dates; a missing bound leaves that side open.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DateRange {
+	pub since: Option<NaiveDate>,
+	pub until: Option<NaiveDate>,
+}
+
+/// Minutes in a clock duration such as `2:30`, `48:00`, `1:05:00` (seconds are
+/// dropped) or, with org's day format, `1d 3:00` or `2d`. Hours, minutes and
+/// seconds may have any number of digits.
+fn parse_duration(text: &str) -> Option<u32> {
+	let text = text.trim();
+	let (days, time) = match text.split_once('d') {
+		Some((days, rest)) => (days.parse::<u32>().ok()?, rest.trim_start()),
+		None => (0, text),
+	};
+	// `2d` has no time part, but a bare duration must have one
+	let time_minutes = if time.is_empty() && time.len() < text.len() {
+		0
+	} else {
+		let mut parts = time.split(':');
+		let hours = parts.next()?.parse::<u32>().ok()?;
+		let minutes = parts.next()?.parse::<u32>().ok()?;
+		if let Some(seconds) = parts.next() {
+			seconds.parse::<u32>().ok()?;
+		}
+		if parts.next().is_some() {
+			return None;
+		}
+		hours.checked_mul(60)?.checked_add(minutes)?
+	};
+	days.checked_mul(24 * 60)?.checked_add(time_minutes)
+}
+
+impl DateRange {
+	pub fn contains(&self, date: NaiveDate) -> bool {
+		self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date <= until)
+	}
+}
+
+/// How reports write an amount of time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TimeFormat {
+	/// `50h 0m`
+	#[default]
+	Hours,
+	/// `2d 2h 0m`, or `5h 30m` below a day
+	Days,
+	/// `50:00`, as CLOCK lines write durations
+	Clock,
+}
+
+impl std::str::FromStr for TimeFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"hours" => Ok(TimeFormat::Hours),
+			"days" => Ok(TimeFormat::Days),
+			"clock" => Ok(TimeFormat::Clock),
+			_ => Err(format!("unknown time format '{}'", s)),
+		}
+	}
+}
+
+impl TimeFormat {
+	pub fn format(self, minutes: u32) -> String {
+		let (hours, minutes) = (minutes / 60, minutes % 60);
+		match self {
+			TimeFormat::Days if hours >= 24 => {
+				format!("{}d {}h {}m", hours / 24, hours % 24, minutes)
+			},
+			TimeFormat::Hours | TimeFormat::Days => format!("{}h {}m", hours, minutes),
+			TimeFormat::Clock => format!("{}:{:02}", hours, minutes),
+		}
+	}
+}
+
+/// Which way [`Rounding`] moves a duration that is not a whole number of steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundMode {
+	Nearest,
+	Up,
+	Down,
+}
+
+impl std::str::FromStr for RoundMode {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"nearest" => Ok(RoundMode::Nearest),
+			"up" => Ok(RoundMode::Up),
+			"down" => Ok(RoundMode::Down),
+			_ => Err(format!("unknown rounding mode '{}'", s)),
+		}
+	}
+}
+
+/// Rounds clock durations to a multiple of `step` minutes. The default, a
+/// one-minute step, leaves durations unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rounding {
+	pub step: u32,
+	pub mode: RoundMode,
+}
+
+impl Default for Rounding {
+	fn default() -> Self {
+		Self {
+			step: 1,
+			mode: RoundMode::Nearest,
+		}
+	}
+}
+
+impl Rounding {
+	pub fn apply(&self, minutes: u32) -> u32 {
+		let step = self.step.max(1);
+		let down = minutes - minutes % step;
+		match self.mode {
+			RoundMode::Down => down,
+			_ if down == minutes => down,
+			RoundMode::Up => down + step,
+			RoundMode::Nearest if minutes - down >= step.div_ceil(2) => down + step,
+			RoundMode::Nearest => down,
+		}
+	}
+}
+
+impl OrgLogbook {
+	/// Sum of the clock durations, skipping entries that are not [valid](OrgClockEntry::is_valid).
+	pub fn total_minutes(&self) -> u32 {
+		self.minutes_in(&DateRange::default(), &Rounding::default())
+	}
+
+	/// Like [`total_minutes`](Self::total_minutes), but only counting entries
+	/// whose start date falls in `range`, each rounded by `rounding` first.
+	pub fn minutes_in(&self, range: &DateRange, rounding: &Rounding) -> u32 {
+		self.clock_entries
+			.iter()
+			.filter(|entry| entry.is_valid())
+			.filter(|entry| {
+				entry
+					.start
+					.to_naive_date()
+					.is_some_and(|date| range.contains(date))
+			})
+			.filter_map(|entry| entry.parse_duration_minutes())
+			.map(|minutes| rounding.apply(minutes))
+			.sum()
+	}
+
+	/// The [valid](OrgClockEntry::is_valid) entries that started on `date`,
+	/// running ones included. An entry that runs past midnight belongs wholly
+	/// to the day it started on.
+	pub fn entries_on(&self, date: NaiveDate) -> Vec<&OrgClockEntry> {
+		self.clock_entries
+			.iter()
+			.filter(|entry| entry.is_valid() && entry.start.to_naive_date() == Some(date))
+			.collect()
+	}
+
+	/// Minutes clocked by the [entries that started on `date`](Self::entries_on),
+	/// the same as [`minutes_in`](Self::minutes_in) for a one-day range.
+	pub fn minutes_on(&self, date: NaiveDate) -> u32 {
+		let day = DateRange {
+			since: Some(date),
+			until: Some(date),
+		};
+		self.minutes_in(&day, &Rounding::default())
+	}
+
+	pub fn format_total_time(&self) -> String {
+		self.format_total_time_as(TimeFormat::Hours)
+	}
+
+	/// The total time written in `format`, e.g. `2d 2h 0m` for [`TimeFormat::Days`].
+	pub fn format_total_time_as(&self, format: TimeFormat) -> String {
+		format.format(self.total_minutes())
+	}
+}
+
+/// Parses org-mode text into its top-level notes, with nested headings as children.
+/// Text before the first heading is not part of any note; see [`preamble`].
+pub fn parse(content: &str) -> Vec<OrgNote> {
+	OrgParser::new(content).parse()
+}
+
+/// The text before the first heading, such as `#+TITLE:` lines, exactly as
+/// written. [`serialize_document`] puts it back in front of the notes. Empty if
+/// there is nothing but whitespace before the first heading.
+pub fn preamble(content: &str) -> &str {
+	let parser = OrgParser::new(content);
+	let mut line = 0;
+	while line < parser.lines.len() {
+		if let Some(end) = parser.block_end_line(line) {
+			line = end + 1;
+		} else if parser.count_asterisks(&parser.lines[line]).is_some() {
+			break;
+		} else {
+			line += 1;
+		}
+	}
+	let end = content.split_inclusive('\n').take(line).map(str::len).sum();
+	let preamble = &content[..end];
+	if preamble.trim().is_empty() {
+		""
+	} else {
+		preamble
+	}
+}
+
+/// Formatting choices for [`serialize_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+	/// Written before every `CLOCK:` line in place of its original indentation,
+	/// so parsed and newly clocked entries line up. By default each note's
+	/// [`body_indent`](OrgNote::body_indent) is used.
+	pub clock_indent: Option<String>,
+	/// Applied to the durations of closed `CLOCK:` lines. By default they are
+	/// written as parsed.
+	pub round_durations: Rounding,
+	/// Re-align tables in the content with [`align_table`]. Off by default, so
+	/// tables are written as parsed.
+	pub align_tables: bool,
+	/// Write headings with an odd number of stars by their depth in the tree,
+	/// `*`, `***`, `*****` ..., like org's `org-odd-levels-only`. Off by
+	/// default, so each heading keeps the stars of its level.
+	pub odd_levels: bool,
+	/// Indent planning lines, drawers and content under the heading text with
+	/// `Some(true)`, or write them flush left with `Some(false)`; indentation
+	/// past the note's [`body_indent`](OrgNote::body_indent) is kept. By
+	/// default the body keeps its own indentation.
+	pub indent_body: Option<bool>,
+	/// Follow every note with exactly one blank line with `Some(true)`, or with
+	/// none with `Some(false)`, dropping the blank lines that end its content.
+	/// By default parsed notes keep their blank lines and new ones get one.
+	pub blank_line_after_notes: Option<bool>,
+}
+
+/// Serializes notes back into org-mode text.
+pub fn serialize(notes: &[OrgNote]) -> String {
+	serialize_with_options(notes, &SerializeOptions::default())
+}
+
+/// Serializes a whole file: its [`preamble`] followed by the notes, which
+/// start on a line of their own.
+pub fn serialize_document(preamble: &str, notes: &[OrgNote], options: &SerializeOptions) -> String {
+	let mut output = preamble.to_string();
+	if !notes.is_empty() && !output.is_empty() && !output.ends_with('\n') {
+		output.push('\n');
+	}
+	output + &serialize_with_options(notes, options)
+}
+
+/// Serializes notes back into org-mode text, formatted according to `options`.
+pub fn serialize_with_options(notes: &[OrgNote], options: &SerializeOptions) -> String {
+	let mut output = String::new();
+
+	for note in notes {
+		serialize_note(&mut output, note, 1, options);
+	}
+
+	output
+}
+
+fn serialize_note(output: &mut String, note: &OrgNote, depth: usize, options: &SerializeOptions) {
+	// Write heading
+	let stars = if options.odd_levels {
+		2 * depth - 1
+	} else {
+		note.level
+	};
+	let mut heading = "*".repeat(stars);
+	let labels = if note.labels.is_empty() {
+		String::new()
+	} else {
+		format!(":{}:", note.labels.join(":"))
+	};
+	for part in [note.heading_text(), labels] {
+		if !part.is_empty() {
+			heading.push(' ');
+			heading.push_str(&part);
+		}
+	}
+	// A bare heading still needs the space after its stars to be one
+	if heading.len() == stars {
+		heading.push(' ');
+	}
+	output.push_str(&heading);
+	output.push('\n');
+
+	let planning = note.planning.clone().unwrap_or_default();
+	// Keywords on the planning line being written, joined once it ends
+	let mut planning_line = Vec::new();
+
+	// Content is only written when there is something to write, or the source had lines there
+	let has_content = !note.content.trim().is_empty()
+		|| note
+			.layout
+			.iter()
+			.flatten()
+			.any(|block| matches!(block, OrgBlock::Content(_)));
+	let content = if options.align_tables {
+		align_tables_in(&note.content)
+	} else {
+		note.content.clone()
+	};
+	let mut content_lines: Vec<&str> = content.split('\n').filter(|_| has_content).collect();
+	if options.blank_line_after_notes.is_some() {
+		while content_lines
+			.last()
+			.is_some_and(|line| line.trim().is_empty())
+		{
+			content_lines.pop();
+		}
+	}
+	let body_indent = match options.indent_body {
+		Some(true) => " ".repeat(stars + 1),
+		Some(false) => String::new(),
+		None => note.body_indent.clone(),
+	};
+	// Body lines with the note's own indentation swapped for `body_indent`
+	let reindent = |line: &str| match options.indent_body {
+		Some(_) if !line.trim().is_empty() => {
+			let rest = line
+				.strip_prefix(note.body_indent.as_str())
+				.unwrap_or(line.trim_start());
+			format!("{}{}", body_indent, rest)
+		},
+		_ => line.to_string(),
+	};
+	let mut content_lines = content_lines.into_iter().map(reindent);
+
+	let layout = serialization_layout(note);
+	for (i, &block) in layout.iter().enumerate() {
+		let timestamp = match block {
+			OrgBlock::Scheduled => Some(("SCHEDULED:", &planning.scheduled)),
+			OrgBlock::Deadline => Some(("DEADLINE:", &planning.deadline)),
+			OrgBlock::Closed => Some(("CLOSED:", &planning.closed)),
+			_ => None,
+		};
+		if let Some((keyword, Some(timestamp))) = timestamp {
+			planning_line.push(format!("{} {}", keyword, timestamp.raw));
+		}
+		let line_continues =
+			block == OrgBlock::SameLine || layout.get(i + 1) == Some(&OrgBlock::SameLine);
+		if !planning_line.is_empty() && !line_continues {
+			output.push_str(&format!("{}{}\n", body_indent, planning_line.join(" ")));
+			planning_line.clear();
+		}
+
+		match block {
+			OrgBlock::Scheduled | OrgBlock::Deadline | OrgBlock::Closed | OrgBlock::SameLine => {},
+			OrgBlock::Drawer(i) => {
+				if let Some(drawer) = note.drawers.get(i) {
+					output.push_str(&format!("{}:{}:\n", body_indent, drawer.name));
+					for line in &drawer.lines {
+						output.push_str(&format!("{}\n", reindent(line)));
+					}
+					output.push_str(&format!("{}:END:\n", body_indent));
+				}
+			},
+			OrgBlock::Logbook => {
+				if let Some(logbook) = &note.logbook
+					&& !logbook.clock_entries.is_empty()
+				{
+					if !logbook.inline {
+						output.push_str(&format!("{}:LOGBOOK:\n", body_indent));
+					}
+					let clock_indent = options.clock_indent.as_ref().unwrap_or(&body_indent);
+					for entry in &logbook.clock_entries {
+						let rounded = rounded_clock_line(entry, &options.round_durations);
+						output.push_str(&format!(
+							"{}{}\n",
+							clock_indent,
+							rounded.as_deref().unwrap_or(entry.raw.trim_start())
+						));
+					}
+					if !logbook.inline {
+						output.push_str(&format!("{}:END:\n", body_indent));
+					}
+				}
+			},
+			OrgBlock::Content(count) => {
+				for line in content_lines.by_ref().take(count) {
+					output.push_str(&format!("{}\n", line));
+				}
+			},
+		}
+	}
+
+	// Content lines the layout does not account for, e.g. lines added after parsing
+	for line in content_lines {
+		output.push_str(&format!("{}\n", line));
+	}
+
+	// Parsed notes carry their own blank lines in the content
+	if options
+		.blank_line_after_notes
+		.unwrap_or(note.layout.is_none())
+	{
+		output.push('\n');
+	}
+
+	// Write children
+	for child in &note.children {
+		serialize_note(output, child, depth + 1, options);
+	}
+}
+
+/// `content` with each table replaced by its aligned form, keeping the table's
+/// indentation. Every table line maps to one line, so the line count stays.
+fn align_tables_in(content: &str) -> String {
+	let mut lines = Vec::new();
+	let mut table_lines = Vec::new();
+	let content_lines: Vec<&str> = content.split('\n').collect();
+	for (&line, is_table) in content_lines.iter().zip(table_line_flags(&content_lines)) {
+		if is_table {
+			table_lines.push(line);
+			continue;
+		}
+		push_aligned_table(&mut lines, &mut table_lines);
+		lines.push(line.to_string());
+	}
+	push_aligned_table(&mut lines, &mut table_lines);
+	lines.join("\n")
+}
+
+fn push_aligned_table(lines: &mut Vec<String>, table_lines: &mut Vec<&str>) {
+	let Some(first) = table_lines.first() else {
+		return;
+	};
+	let indent = &first[..first.len() - first.trim_start().len()];
+	for mut table in extract_tables(&table_lines.join("\n")) {
+		align_table(&mut table);
+		lines.extend(
+			table
+				.to_org()
+				.lines()
+				.map(|line| format!("{}{}", indent, line)),
+		);
+	}
+	table_lines.clear();
+}
+
+/// An indented outline of `notes` for reading in a terminal, one heading per
+/// line with its status, priority and tags, and its planning dates in brackets.
+/// Children hang off their parent with box-drawing lines.
+pub fn render_tree(notes: &[OrgNote]) -> String {
+	let mut output = String::new();
+	for note in notes {
+		push_tree_line(&mut output, note, "", "");
+	}
+	output
+}
+
+/// Writes `note` after `lead`, then its children with `indent` in front.
+fn push_tree_line(output: &mut String, note: &OrgNote, lead: &str, indent: &str) {
+	let mut line = format!("{}{}", lead, note.heading_text());
+	if !note.labels.is_empty() {
+		line.push_str(&format!(" :{}:", note.labels.join(":")));
+	}
+	if let Some(planning) = &note.planning {
+		let dates: Vec<String> = [
+			("scheduled", &planning.scheduled),
+			("deadline", &planning.deadline),
+			("closed", &planning.closed),
+		]
+		.into_iter()
+		.filter_map(|(name, ts)| {
+			ts.as_ref()
+				.map(|ts| format!("{} {}", name, ts.to_datetime_string()))
+		})
+		.collect();
+		if !dates.is_empty() {
+			line.push_str(&format!(" ({})", dates.join(", ")));
+		}
+	}
+	output.push_str(line.trim_end());
+	output.push('\n');
+
+	for (i, child) in note.children.iter().enumerate() {
+		let (branch, next) = if i + 1 == note.children.len() {
+			("└── ", "    ")
+		} else {
+			("├── ", "│   ")
+		};
+		push_tree_line(
+			output,
+			child,
+			&format!("{}{}", indent, branch),
+			&format!("{}{}", indent, next),
+		);
+	}
+}
+
+/// Writes `notes` as Markdown: headings become `#` headings (six deep at most)
+/// with their tags in backticks, content is kept with its tables converted to
+/// Markdown tables, and planning, drawers and the logbook are left out.
+pub fn to_markdown(notes: &[OrgNote]) -> String {
+	let mut output = String::new();
+	for (depth, note) in iter_notes(notes) {
+		let mut heading = format!("{} {}", "#".repeat((depth + 1).min(6)), note.heading_text());
+		if !note.labels.is_empty() {
+			heading.push_str(&format!(" `:{}:`", note.labels.join(":")));
+		}
+		output.push_str(heading.trim_end());
+		output.push_str("\n\n");
+
+		let mut table_lines = Vec::new();
+		let content_lines: Vec<&str> = note.content.lines().collect();
+		for (&line, is_table) in content_lines.iter().zip(table_line_flags(&content_lines)) {
+			if is_table {
+				table_lines.push(line);
+				continue;
+			}
+			push_markdown_table(&mut output, &mut table_lines);
+			output.push_str(line);
+			output.push('\n');
+		}
+		push_markdown_table(&mut output, &mut table_lines);
+		if !note.content.trim().is_empty() {
+			output.push('\n');
+		}
+	}
+	output
+}
+
+fn push_markdown_table(output: &mut String, table_lines: &mut Vec<&str>) {
+	if table_lines.is_empty() {
+		return;
+	}
+	for table in extract_tables(&table_lines.join("\n")) {
+		output.push_str(&table.to_markdown());
+		output.push('\n');
+	}
+	table_lines.clear();
+}
+
+/// The order to write a note's blocks in: its source layout, with blocks added since
+/// parsing placed first in the fixed planning/drawers/logbook/content order.
+fn serialization_layout(note: &OrgNote) -> Vec<OrgBlock> {
+	let planning = note.planning.clone().unwrap_or_default();
+	let present = [
+		(OrgBlock::Scheduled, planning.scheduled.is_some()),
+		(OrgBlock::Deadline, planning.deadline.is_some()),
+		(OrgBlock::Closed, planning.closed.is_some()),
+	]
+	.into_iter()
+	.chain((0..note.drawers.len()).map(|i| (OrgBlock::Drawer(i), true)))
+	.chain([(OrgBlock::Logbook, note.logbook.is_some())]);
+
+	let parsed = note.layout.as_deref().unwrap_or_default();
+	let mut layout: Vec<OrgBlock> = present
+		.filter(|(block, present)| *present && !parsed.contains(block))
+		.map(|(block, _)| block)
+		.collect();
+
+	match &note.layout {
+		Some(parsed) => layout.extend(parsed),
+		None => layout.push(OrgBlock::Content(usize::MAX)),
+	}
+	layout
+}
+
+/// What [`sort_notes`] orders notes by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+	/// The earlier of the scheduled and deadline dates, as in [`agenda_sort_key`].
+	Date,
+	Deadline,
+	Scheduled,
+	Title,
+	Priority,
+}
+
+impl std::str::FromStr for SortKey {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"date" => Ok(SortKey::Date),
+			"deadline" => Ok(SortKey::Deadline),
+			"scheduled" => Ok(SortKey::Scheduled),
+			"title" => Ok(SortKey::Title),
+			"priority" => Ok(SortKey::Priority),
+			_ => Err(format!("unknown sort key '{}'", s)),
+		}
+	}
+}
+
+/// Position of a note's priority in sort order: `A` first, then any priority
+/// that is not an uppercase letter, notes without one last.
+fn priority_rank(note: &OrgNote) -> u8 {
+	match note.priority {
+		Some(letter) if letter.is_ascii_uppercase() => letter as u8 - b'A',
+		Some(_) => u8::MAX - 1,
+		None => u8::MAX,
+	}
+}
+
+fn planning_date(
+	note: &OrgNote,
+	pick: fn(&OrgPlanning) -> &Option<OrgTimestamp>,
+) -> Option<NaiveDate> {
+	pick(note.planning.as_ref()?).as_ref()?.to_naive_date()
+}
+
+/// Agenda order for `note`: its earliest scheduled or deadline date (undated
+/// notes last), then priority, then title. Like org's agenda, times of day are
+/// not compared.
+pub fn agenda_sort_key(note: &OrgNote) -> (NaiveDate, u8, String) {
+	let date = [
+		planning_date(note, |p| &p.scheduled),
+		planning_date(note, |p| &p.deadline),
+	]
+	.into_iter()
+	.flatten()
+	.min();
+	dated_sort_key(note, date)
+}
+
+fn dated_sort_key(note: &OrgNote, date: Option<NaiveDate>) -> (NaiveDate, u8, String) {
+	(
+		date.unwrap_or(NaiveDate::MAX),
+		priority_rank(note),
+		note.title.to_lowercase(),
+	)
+}
+
+/// Stably sorts `notes` by `key`, with notes lacking the key last. Ties on
+/// a date or priority are broken by priority and then title, as in
+/// [`agenda_sort_key`]. With `recursive`, every level of children is sorted
+/// the same way.
+pub fn sort_notes(notes: &mut [OrgNote], key: SortKey, recursive: bool) {
+	match key {
+		SortKey::Date => notes.sort_by_key(agenda_sort_key),
+		SortKey::Deadline => {
+			notes.sort_by_key(|note| dated_sort_key(note, planning_date(note, |p| &p.deadline)));
+		},
+		SortKey::Scheduled => {
+			notes.sort_by_key(|note| dated_sort_key(note, planning_date(note, |p| &p.scheduled)));
+		},
+		SortKey::Title => notes.sort_by_key(|note| note.title.to_lowercase()),
+		SortKey::Priority => {
+			notes.sort_by_key(|note| (priority_rank(note), note.title.to_lowercase()));
+		},
+	}
+
+	if recursive {
+		for note in notes {
+			sort_notes(&mut note.children, key, recursive);
+		}
+	}
+}
+
+/// A note taken out of its tree by [`flatten_notes`], with its children cleared.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlatNote {
+	#[serde(flatten)]
+	pub note: OrgNote,
+	/// Title of the parent note, `None` at the top level.
+	pub parent_title: Option<String>,
+	/// Position of the parent note in the flattened list.
+	pub parent_index: Option<usize>,
+	/// The note's [category](OrgNote::category), including an inherited one.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub category: Option<String>,
+}
+
+/// Walks a tree of notes depth-first, parents before their children, yielding
+/// each note with its depth (0 for top-level notes). See [`iter_notes`].
+#[derive(Debug, Clone)]
+pub struct NoteIter<'a> {
+	stack: Vec<std::slice::Iter<'a, OrgNote>>,
+	prune: Option<fn(&OrgNote) -> bool>,
+}
+
+/// Iterates over `notes` and all their descendants, depth-first.
+pub fn iter_notes(notes: &[OrgNote]) -> NoteIter<'_> {
+	NoteIter {
+		stack: vec![notes.iter()],
+		prune: None,
+	}
+}
+
+impl NoteIter<'_> {
+	/// Leaves out notes matching `prune`, along with their whole subtrees.
+	pub fn skip_subtree_if(self, prune: fn(&OrgNote) -> bool) -> Self {
+		Self {
+			prune: Some(prune),
+			..self
+		}
+	}
+}
+
+impl<'a> Iterator for NoteIter<'a> {
+	type Item = (usize, &'a OrgNote);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let depth = self.stack.len().checked_sub(1)?;
+			match self.stack[depth].next() {
+				Some(note) if self.prune.is_some_and(|prune| prune(note)) => {},
+				Some(note) => {
+					self.stack.push(note.children.iter());
+					return Some((depth, note));
+				},
+				None => {
+					self.stack.pop();
+				},
+			}
+		}
+	}
+}
+
+/// Lists the whole tree depth-first, parents before their children.
+pub fn flatten_notes(notes: &[OrgNote]) -> Vec<FlatNote> {
+	let mut flat: Vec<FlatNote> = Vec::new();
+	// Index in `flat` of the latest note at each depth
+	let mut ancestors: Vec<usize> = Vec::new();
+
+	for (depth, note) in iter_notes(notes) {
+		ancestors.truncate(depth);
+		let parent = ancestors.last().copied();
+		ancestors.push(flat.len());
+		let category = note.category(parent.and_then(|parent| flat[parent].category.as_deref()));
+		flat.push(FlatNote {
+			note: OrgNote {
+				children: Vec::new(),
+				..note.clone()
+			},
+			parent_title: parent.map(|parent| flat[parent].note.title.clone()),
+			parent_index: parent,
+			category,
+		});
+	}
+	flat
+}
+
+/// All notes in the tree whose title is exactly `title`, depth-first.
+pub fn find_by_title<'a>(notes: &'a [OrgNote], title: &str) -> Vec<&'a OrgNote> {
+	iter_notes(notes)
+		.map(|(_, note)| note)
+		.filter(|note| note.title == title)
+		.collect()
+}
+
+/// A note whose title or content matches a pattern, see [`grep_notes`].
+#[derive(Debug, Clone)]
+pub struct TextMatch<'a> {
+	pub note: &'a OrgNote,
+	/// Whether the title matched; otherwise a content line did.
+	pub in_title: bool,
+	/// The matched text with up to [`SNIPPET_CONTEXT`] characters around it.
+	pub snippet: String,
+}
+
+/// Characters of context on each side of a match in [`TextMatch::snippet`].
+pub const SNIPPET_CONTEXT: usize = 30;
+
+/// Every note in the tree, depth-first, whose title or content matches `pattern`.
+/// The title is tried first, then the content line by line; each note is
+/// listed once, for its first match.
+pub fn grep_notes<'a>(notes: &'a [OrgNote], pattern: &Regex) -> Vec<TextMatch<'a>> {
+	iter_notes(notes)
+		.filter_map(|(_, note)| {
+			let found = |text: &str| pattern.find(text).map(|m| snippet(text, m.range()));
+			match found(&note.title) {
+				Some(snippet) => Some(TextMatch {
+					note,
+					in_title: true,
+					snippet,
+				}),
+				None => note
+					.content
+					.lines()
+					.find_map(found)
+					.map(|snippet| TextMatch {
+						note,
+						in_title: false,
+						snippet,
+					}),
+			}
+		})
+		.collect()
+}
+
+/// `line` cut down to [`SNIPPET_CONTEXT`] characters either side of `range`,
+/// with `…` where it was cut.
+fn snippet(line: &str, range: std::ops::Range<usize>) -> String {
+	let before = &line[..range.start];
+	let after = &line[range.end..];
+	let start = before
+		.char_indices()
+		.rev()
+		.nth(SNIPPET_CONTEXT - 1)
+		.map_or(0, |(idx, _)| idx);
+	let end = after
+		.char_indices()
+		.nth(SNIPPET_CONTEXT)
+		.map_or(after.len(), |(idx, _)| idx);
+	let mut snippet = String::new();
+	if start > 0 {
+		snippet.push('…');
+	}
+	snippet.push_str(before[start..].trim_start());
+	snippet.push_str(&line[range]);
+	snippet.push_str(after[..end].trim_end());
+	if end < after.len() {
+		snippet.push('…');
+	}
+	snippet
+}
+
+/// The id of the first note after the one with `from_id`, in depth-first order,
+/// that satisfies `predicate`, wrapping around past the end. The starting note
+/// itself is only returned if it is the one match. Without a note with
+/// `from_id`, the search starts at the top.
+pub fn next_matching<F>(notes: &[OrgNote], from_id: u64, predicate: F) -> Option<u64>
+where
+	F: Fn(&OrgNote) -> bool,
+{
+	let order: Vec<&OrgNote> = iter_notes(notes).map(|(_, note)| note).collect();
+	let split = order
+		.iter()
+		.position(|note| note.id == from_id)
+		.map_or(0, |start| start + 1);
+	order[split..]
+		.iter()
+		.chain(&order[..split])
+		.find(|note| predicate(note))
+		.map(|note| note.id)
+}
+
+/// Like [`next_matching`], searching backwards and wrapping past the start.
+pub fn prev_matching<F>(notes: &[OrgNote], from_id: u64, predicate: F) -> Option<u64>
+where
+	F: Fn(&OrgNote) -> bool,
+{
+	let order: Vec<&OrgNote> = iter_notes(notes).map(|(_, note)| note).collect();
+	let split = order
+		.iter()
+		.position(|note| note.id == from_id)
+		.unwrap_or(order.len());
+	order[..split]
+		.iter()
+		.rev()
+		.chain(order[split..].iter().rev())
+		.find(|note| predicate(note))
+		.map(|note| note.id)
+}
+
+/// Ids of the notes held back by an [ordered](OrgNote::is_ordered) parent: the
+/// children after its first unfinished task, with their subtrees. A child
+/// without a status does not hold back the ones after it. Like org's, the
+/// property applies to the note's own children only.
+pub fn blocked_by_order(notes: &[OrgNote], keywords: &Keywords) -> HashSet<u64> {
+	let unfinished = |note: &OrgNote| note.status.is_some() && !keywords.is_done_task(note);
+	let mut blocked = HashSet::new();
+	for (_, note) in iter_notes(notes).filter(|(_, note)| note.is_ordered()) {
+		if let Some(first) = note.children.iter().position(unfinished) {
+			let later = iter_notes(&note.children[first + 1..]);
+			blocked.extend(later.map(|(_, note)| note.id));
+		}
+	}
+	blocked
+}
+
+/// The next [active task](Keywords::is_active_task) after the note with
+/// `from_id` that is not [blocked by an ordered parent](blocked_by_order), see
+/// [`next_matching`].
+pub fn next_todo(notes: &[OrgNote], keywords: &Keywords, from_id: u64) -> Option<u64> {
+	let blocked = blocked_by_order(notes, keywords);
+	next_matching(notes, from_id, |note| {
+		keywords.is_active_task(note) && !blocked.contains(&note.id)
+	})
+}
+
+/// The previous unblocked active task before the note with `from_id`, see
+/// [`prev_matching`].
+pub fn prev_todo(notes: &[OrgNote], keywords: &Keywords, from_id: u64) -> Option<u64> {
+	let blocked = blocked_by_order(notes, keywords);
+	prev_matching(notes, from_id, |note| {
+		keywords.is_active_task(note) && !blocked.contains(&note.id)
+	})
+}
+
+/// Follows `path` down the tree, one title per level, e.g. `["Project", "Subtask"]`.
+/// When siblings share a title, the first one the rest of the path resolves under wins.
+pub fn find_by_path<'a>(notes: &'a [OrgNote], path: &[&str]) -> Option<&'a OrgNote> {
+	let (first, rest) = path.split_first()?;
+	notes
+		.iter()
+		.filter(|note| note.title == *first)
+		.find_map(|note| {
+			if rest.is_empty() {
+				Some(note)
+			} else {
+				find_by_path(&note.children, rest)
+			}
+		})
+}
+
+/// Sets every child's `level` to one more than its parent's, top-down, so
+/// the tree serializes to headings whose stars match its shape. Top-level
+/// notes keep their levels.
+pub fn renumber_levels(notes: &mut [OrgNote]) {
+	for note in notes {
+		for child in &mut note.children {
+			child.level = note.level + 1;
+		}
+		renumber_levels(&mut note.children);
+	}
+}
+
+/// Drops the notes more than `max_depth` levels down the tree, so `1` keeps only
+/// the top-level notes. With `count`, a note that lost its children records how
+/// many in [`OrgNote::children_count`].
+pub fn prune_depth(notes: &mut [OrgNote], max_depth: usize, count: bool) {
+	for note in notes {
+		if max_depth <= 1 {
+			if count && !note.children.is_empty() {
+				note.children_count = Some(note.children.len());
+			}
+			note.children.clear();
+		} else {
+			prune_depth(&mut note.children, max_depth - 1, count);
+		}
+	}
+}
+
+/// [Clocks out](OrgClockEntry::clock_out) every running entry in the tree at
+/// `now`. Returns how many were closed.
+pub fn clock_out_all(notes: &mut [OrgNote], now: NaiveDateTime) -> usize {
+	let mut closed = 0;
+	for note in notes {
+		if let Some(logbook) = &mut note.logbook {
+			for entry in &mut logbook.clock_entries {
+				if entry.clock_out(now) {
+					closed += 1;
+				}
+			}
+		}
+		closed += clock_out_all(&mut note.children, now);
+	}
+	closed
+}
+
+/// Heading that [`archive_to_heading`] uses when no `ARCHIVE` property says otherwise.
+pub const DEFAULT_ARCHIVE_HEADING: &str = "Archive";
+
+/// Moves the subtree of the note with `id` to the end of the in-file archive
+/// heading, which is created at the end of the file if missing. Like org, the
+/// heading comes from the `ARCHIVE` property of the note or its nearest ancestor
+/// with one, written `::* Heading`; without one it is a top-level
+/// [`DEFAULT_ARCHIVE_HEADING`]. Archiving into another file is not supported.
+pub fn archive_to_heading(notes: &mut Vec<OrgNote>, id: u64) -> Result<(), String> {
+	let mut ancestors: Vec<&OrgNote> = Vec::new();
+	let mut found = None;
+	for (depth, note) in iter_notes(notes) {
+		ancestors.truncate(depth);
+		ancestors.push(note);
+		if note.id == id {
+			found = Some(ancestors);
+			break;
+		}
+	}
+	let ancestors = found.ok_or_else(|| "no such note".to_string())?;
+	let location = ancestors
+		.iter()
+		.rev()
+		.find_map(|note| note.property("ARCHIVE"));
+	let (level, title) = match location {
+		Some(location) => parse_archive_location(location)?,
+		None => (1, DEFAULT_ARCHIVE_HEADING.to_string()),
+	};
+	if ancestors
+		.iter()
+		.any(|note| note.level == level && note.title == title)
+	{
+		return Err(format!("already under '{}'", title));
+	}
+
+	let mut note = take_note(notes, id).ok_or_else(|| "no such note".to_string())?;
+	let archive = match find_heading_mut(notes, level, &title) {
+		Some(archive) => archive,
+		None => {
+			notes.push(OrgNote::new(level, title));
+			notes.last_mut().unwrap()
+		},
+	};
+	note.level = archive.level + 1;
+	renumber_levels(std::slice::from_mut(&mut note));
+	archive.children.push(note);
+	Ok(())
+}
+
+/// Splits an `ARCHIVE` property such as `::* Archived Tasks` into the heading's
+/// level and title.
+fn parse_archive_location(location: &str) -> Result<(usize, String), String> {
+	let (file, heading) = location
+		.split_once("::")
+		.ok_or_else(|| format!("invalid ARCHIVE location '{}'", location))?;
+	if !file.trim().is_empty() {
+		return Err(format!("cannot archive to another file ({})", file.trim()));
+	}
+	let heading = heading.trim();
+	let title = heading.trim_start_matches('*');
+	let level = (heading.len() - title.len()).max(1);
+	match title.trim() {
+		"" => Ok((1, DEFAULT_ARCHIVE_HEADING.to_string())),
+		title => Ok((level, title.to_string())),
+	}
+}
+
+/// Removes the note with `id` and its subtree from the tree.
+fn take_note(notes: &mut Vec<OrgNote>, id: u64) -> Option<OrgNote> {
+	if let Some(position) = notes.iter().position(|note| note.id == id) {
+		return Some(notes.remove(position));
+	}
+	notes
+		.iter_mut()
+		.find_map(|note| take_note(&mut note.children, id))
+}
+
+/// The first note, depth-first, with this level and title.
+fn find_heading_mut<'a>(
+	notes: &'a mut [OrgNote],
+	level: usize,
+	title: &str,
+) -> Option<&'a mut OrgNote> {
+	for note in notes {
+		if note.level == level && note.title == title {
+			return Some(note);
+		}
+		if let Some(found) = find_heading_mut(&mut note.children, level, title) {
+			return Some(found);
+		}
+	}
+	None
+}
+
+/// Returns a copy of the tree keeping the notes that match `keep`, along
+/// with the ancestors needed to reach them.
+pub fn filter_notes<F>(notes: &[OrgNote], keep: &F) -> Vec<OrgNote>
+where
+	F: Fn(&OrgNote) -> bool,
+{
+	notes
+		.iter()
+		.filter_map(|note| {
+			let children = filter_notes(&note.children, keep);
+			(keep(note) || !children.is_empty()).then(|| OrgNote {
+				children,
+				..note.clone()
+			})
+		})
+		.collect()
+}
+
+/// A filter for notes, read from text such as `status:TODO|status:NEXT,!tag:work`.
+///
+/// Terms are `tag:NAME`, `status:KEYWORD` and `priority:A`. A tag term matches
+/// tags inherited from ancestors too, as in org. `!` negates the term after it
+/// and binds tightest, then `|` joins terms of which any may match, and `,`
+/// joins groups that must all match, so `a|b,!c` means `(a or b) and not c`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+	Tag(String),
+	Status(String),
+	Priority(char),
+	Not(Box<Query>),
+	Any(Vec<Query>),
+	All(Vec<Query>),
+}
+
+impl std::str::FromStr for Query {
+	type Err = String;
+
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		let groups = text
+			.split(',')
+			.map(|group| {
+				let terms = group
+					.split('|')
+					.map(Query::parse_term)
+					.collect::<Result<Vec<_>, _>>()?;
+				Ok(Query::Any(terms))
+			})
+			.collect::<Result<Vec<_>, String>>()?;
+		Ok(Query::All(groups))
+	}
+}
+
+impl Query {
+	fn parse_term(term: &str) -> Result<Query, String> {
+		let term = term.trim();
+		if let Some(negated) = term.strip_prefix('!') {
+			return Ok(Query::Not(Box::new(Query::parse_term(negated)?)));
+		}
+		let (field, value) = term
+			.split_once(':')
+			.filter(|(_, value)| !value.is_empty())
+			.ok_or_else(|| format!("expected FIELD:VALUE, found '{}'", term))?;
+		match field {
+			"tag" => Ok(Query::Tag(value.to_string())),
+			"status" => Ok(Query::Status(value.to_string())),
+			"priority" => match value.chars().collect::<Vec<_>>()[..] {
+				[priority] => Ok(Query::Priority(priority.to_ascii_uppercase())),
+				_ => Err(format!("priority must be one letter, found '{}'", value)),
+			},
+			_ => Err(format!("unknown query field '{}'", field)),
+		}
+	}
+
+	/// Whether `note` matches, `tags` being its own tags and those it inherits.
+	pub fn matches(&self, note: &OrgNote, tags: &[&str]) -> bool {
+		match self {
+			Query::Tag(tag) => tags.contains(&tag.as_str()),
+			Query::Status(status) => note.status.as_deref() == Some(status),
+			Query::Priority(priority) => note.priority == Some(*priority),
+			Query::Not(query) => !query.matches(note, tags),
+			Query::Any(queries) => queries.iter().any(|query| query.matches(note, tags)),
+			Query::All(queries) => queries.iter().all(|query| query.matches(note, tags)),
+		}
+	}
+}
+
+/// Like [`filter_notes`], keeping the notes that match `query` and their ancestors.
+pub fn query_notes(notes: &[OrgNote], query: &Query) -> Vec<OrgNote> {
+	query_notes_under(notes, query, &[])
+}
+
+fn query_notes_under(notes: &[OrgNote], query: &Query, inherited: &[&str]) -> Vec<OrgNote> {
+	notes
+		.iter()
+		.filter_map(|note| {
+			let mut tags = inherited.to_vec();
+			tags.extend(note.labels.iter().map(String::as_str));
+			let children = query_notes_under(&note.children, query, &tags);
+			(query.matches(note, &tags) || !children.is_empty()).then(|| OrgNote {
+				children,
+				..note.clone()
+			})
+		})
+		.collect()
+}
+
+/// One clock entry together with the note it was logged under.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockedEntry<'a> {
+	pub note: &'a OrgNote,
+	pub entry: &'a OrgClockEntry,
+}
+
+/// Two clock entries whose intervals overlap, `first` starting no later than `second`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockOverlap<'a> {
+	pub first: ClockedEntry<'a>,
+	pub second: ClockedEntry<'a>,
+}
+
+/// Finds every pair of valid clock entries in the tree whose intervals overlap.
+/// Running entries count as ending at `now`. Entries that only touch, one
+/// ending the minute the other starts, do not overlap.
+pub fn find_clock_overlaps(notes: &[OrgNote], now: NaiveDateTime) -> Vec<ClockOverlap<'_>> {
+	let mut intervals = Vec::new();
+	for (_, note) in iter_notes(notes) {
+		for entry in note
+			.logbook
+			.iter()
+			.flat_map(|logbook| &logbook.clock_entries)
+		{
+			if !entry.is_valid() {
+				continue;
+			}
+			let Some(start) = entry.start.to_naive_datetime() else {
+				continue;
+			};
+			let end = match &entry.end {
+				Some(end) => end.to_naive_datetime().unwrap_or(start),
+				None => now.max(start),
+			};
+			intervals.push((start, end, ClockedEntry { note, entry }));
+		}
+	}
+	intervals.sort_by_key(|&(start, end, _)| (start, end));
+
+	let mut overlaps = Vec::new();
+	for (i, &(_, end, first)) in intervals.iter().enumerate() {
+		for &(start, _, second) in &intervals[i + 1..] {
+			if start >= end {
+				break;
+			}
+			overlaps.push(ClockOverlap { first, second });
+		}
+	}
+	overlaps
+}
+
+/// A kind of problem reported by [`lint_notes`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LintRule {
+	/// A done task without a `CLOSED:` timestamp.
+	DoneWithoutClosed,
+	/// An unfinished task whose deadline has passed.
+	OverdueDeadline,
+	/// A clock that has been running for too long.
+	StaleClock,
+	/// A planning timestamp that names no real date or time, like `2024-02-30`.
+	InvalidDate,
+	/// An `:ID:` property already used by an earlier heading.
+	DuplicateId,
+}
+
+/// One problem found by [`lint_notes`], with the note it was found on.
+#[derive(Debug, Clone)]
+pub struct LintFinding<'a> {
+	pub note: &'a OrgNote,
+	pub rule: LintRule,
+	pub message: String,
+}
+
+/// Checks every note in the tree for common mistakes, in document order. A
+/// running clock is stale once it started more than `max_clock_hours` before `now`.
+/// Which tasks are done or overdue follows `keywords`.
+pub fn lint_notes<'a>(
+	notes: &'a [OrgNote],
+	keywords: &Keywords,
+	now: NaiveDateTime,
+	max_clock_hours: u32,
+) -> Vec<LintFinding<'a>> {
+	let mut findings = Vec::new();
+	let mut ids: HashMap<&str, &OrgNote> = HashMap::new();
+	for (_, note) in iter_notes(notes) {
+		let mut report = |rule, message| {
+			findings.push(LintFinding {
+				note,
+				rule,
+				message,
+			})
+		};
+		let planning = note.planning.as_ref();
+		let done = keywords.is_done_task(note);
+
+		if done
+			&& planning
+				.and_then(|planning| planning.closed.as_ref())
+				.is_none()
+		{
+			report(
+				LintRule::DoneWithoutClosed,
+				"done task has no CLOSED timestamp".to_string(),
+			);
+		}
+
+		if keywords.is_overdue(note, now)
+			&& let Some(deadline) = planning.and_then(|planning| planning.deadline.as_ref())
+		{
+			report(
+				LintRule::OverdueDeadline,
+				format!("deadline {} has passed", deadline.raw),
+			);
+		}
+
+		for entry in note
+			.logbook
+			.iter()
+			.flat_map(|logbook| &logbook.clock_entries)
+			.filter(|entry| entry.end.is_none())
+		{
+			if let Some(start) = entry.start.to_naive_datetime()
+				&& now - start > chrono::Duration::hours(max_clock_hours.into())
+			{
+				report(
+					LintRule::StaleClock,
+					format!(
+						"clock running since {} for over {} hours",
+						entry.start.raw, max_clock_hours
+					),
+				);
+			}
+		}
+
+		for (keyword, timestamp) in planning.into_iter().flat_map(|planning| {
+			[
+				("SCHEDULED", &planning.scheduled),
+				("DEADLINE", &planning.deadline),
+				("CLOSED", &planning.closed),
+			]
+		}) {
+			if let Some(timestamp) = timestamp
+				&& timestamp.to_naive_datetime().is_none()
+			{
+				report(
+					LintRule::InvalidDate,
+					format!("{} {} is not a valid date", keyword, timestamp.raw),
+				);
+			}
+		}
+
+		if let Some(id) = note.property("ID") {
+			match ids.get(id) {
+				Some(first) => report(
+					LintRule::DuplicateId,
+					format!("ID {} is also used by '{}'", id, first.heading_text()),
+				),
+				None => {
+					ids.insert(id, note);
+				},
+			}
+		}
+	}
+	findings
+}
+
+/// A note with an effort estimate and the time clocked on it and its subtree.
+#[derive(Debug, Clone, Copy)]
+pub struct EffortComparison<'a> {
+	pub note: &'a OrgNote,
+	pub estimated: u32,
+	pub tracked: u32,
+}
+
+impl EffortComparison<'_> {
+	/// Whether more time was tracked than estimated.
+	pub fn is_over(&self) -> bool {
+		self.tracked > self.estimated
+	}
+}
+
+/// Every note with an [effort](OrgNote::effort_minutes), in document order, with
+/// the minutes clocked on it and its children in `range`. Like the summary,
+/// archived subtrees are skipped.
+pub fn compare_efforts<'a>(
+	notes: &'a [OrgNote],
+	range: &DateRange,
+	rounding: &Rounding,
+) -> Vec<EffortComparison<'a>> {
+	iter_notes(notes)
+		.skip_subtree_if(OrgNote::is_archived)
+		.filter_map(|(_, note)| {
+			let estimated = note.effort_minutes()?;
+			let tracked = iter_notes(std::slice::from_ref(note))
+				.skip_subtree_if(OrgNote::is_archived)
+				.filter_map(|(_, note)| note.logbook.as_ref())
+				.map(|logbook| logbook.minutes_in(range, rounding))
+				.sum();
+			Some(EffortComparison {
+				note,
+				estimated,
+				tracked,
+			})
+		})
+		.collect()
+}
+
+/// A calendar period to group clock totals by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockPeriod {
+	Day,
+	/// An ISO 8601 week, which starts on Monday.
+	Week,
+	Month,
+}
+
+impl std::str::FromStr for ClockPeriod {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"day" => Ok(ClockPeriod::Day),
+			"week" => Ok(ClockPeriod::Week),
+			"month" => Ok(ClockPeriod::Month),
+			_ => Err(format!("unknown period '{}'", s)),
+		}
+	}
+}
+
+impl ClockPeriod {
+	/// The period `date` falls in, e.g. `2024-01-15`, `2024-W03` or `2024-01`.
+	/// Labels of the same kind sort chronologically.
+	pub fn label(&self, date: NaiveDate) -> String {
+		match self {
+			ClockPeriod::Day => date.format("%Y-%m-%d").to_string(),
+			ClockPeriod::Week => {
+				let week = date.iso_week();
+				format!("{:04}-W{:02}", week.year(), week.week())
+			},
+			ClockPeriod::Month => date.format("%Y-%m").to_string(),
+		}
+	}
+}
+
+/// Clocked minutes per `period`, in chronological order. Counts the same
+/// entries as [`OrgLogbook::minutes_in`], but splits those crossing midnight
+/// with [`OrgClockEntry::minutes_by_day`], rounding each day's part on its
+/// own. Like the summary, skips [archived](OrgNote::is_archived) subtrees.
+pub fn clock_totals_by_period(
+	notes: &[OrgNote],
+	period: ClockPeriod,
+	range: &DateRange,
+	rounding: &Rounding,
+) -> Vec<(String, u32)> {
+	let mut totals = BTreeMap::new();
+	for (_, note) in iter_notes(notes).skip_subtree_if(OrgNote::is_archived) {
+		let entries = note
+			.logbook
+			.iter()
+			.flat_map(|logbook| &logbook.clock_entries);
+		for (date, minutes) in entries.flat_map(OrgClockEntry::minutes_by_day) {
+			if range.contains(date) {
+				*totals.entry(period.label(date)).or_default() += rounding.apply(minutes);
+			}
+		}
+	}
+	totals.into_iter().collect()
+}
+
+/// Clocked minutes per tag, sorted by tag, counting the same entries as
+/// [`clock_totals_by_period`]. A note's time counts once toward each of its
+/// own tags, even one repeated on the heading; untagged notes are left out.
+pub fn clock_totals_by_tag(
+	notes: &[OrgNote],
+	range: &DateRange,
+	rounding: &Rounding,
+) -> Vec<(String, u32)> {
+	let mut totals = BTreeMap::new();
+	for (_, note) in iter_notes(notes).skip_subtree_if(OrgNote::is_archived) {
+		if note.labels.is_empty() {
+			continue;
+		}
+		let minutes: u32 = clocked_minutes(note)
+			.filter(|(date, _)| range.contains(*date))
+			.map(|(_, minutes)| rounding.apply(minutes))
+			.sum();
+		if minutes == 0 {
+			continue;
+		}
+		for tag in note.labels.iter().collect::<BTreeSet<_>>() {
+			*totals.entry(tag.clone()).or_default() += minutes;
+		}
+	}
+	totals.into_iter().collect()
+}
+
+/// Start date and duration of each valid, finished clock entry on `note`.
+fn clocked_minutes(note: &OrgNote) -> impl Iterator<Item = (NaiveDate, u32)> + '_ {
+	note.logbook
+		.iter()
+		.flat_map(|logbook| &logbook.clock_entries)
+		.filter(|entry| entry.is_valid())
+		.filter_map(|entry| {
+			Some((
+				entry.start.to_naive_date()?,
+				entry.parse_duration_minutes()?,
+			))
+		})
+}
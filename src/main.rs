@@ -1,535 +1,315 @@
-use chrono::{Datelike, Local, Timelike};
-use clap::{Arg, Command};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use clap::{Arg, ArgMatches, Command};
 use crossterm::{
+	cursor::MoveTo,
 	event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
 	execute,
-	terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+	terminal::{
+		self, ClearType, EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode,
+		enable_raw_mode,
+	},
 };
+use glob::Pattern;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use ratatui::{
 	Frame, Terminal,
 	backend::{Backend, CrosstermBackend},
 	layout::{Constraint, Direction, Layout, Rect},
 	style::{Color, Modifier, Style},
 	text::{Line, Span},
-	widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+	widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
 };
-use serde::{Deserialize, Serialize};
+use regex::RegexBuilder;
+use rorg::{
+	ClockOverlap, ClockPeriod, DateRange, Keywords, LintFinding, OrgClockEntry, OrgLogbook,
+	OrgNote, OrgParser, OrgPlanning, OrgTimestamp, Query, Rounding, SerializeOptions, SortKey,
+	TextMatch, TimeFormat, TimestampKind, archive_to_heading, clock_out_all,
+	clock_totals_by_period, clock_totals_by_tag, compare_efforts, extract_links, extract_tables,
+	filter_notes, find_by_path, find_clock_overlaps, flatten_notes, grep_notes, is_valid_tag,
+	iter_notes, lint_notes, next_matching, next_todo, parse_relative_date, prev_todo, prune_depth,
+	query_notes, renumber_levels, sort_notes,
+};
+use serde::Deserialize;
+use similar::TextDiff;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
-use std::io;
-use std::path::Path;
-
-mod tests;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OrgTimestamp {
-	pub year: u32,
-	pub month: u32,
-	pub day: u32,
-	pub hour: Option<u32>,
-	pub minute: Option<u32>,
-	pub day_name: Option<String>,
-	pub raw: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OrgClockEntry {
-	pub start: OrgTimestamp,
-	pub end: Option<OrgTimestamp>,
-	pub duration: Option<String>,
-	pub raw: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OrgLogbook {
-	pub clock_entries: Vec<OrgClockEntry>,
-	pub raw_content: Vec<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OrgPlanning {
-	pub scheduled: Option<OrgTimestamp>,
-	pub deadline: Option<OrgTimestamp>,
-	pub closed: Option<OrgTimestamp>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OrgNote {
-	pub level: usize,
-	pub status: Option<String>,
-	pub title: String,
-	pub labels: Vec<String>,
-	pub content: String,
-	pub children: Vec<OrgNote>,
-	pub planning: Option<OrgPlanning>,
-	pub logbook: Option<OrgLogbook>,
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[cfg(test)]
+mod tui_tests;
+
+/// Which clock entries the time summary counts, how each is rounded, and
+/// the period to break the total down by, if any.
+#[derive(Debug, Default)]
+struct ClockReport {
+	range: DateRange,
+	rounding: Rounding,
+	group: Option<ClockPeriod>,
+	/// Which statuses count as completed and which as active.
+	keywords: Keywords,
+	time_format: TimeFormat,
 }
 
-impl OrgNote {
-	pub fn new(level: usize, title: String) -> Self {
-		Self {
-			level,
-			status: None,
-			title,
-			labels: Vec::new(),
-			content: String::new(),
-			children: Vec::new(),
-			planning: None,
-			logbook: None,
-		}
-	}
-}
+fn print_time_summary(
+	out: &mut impl io::Write,
+	notes: &[OrgNote],
+	report: &ClockReport,
+) -> io::Result<()> {
+	let mut total_tracked_minutes = 0;
+	let mut completed_tasks = 0;
+	let mut active_tasks = 0;
+	let mut scheduled_tasks = 0;
+	let mut overdue_tasks = 0;
 
-pub struct OrgParser {
-	lines: Vec<String>,
-	current_line: usize,
-}
+	collect_time_stats(
+		notes,
+		report,
+		&mut total_tracked_minutes,
+		&mut completed_tasks,
+		&mut active_tasks,
+		&mut scheduled_tasks,
+		&mut overdue_tasks,
+	);
 
-impl OrgParser {
-	pub fn new(content: &str) -> Self {
-		Self {
-			lines: content.lines().map(|s| s.to_string()).collect(),
-			current_line: 0,
-		}
+	writeln!(out, "Time Tracking Summary:")?;
+	writeln!(out, "---------------------")?;
+	let time = |minutes: u32| report.time_format.format(minutes);
+	writeln!(out, "Total tracked time: {}", time(total_tracked_minutes))?;
+	writeln!(out, "Completed tasks: {}", completed_tasks)?;
+	writeln!(out, "Active tasks: {}", active_tasks)?;
+	writeln!(out, "Scheduled tasks: {}", scheduled_tasks)?;
+	if overdue_tasks > 0 {
+		writeln!(out, "⚠️  Overdue tasks: {}", overdue_tasks)?;
 	}
-
-	pub fn parse(&mut self) -> Vec<OrgNote> {
-		let mut notes = Vec::new();
-
-		while self.current_line < self.lines.len() {
-			let line = &self.lines[self.current_line];
-
-			if let Some(level) = self.count_asterisks(line) {
-				if let Some(note) = self.parse_note(level) {
-					notes.push(note);
-				}
-			} else {
-				self.current_line += 1;
-			}
+	let tag_totals = clock_totals_by_tag(notes, &report.range, &report.rounding);
+	if !tag_totals.is_empty() {
+		writeln!(out)?;
+		writeln!(out, "Tracked time by tag:")?;
+		for (tag, minutes) in tag_totals {
+			writeln!(out, "  {}: {}", tag, time(minutes))?;
 		}
-
-		notes
 	}
-
-	fn count_asterisks(&self, line: &str) -> Option<usize> {
-		let trimmed = line.trim_start();
-		if trimmed.starts_with('*') {
-			let count = trimmed.chars().take_while(|&c| c == '*').count();
-			if count > 0 && trimmed.chars().nth(count) == Some(' ') {
-				return Some(count);
-			}
+	if let Some(period) = report.group {
+		writeln!(out)?;
+		writeln!(out, "Tracked time by {}:", period_name(period))?;
+		for (label, minutes) in
+			clock_totals_by_period(notes, period, &report.range, &report.rounding)
+		{
+			writeln!(out, "  {}: {}", label, time(minutes))?;
 		}
-		None
 	}
-
-	fn parse_note(&mut self, level: usize) -> Option<OrgNote> {
-		if self.current_line >= self.lines.len() {
-			return None;
-		}
-
-		let line = &self.lines[self.current_line];
-		let header_content = self.extract_header_content(line, level);
-
-		let (status, title, labels) = self.parse_header_parts(&header_content);
-
-		let mut note = OrgNote::new(level, title);
-		note.status = status;
-		note.labels = labels;
-
-		self.current_line += 1;
-
-		// Collect content until next heading of same or higher level
-		let mut content_lines = Vec::new();
-		let mut child_notes = Vec::new();
-
-		while self.current_line < self.lines.len() {
-			let line = &self.lines[self.current_line];
-
-			if let Some(next_level) = self.count_asterisks(line) {
-				if next_level <= level {
-					// Same or higher level heading, stop collecting content
-					break;
+	let efforts = compare_efforts(notes, &report.range, &report.rounding);
+	if !efforts.is_empty() {
+		writeln!(out)?;
+		writeln!(out, "Tracked / estimated effort:")?;
+		for effort in &efforts {
+			writeln!(
+				out,
+				"  {}: {} / {}{}",
+				effort.note.title,
+				time(effort.tracked),
+				time(effort.estimated),
+				if effort.is_over() {
+					"  ⚠️  over"
 				} else {
-					// Child heading, parse it as a child note
-					if let Some(child_note) = self.parse_note(next_level) {
-						child_notes.push(child_note);
-					}
+					""
 				}
-			} else {
-				// Regular content line
-				content_lines.push(line.clone());
-				self.current_line += 1;
-			}
+			)?;
 		}
-
-		let content_text = content_lines.join("\n");
-		let (cleaned_content, planning, logbook) = self.parse_time_elements(&content_text);
-
-		note.content = cleaned_content;
-		note.planning = planning;
-		note.logbook = logbook;
-		note.children = child_notes;
-
-		Some(note)
 	}
+	writeln!(out)
+}
 
-	fn extract_header_content(&self, line: &str, level: usize) -> String {
-		let trimmed = line.trim_start();
-		// Skip the asterisks and the space after them
-		trimmed.chars().skip(level + 1).collect()
+fn period_name(period: ClockPeriod) -> &'static str {
+	match period {
+		ClockPeriod::Day => "day",
+		ClockPeriod::Week => "week",
+		ClockPeriod::Month => "month",
 	}
+}
 
-	fn parse_header_parts(&self, header: &str) -> (Option<String>, String, Vec<String>) {
-		let trimmed = header.trim();
-
-		// Extract labels (org-mode tags at the end, starting with :)
-		let mut labels = Vec::new();
-		let mut content = trimmed;
-
-		// Find the last space followed by a colon (start of tags section)
-		if let Some(tag_start) = trimmed.rfind(char::is_whitespace) {
-			let potential_tags = &trimmed[tag_start..].trim_start();
-			if potential_tags.starts_with(':')
-				&& potential_tags.ends_with(':')
-				&& potential_tags.len() > 2
-			{
-				// Extract tags between colons
-				let tags_content = &potential_tags[1..potential_tags.len() - 1];
-				labels = tags_content
-					.split(':')
-					.map(|s| s.trim().to_string())
-					.filter(|s| !s.is_empty())
-					.collect();
-				content = trimmed[..tag_start].trim();
-			}
-		}
-
-		// Extract status (first word if it's uppercase)
-		let words: Vec<&str> = content.split_whitespace().collect();
-		let mut status = None;
-		let mut title_start = 0;
-
-		if let Some(first_word) = words.first() {
-			if first_word
-				.chars()
-				.all(|c| c.is_uppercase() || !c.is_alphabetic())
-				&& first_word.len() > 0
-			{
-				status = Some(first_word.to_string());
-				title_start = 1;
-			}
-		}
-
-		let title = words[title_start..].join(" ");
-
-		(status, title, labels)
+fn print_word_count_summary(out: &mut impl io::Write, notes: &[OrgNote]) -> io::Result<()> {
+	writeln!(out, "Word Count Summary:")?;
+	writeln!(out, "-------------------")?;
+	for note in notes {
+		writeln!(
+			out,
+			"{}: {} words, {} characters",
+			note.title,
+			note.total_word_count(),
+			note.total_char_count()
+		)?;
 	}
+	writeln!(
+		out,
+		"Total: {} words, {} characters",
+		notes.iter().map(OrgNote::total_word_count).sum::<usize>(),
+		notes.iter().map(OrgNote::total_char_count).sum::<usize>()
+	)?;
+	writeln!(out)
+}
 
-	fn parse_time_elements(
-		&self,
-		content: &str,
-	) -> (String, Option<OrgPlanning>, Option<OrgLogbook>) {
-		let lines: Vec<&str> = content.lines().collect();
-		let mut cleaned_lines = Vec::new();
-		let mut planning = OrgPlanning {
-			scheduled: None,
-			deadline: None,
-			closed: None,
-		};
-		let mut logbook = None;
-		let mut in_logbook = false;
-		let mut logbook_lines = Vec::new();
-		let mut clock_entries = Vec::new();
-
-		for line in lines {
-			let trimmed = line.trim();
-
-			// Check for logbook start/end
-			if trimmed == ":LOGBOOK:" {
-				in_logbook = true;
-				continue;
-			} else if trimmed == ":END:" && in_logbook {
-				in_logbook = false;
-				logbook = Some(OrgLogbook {
-					clock_entries: clock_entries.clone(),
-					raw_content: logbook_lines.clone(),
-				});
-				logbook_lines.clear();
-				continue;
-			}
-
-			if in_logbook {
-				logbook_lines.push(line.to_string());
-				if let Some(clock_entry) = self.parse_clock_line(line) {
-					clock_entries.push(clock_entry);
-				}
-				continue;
-			}
-
-			// Check for planning keywords
-			if let Some(timestamp) = self.extract_planning_timestamp(line, "SCHEDULED:") {
-				planning.scheduled = Some(timestamp);
-				continue;
-			} else if let Some(timestamp) = self.extract_planning_timestamp(line, "DEADLINE:") {
-				planning.deadline = Some(timestamp);
-				continue;
-			} else if let Some(timestamp) = self.extract_planning_timestamp(line, "CLOSED:") {
-				planning.closed = Some(timestamp);
-				continue;
+fn print_links(out: &mut impl io::Write, notes: &[OrgNote]) -> io::Result<()> {
+	for note in notes {
+		for link in &note.links {
+			match &link.description {
+				Some(description) => {
+					writeln!(out, "{}: {} ({})", note.title, link.target, description)?
+				},
+				None => writeln!(out, "{}: {}", note.title, link.target)?,
 			}
-
-			cleaned_lines.push(line);
-		}
-
-		let has_planning = planning.scheduled.is_some()
-			|| planning.deadline.is_some()
-			|| planning.closed.is_some();
-		let final_planning = if has_planning { Some(planning) } else { None };
-
-		(cleaned_lines.join("\n"), final_planning, logbook)
-	}
-
-	fn extract_planning_timestamp(&self, line: &str, keyword: &str) -> Option<OrgTimestamp> {
-		if let Some(pos) = line.find(keyword) {
-			let after_keyword = &line[pos + keyword.len()..].trim();
-			self.parse_timestamp_from_text(after_keyword)
-		} else {
-			None
 		}
+		print_links(out, &note.children)?;
 	}
+	Ok(())
+}
 
-	fn parse_clock_line(&self, line: &str) -> Option<OrgClockEntry> {
-		let trimmed = line.trim();
-		if !trimmed.starts_with("CLOCK:") {
-			return None;
-		}
-
-		let clock_content = &trimmed[6..].trim();
-
-		// Parse format: [start]--[end] => duration
-		if let Some(arrow_pos) = clock_content.find("=>") {
-			let time_part = &clock_content[..arrow_pos].trim();
-			let duration_part = clock_content[arrow_pos + 2..].trim();
-
-			if let Some(dash_pos) = time_part.find("--") {
-				let start_part = &time_part[..dash_pos].trim();
-				let end_part = &time_part[dash_pos + 2..].trim();
-
-				if let (Some(start), Some(end)) = (
-					self.parse_timestamp_from_text(start_part),
-					self.parse_timestamp_from_text(end_part),
-				) {
-					return Some(OrgClockEntry {
-						start,
-						end: Some(end),
-						duration: Some(duration_part.to_string()),
-						raw: line.to_string(),
-					});
-				}
-			}
-		} else if let Some(timestamp) = self.parse_timestamp_from_text(clock_content) {
-			// Single timestamp (clock in, no clock out yet)
-			return Some(OrgClockEntry {
-				start: timestamp,
-				end: None,
-				duration: None,
-				raw: line.to_string(),
-			});
-		}
-
-		None
+fn print_clock_overlaps(out: &mut impl io::Write, overlaps: &[ClockOverlap]) -> io::Result<()> {
+	for ClockOverlap { first, second } in overlaps {
+		writeln!(
+			out,
+			"{}: {}\n  overlaps {}: {}",
+			first.note.title,
+			first.entry.raw.trim(),
+			second.note.title,
+			second.entry.raw.trim()
+		)?;
 	}
+	writeln!(out, "{} overlapping clock entry pairs", overlaps.len())
+}
 
-	fn parse_timestamp_from_text(&self, text: &str) -> Option<OrgTimestamp> {
-		// Handle both [timestamp] and <timestamp> formats
-		let content = if text.starts_with('[') && text.ends_with(']') {
-			&text[1..text.len() - 1]
-		} else if text.starts_with('<') && text.ends_with('>') {
-			&text[1..text.len() - 1]
-		} else {
-			text
-		};
-
-		// Parse format like: "2024-01-01 Mon 10:00" or "2023-03-29 Ср"
-		let parts: Vec<&str> = content.split_whitespace().collect();
-		if parts.len() < 2 {
-			return None;
-		}
-
-		// Parse date part (YYYY-MM-DD)
-		let date_parts: Vec<&str> = parts[0].split('-').collect();
-		if date_parts.len() != 3 {
-			return None;
+/// Lists each match as `file:line: heading`, followed by the matched content
+/// line for matches outside the title. `file` names notes without a source file.
+fn print_text_matches(
+	out: &mut impl io::Write,
+	matches: &[TextMatch],
+	file: &str,
+) -> io::Result<()> {
+	for found in matches {
+		write_location(out, found.note, file)?;
+		writeln!(out, "{}", found.note.heading_text())?;
+		if !found.in_title {
+			writeln!(out, "    {}", found.snippet)?;
 		}
-
-		let year = date_parts[0].parse::<u32>().ok()?;
-		let month = date_parts[1].parse::<u32>().ok()?;
-		let day = date_parts[2].parse::<u32>().ok()?;
-
-		let day_name = if parts.len() > 1 {
-			Some(parts[1].to_string())
-		} else {
-			None
-		};
-
-		// Parse time part if present (HH:MM)
-		let (hour, minute) = if parts.len() > 2 {
-			let time_parts: Vec<&str> = parts[2].split(':').collect();
-			if time_parts.len() == 2 {
-				let h = time_parts[0].parse::<u32>().ok();
-				let m = time_parts[1].parse::<u32>().ok();
-				(h, m)
-			} else {
-				(None, None)
-			}
-		} else {
-			(None, None)
-		};
-
-		Some(OrgTimestamp {
-			year,
-			month,
-			day,
-			hour,
-			minute,
-			day_name,
-			raw: text.to_string(),
-		})
 	}
+	Ok(())
 }
 
-impl OrgTimestamp {
-	pub fn to_date_string(&self) -> String {
-		format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+/// Writes `file:line: ` for where `note` starts, or `file: ` without its line.
+/// `file` names notes without a source file.
+fn write_location(out: &mut impl io::Write, note: &OrgNote, file: &str) -> io::Result<()> {
+	let source = note.source_file.as_deref().unwrap_or(file);
+	match note.source_line {
+		Some(line) => write!(out, "{}:{}: ", source, line),
+		None => write!(out, "{}: ", source),
 	}
+}
 
-	pub fn to_datetime_string(&self) -> String {
-		if let (Some(hour), Some(minute)) = (self.hour, self.minute) {
-			format!("{} {:02}:{:02}", self.to_date_string(), hour, minute)
-		} else {
-			self.to_date_string()
-		}
+/// Lists each finding as `file:line: heading: problem`, like [`print_text_matches`].
+fn print_lint_findings(
+	out: &mut impl io::Write,
+	findings: &[LintFinding],
+	file: &str,
+) -> io::Result<()> {
+	for finding in findings {
+		write_location(out, finding.note, file)?;
+		writeln!(
+			out,
+			"{}{}: {}",
+			heading_prefix(finding.note, 0),
+			finding.note.heading_text(),
+			finding.message
+		)?;
 	}
+	writeln!(out, "{} problems found", findings.len())
 }
 
-impl OrgClockEntry {
-	pub fn parse_duration_minutes(&self) -> Option<u32> {
-		self.duration.as_ref().and_then(|d| {
-			let parts: Vec<&str> = d.trim().split(':').collect();
-			if parts.len() == 2 {
-				let hours = parts[0].parse::<u32>().ok()?;
-				let minutes = parts[1].parse::<u32>().ok()?;
-				Some(hours * 60 + minutes)
-			} else {
-				None
+/// `notes` without their subtrees for `--shallow`: each has `children_count`, how
+/// many children it has (or `--max-depth` left out), in place of `children`.
+fn shallow_notes(notes: &[OrgNote]) -> io::Result<Vec<serde_json::Value>> {
+	notes
+		.iter()
+		.map(|note| {
+			let count = match note.children.len() {
+				0 => note.children_count.unwrap_or(0),
+				count => count,
+			};
+			let shallow = OrgNote {
+				children: Vec::new(),
+				children_count: Some(count),
+				..note.clone()
+			};
+			let mut value = serde_json::to_value(&shallow)
+				.map_err(|err| io::Error::other(format!("cannot serialize note: {}", err)))?;
+			if let Some(object) = value.as_object_mut() {
+				object.shift_remove("children");
 			}
+			Ok(value)
 		})
-	}
-
-	pub fn format_duration(&self) -> String {
-		if let Some(duration) = &self.duration {
-			format!(
-				"{} ({})",
-				duration,
-				if let Some(mins) = self.parse_duration_minutes() {
-					format!("{} minutes", mins)
-				} else {
-					"duration".to_string()
-				}
-			)
-		} else {
-			"running".to_string()
-		}
-	}
-}
-
-impl OrgLogbook {
-	pub fn total_minutes(&self) -> u32 {
-		self.clock_entries
-			.iter()
-			.filter_map(|entry| entry.parse_duration_minutes())
-			.sum()
-	}
-
-	pub fn format_total_time(&self) -> String {
-		let total_mins = self.total_minutes();
-		let hours = total_mins / 60;
-		let minutes = total_mins % 60;
-		format!("{}h {}m", hours, minutes)
-	}
+		.collect()
 }
 
-fn print_time_summary(notes: &[OrgNote]) {
-	let mut total_tracked_minutes = 0;
-	let mut completed_tasks = 0;
-	let mut active_tasks = 0;
-	let mut scheduled_tasks = 0;
-	let mut overdue_tasks = 0;
-
-	collect_time_stats(
-		notes,
-		&mut total_tracked_minutes,
-		&mut completed_tasks,
-		&mut active_tasks,
-		&mut scheduled_tasks,
-		&mut overdue_tasks,
-	);
-
-	println!("Time Tracking Summary:");
-	println!("---------------------");
-	println!(
-		"Total tracked time: {}h {}m",
-		total_tracked_minutes / 60,
-		total_tracked_minutes % 60
-	);
-	println!("Completed tasks: {}", completed_tasks);
-	println!("Active tasks: {}", active_tasks);
-	println!("Scheduled tasks: {}", scheduled_tasks);
-	if overdue_tasks > 0 {
-		println!("⚠️  Overdue tasks: {}", overdue_tasks);
+fn print_notes<T: serde::Serialize>(
+	out: &mut impl io::Write,
+	notes: &T,
+	format: &str,
+) -> io::Result<()> {
+	match format {
+		"json" => match serde_json::to_string_pretty(notes) {
+			Ok(json_output) => writeln!(out, "{}", json_output),
+			Err(err) => Err(io::Error::other(format!(
+				"cannot serialize to JSON: {}",
+				err
+			))),
+		},
+		"yaml" => match serde_yaml::to_string(notes) {
+			Ok(yaml_output) => writeln!(out, "{}", yaml_output),
+			Err(err) => Err(io::Error::other(format!(
+				"cannot serialize to YAML: {}",
+				err
+			))),
+		},
+		_ => unreachable!(),
 	}
-	println!();
 }
 
 fn collect_time_stats(
 	notes: &[OrgNote],
+	report: &ClockReport,
 	total_minutes: &mut u32,
 	completed: &mut u32,
 	active: &mut u32,
 	scheduled: &mut u32,
 	overdue: &mut u32,
 ) {
-	for note in notes {
+	let now = Local::now().naive_local();
+	for (_, note) in iter_notes(notes).skip_subtree_if(OrgNote::is_archived) {
 		if let Some(logbook) = &note.logbook {
-			*total_minutes += logbook.total_minutes();
+			*total_minutes += logbook.minutes_in(&report.range, &report.rounding);
 		}
 
 		match &note.status {
-			Some(status) if status == "DONE" => *completed += 1,
-			Some(status) if status == "TODO" || status == "IN-PROGRESS" => *active += 1,
+			Some(status) if report.keywords.is_done(status) => *completed += 1,
+			Some(status) if report.keywords.is_active(status) => *active += 1,
 			_ => {},
 		}
 
-		if let Some(planning) = &note.planning {
-			if planning.scheduled.is_some() {
-				*scheduled += 1;
-			}
-
-			// Simple overdue check (tasks with deadlines in the past)
-			if let Some(deadline) = &planning.deadline {
-				if deadline.year < 2024 || (deadline.year == 2024 && deadline.month < 12) {
-					*overdue += 1;
-				}
-			}
+		if let Some(planning) = &note.planning
+			&& planning.scheduled.is_some()
+		{
+			*scheduled += 1;
 		}
 
-		collect_time_stats(
-			&note.children,
-			total_minutes,
-			completed,
-			active,
-			scheduled,
-			overdue,
-		);
+		if report.keywords.is_overdue(note, now) {
+			*overdue += 1;
+		}
 	}
 }
 
@@ -539,7 +319,7 @@ enum Focus {
 	Right,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 enum EditMode {
 	None,
 	Status,
@@ -549,12 +329,19 @@ enum EditMode {
 	Scheduled,
 	Deadline,
 	Closed,
+	ClockEntry(usize),
+	/// Typing the heading number or outline path to jump to.
+	GotoPath,
+	/// Typing the name of the capture template to add a note from.
+	Capture,
 }
 
 struct App {
 	notes: Vec<OrgNote>,
-	flat_notes: Vec<(usize, String)>, // (index in notes tree, display string)
-	selected_note_idx: usize,
+	flat_notes: Vec<(usize, String)>, // (index in notes tree, display string) of visible notes
+	selected_note_idx: usize,         // index in notes tree
+	selected_id: Option<u64>,         // id of the selected note, which outlasts tree edits
+	collapsed: HashSet<usize>,        // tree indices of notes whose children are hidden
 	selected_field_idx: usize,
 	focus: Focus,
 	edit_mode: EditMode,
@@ -563,94 +350,649 @@ struct App {
 	file_path: String,
 	modified: bool,
 	status_message: String,
+	content_scroll: u16,
+	content_viewport: (u16, u16), // (width, height) of the content panel's inner area
+	tick_rate: Duration,          // how long the event loop waits for input before a tick
+	serialize_options: SerializeOptions,
+	status_styles: StatusStyles,
+	keymap: Keymap,
+	tag_cursor: Option<usize>, // tag selected for removal while editing labels
+	labels_before_edit: Option<(Vec<String>, bool)>, // tags and modified flag for Esc to restore
+	show_tag_totals: bool,
+	show_timeline: bool,  // the timeline replaces the metadata and content panels
+	timeline_days: usize, // days the timeline shows, starting today
+	renumber: bool,       // keep levels matching tree depth after structural edits
+	save_preview: Option<SavePreview>,
+	templates: HashMap<String, CaptureTemplate>, // capture templates from the config
+	keywords: Keywords,
+	keep_clock_durations: bool, // parser option, kept for reloading the file
+	reload_pending: bool,       // the file changed on disk while there were unsaved edits
+	disk_hash: Option<u64>,     // content_hash of the file as last read or written
+	preamble: String,           // text before the first heading, written back on save
 }
 
-impl App {
-	fn new(notes: Vec<OrgNote>, file_path: String) -> Self {
-		let flat_notes = Self::flatten_notes(&notes);
-		let mut list_state = ListState::default();
-		if !flat_notes.is_empty() {
-			list_state.select(Some(0));
-		}
+/// The diff shown before saving, see [`App::open_save_preview`].
+struct SavePreview {
+	lines: Vec<String>,
+	scroll: usize,
+}
 
-		Self {
-			notes,
-			flat_notes,
-			selected_note_idx: 0,
-			selected_field_idx: 0,
-			focus: Focus::Left,
-			edit_mode: EditMode::None,
-			edit_buffer: String::new(),
-			list_state,
-			file_path,
-			modified: false,
-			status_message: "Press Tab to switch panels, Enter to edit, q to quit".to_string(),
-		}
+const DEFAULT_TICK_RATE: Duration = Duration::from_secs(1);
+
+/// How long the watched file has to stay unchanged before it is reloaded, so an
+/// editor's burst of writes leads to one reload.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+const TAG_COLOR: Color = Color::Cyan;
+
+const DEFAULT_TIMELINE_DAYS: usize = 14;
+
+/// Colors for status keywords. Keywords without an override use the built-in
+/// colors, falling back to green for the configured done keywords and red for
+/// the rest.
+#[derive(Debug, Default)]
+struct StatusStyles {
+	overrides: HashMap<String, Color>,
+}
+
+impl StatusStyles {
+	fn set(&mut self, keyword: &str, color: Color) {
+		self.overrides.insert(keyword.to_string(), color);
 	}
 
-	fn flatten_notes(notes: &[OrgNote]) -> Vec<(usize, String)> {
-		let mut flat = Vec::new();
-		Self::flatten_recursive(notes, &mut flat, 0);
-		flat
+	fn style_for(&self, keyword: &str, keywords: &Keywords) -> Style {
+		let color = self
+			.overrides
+			.get(keyword)
+			.copied()
+			.unwrap_or(match keyword {
+				"IN-PROGRESS" => Color::Yellow,
+				"CANCELLED" => Color::DarkGray,
+				_ if keywords.is_done(keyword) => Color::Green,
+				_ => Color::Red,
+			});
+		Style::default().fg(color)
 	}
+}
 
-	fn flatten_recursive(notes: &[OrgNote], flat: &mut Vec<(usize, String)>, depth: usize) {
-		for (_idx, note) in notes.iter().enumerate() {
-			let indent = "  ".repeat(depth);
-			let status = if let Some(s) = &note.status {
-				format!("{} ", s)
-			} else {
-				String::new()
-			};
-			let display = format!(
-				"{}{}*{} {}{}",
-				indent,
-				if depth > 0 { "" } else { "" },
-				"*".repeat(note.level.saturating_sub(depth)),
-				status,
-				note.title
-			);
-			let flat_idx = flat.len(); // Use sequential index instead of tree index
-			flat.push((flat_idx, display));
-			Self::flatten_recursive(&note.children, flat, depth + 1);
+/// Indentation for the note's depth in the tree, then its own stars as org
+/// would write them, at least one even if `level` was never set.
+fn heading_prefix(note: &OrgNote, depth: usize) -> String {
+	format!("{}{} ", "  ".repeat(depth), "*".repeat(note.level.max(1)))
+}
+
+/// Parses a `--since` or `--until` date such as `2024-01-31`.
+fn parse_date_arg(value: &str) -> Result<NaiveDate, String> {
+	NaiveDate::parse_from_str(value, "%Y-%m-%d")
+		.map_err(|_| "expected a date like 2024-01-31".to_string())
+}
+
+/// Applies the `--status-color` flags, which win over the config file's colors.
+fn apply_status_colors(styles: &mut StatusStyles, matches: &ArgMatches) {
+	for (keyword, color) in matches
+		.get_many::<(String, Color)>("status-color")
+		.into_iter()
+		.flatten()
+	{
+		styles.set(keyword, *color);
+	}
+}
+
+/// Parses a `--status-color` value such as `NEXT=blue` or `TODO=#ff5555`.
+fn parse_status_color(value: &str) -> Result<(String, Color), String> {
+	let (keyword, color) = value
+		.split_once('=')
+		.ok_or_else(|| "expected KEYWORD=COLOR".to_string())?;
+	let color = color
+		.parse::<Color>()
+		.map_err(|_| format!("unknown color '{}'", color))?;
+	Ok((keyword.to_string(), color))
+}
+
+/// Something a key does outside of edit mode, named as in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Action {
+	Quit,
+	SwitchPanel,
+	Save,
+	AddNote,
+	AddChild,
+	Delete,
+	ClockIn,
+	ClockOut,
+	ScheduleNow,
+	DeadlineNow,
+	SetNow,
+	Goto,
+	MoveUp,
+	MoveDown,
+	FocusLeft,
+	FocusRight,
+	PreviewSave,
+	Capture,
+	Archive,
+	NextTodo,
+	PrevTodo,
+	NextOverdue,
+	ClockOutAll,
+	ToggleTagTotals,
+	ToggleTimeline,
+}
+
+impl Action {
+	/// Config name and default key of every action. Moving and focusing use the arrow
+	/// keys and Tab unless bound, as in the vim keymap.
+	const ALL: [(&'static str, Action, Option<&'static str>); 25] = [
+		("quit", Action::Quit, Some("q")),
+		("switch_panel", Action::SwitchPanel, Some("tab")),
+		("save", Action::Save, Some("ctrl+s")),
+		("add_note", Action::AddNote, Some("n")),
+		("add_child", Action::AddChild, Some("N")),
+		("delete", Action::Delete, Some("delete")),
+		("clock_in", Action::ClockIn, Some("i")),
+		("clock_out", Action::ClockOut, Some("o")),
+		("clock_out_all", Action::ClockOutAll, Some("ctrl+o")),
+		("schedule_now", Action::ScheduleNow, Some("k")),
+		("deadline_now", Action::DeadlineNow, Some("l")),
+		("set_now", Action::SetNow, Some("=")),
+		("goto", Action::Goto, Some("g")),
+		("preview_save", Action::PreviewSave, Some("p")),
+		("capture", Action::Capture, Some("c")),
+		("archive", Action::Archive, Some("a")),
+		("next_todo", Action::NextTodo, Some("}")),
+		("prev_todo", Action::PrevTodo, Some("{")),
+		("next_overdue", Action::NextOverdue, Some("!")),
+		("toggle_tag_totals", Action::ToggleTagTotals, Some("T")),
+		("toggle_timeline", Action::ToggleTimeline, Some("t")),
+		("move_up", Action::MoveUp, None),
+		("move_down", Action::MoveDown, None),
+		("focus_left", Action::FocusLeft, None),
+		("focus_right", Action::FocusRight, None),
+	];
+}
+
+/// Maps keys to actions. Starts from the default or vim bindings; see [`Keymap::bind`].
+#[derive(Debug)]
+struct Keymap {
+	bindings: HashMap<(KeyCode, KeyModifiers), Action>,
+}
+
+impl Default for Keymap {
+	fn default() -> Self {
+		let bindings = Action::ALL
+			.iter()
+			.filter_map(|&(_, action, key)| Some((parse_key(key?).unwrap(), action)))
+			.collect();
+		Self { bindings }
+	}
+}
+
+impl Keymap {
+	/// hjkl moves and switches panels; setting SCHEDULED and DEADLINE to now moves
+	/// from `k`/`l` to `s`/`d`.
+	fn vim() -> Self {
+		let mut keymap = Self::default();
+		for (action, key) in [
+			("schedule_now", "s"),
+			("deadline_now", "d"),
+			("move_down", "j"),
+			("move_up", "k"),
+			("focus_left", "h"),
+			("focus_right", "l"),
+		] {
+			keymap.bind(action, key).unwrap();
 		}
+		keymap
 	}
 
-	fn get_selected_note(&self) -> Option<&OrgNote> {
-		if self.flat_notes.is_empty() {
-			return None;
+	/// Binds the action named `name` to `key`, replacing its default key. A key
+	/// that already belongs to another action is refused, so no action loses its
+	/// only key.
+	fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+		let action = Action::ALL
+			.iter()
+			.find(|(action_name, _, _)| *action_name == name)
+			.map(|&(_, action, _)| action)
+			.ok_or_else(|| format!("unknown action '{}'", name))?;
+		let parsed = parse_key(key)?;
+		if let Some(&other) = self.bindings.get(&parsed)
+			&& other != action
+		{
+			let other_name = Action::ALL
+				.iter()
+				.find(|&&(_, bound, _)| bound == other)
+				.map_or("another action", |&(name, _, _)| name);
+			return Err(format!("'{}' is already bound to {}", key, other_name));
 		}
 
-		Self::find_note_by_flat_index(&self.notes, self.selected_note_idx, &mut 0)
+		self.bindings.retain(|_, bound| *bound != action);
+		self.bindings.insert(parsed, action);
+		Ok(())
 	}
 
-	fn get_selected_note_mut(&mut self) -> Option<&mut OrgNote> {
-		if self.flat_notes.is_empty() {
-			return None;
-		}
+	fn resolve(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+		self.bindings.get(&normalize_key(code, modifiers)).copied()
+	}
+}
 
-		let target_idx = self.selected_note_idx;
-		Self::find_note_by_flat_index_mut(&mut self.notes, target_idx, &mut 0)
+/// Shift is already part of a typed character, so it is dropped to make `N` match
+/// whether or not the terminal reports the modifier.
+fn normalize_key(code: KeyCode, modifiers: KeyModifiers) -> (KeyCode, KeyModifiers) {
+	match code {
+		KeyCode::Char(_) => (code, modifiers - KeyModifiers::SHIFT),
+		_ => (code, modifiers),
 	}
+}
 
-	fn find_note_by_flat_index<'a>(
-		notes: &'a [OrgNote],
-		target_idx: usize,
-		current_idx: &mut usize,
-	) -> Option<&'a OrgNote> {
-		for note in notes.iter() {
-			if *current_idx == target_idx {
-				return Some(note);
+/// Parses a key such as `i`, `N`, `ctrl+s`, `tab` or `delete`.
+fn parse_key(text: &str) -> Result<(KeyCode, KeyModifiers), String> {
+	let (modifiers, name) = match text.strip_prefix("ctrl+") {
+		Some(name) => (KeyModifiers::CONTROL, name),
+		None => (KeyModifiers::NONE, text),
+	};
+	let code = match name {
+		"tab" => KeyCode::Tab,
+		"enter" => KeyCode::Enter,
+		"delete" => KeyCode::Delete,
+		"backspace" => KeyCode::Backspace,
+		"space" => KeyCode::Char(' '),
+		_ => {
+			let mut chars = name.chars();
+			match (chars.next(), chars.next()) {
+				(Some(c), None) => KeyCode::Char(c),
+				_ => return Err(format!("unknown key '{}'", text)),
 			}
-			*current_idx += 1;
+		},
+	};
+	Ok(normalize_key(code, modifiers))
+}
 
-			if let Some(found) =
-				Self::find_note_by_flat_index(&note.children, target_idx, current_idx)
+/// The TUI settings file, `~/.config/rorg/config.toml` unless `--config` is given:
+///
+/// ```toml
+/// vim = true
+///
+/// [keys]
+/// clock_in = "I"
+/// save = "ctrl+w"
+///
+/// [status_colors]
+/// NEXT = "blue"
+///
+/// [keywords]
+/// active = ["TODO", "NEXT", "WAITING"]
+/// done = ["DONE", "CANCELLED"]
+///
+/// [templates.task]
+/// status = "TODO"
+/// scheduled = "today"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Config {
+	/// Start from [`Keymap::vim`] instead of the default bindings.
+	vim: bool,
+	keys: HashMap<String, String>,
+	status_colors: HashMap<String, String>,
+	/// Replaces the default [`Keywords`] when present.
+	keywords: Option<Keywords>,
+	templates: HashMap<String, CaptureTemplate>,
+}
+
+/// The shape of a note added with the capture key or `--capture NAME`, from the
+/// config's `[templates.NAME]` table:
+///
+/// ```toml
+/// [templates.errand]
+/// title = "Errand"
+/// status = "TODO"
+/// tags = ["home"]
+/// scheduled = "today"
+/// parent = "Inbox"
+/// save = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct CaptureTemplate {
+	/// Heading text, `New Note` when unset.
+	title: String,
+	status: Option<String>,
+	priority: Option<char>,
+	tags: Vec<String>,
+	content: String,
+	/// `now`, a timestamp such as `<2024-01-20 Sat>`, or a relative date such as
+	/// `today`, `+3d` or `fri`.
+	scheduled: Option<String>,
+	/// Like `scheduled`.
+	deadline: Option<String>,
+	/// Heading to add the note under, as a goto path such as `Projects/Garden`.
+	/// The note goes at the end of the file when unset.
+	parent: Option<String>,
+	/// Save the file right after capturing in the TUI.
+	save: bool,
+}
+
+impl CaptureTemplate {
+	/// The note this template describes, with `today` and `now` taken from `now`.
+	fn instantiate(&self, now: NaiveDateTime) -> Result<OrgNote, String> {
+		let title = if self.title.is_empty() {
+			"New Note"
+		} else {
+			&self.title
+		};
+		let mut builder = OrgNote::builder(1, title).content(self.content.clone());
+		if let Some(status) = &self.status {
+			builder = builder.status(status.clone());
+		}
+		if let Some(priority) = self.priority {
+			builder = builder.priority(priority);
+		}
+		for tag in &self.tags {
+			builder = builder.tag(tag.clone());
+		}
+		if let Some(value) = &self.scheduled {
+			builder = builder.scheduled(template_timestamp(value, now)?);
+		}
+		if let Some(value) = &self.deadline {
+			builder = builder.deadline(template_timestamp(value, now)?);
+		}
+		Ok(builder.build())
+	}
+}
+
+fn template_timestamp(value: &str, now: NaiveDateTime) -> Result<OrgTimestamp, String> {
+	match value {
+		"now" => Ok(OrgTimestamp::from_datetime(now, TimestampKind::Active)),
+		_ => parse_timestamp_input(value, TimestampKind::Active, now)
+			.ok_or_else(|| format!("invalid timestamp '{}'", value)),
+	}
+}
+
+/// A timestamp as typed, such as `<2024-01-20 Sat>`, or a date relative to `now`
+/// such as `tomorrow`, `+3d` or `fri` (see [`parse_relative_date`]), which gets
+/// the brackets of `kind`.
+fn parse_timestamp_input(
+	input: &str,
+	kind: TimestampKind,
+	now: NaiveDateTime,
+) -> Option<OrgTimestamp> {
+	let input = input.trim();
+	input
+		.parse()
+		.ok()
+		.or_else(|| parse_relative_date(input, now).map(|date| OrgTimestamp::from_date(date, kind)))
+}
+
+/// Adds the note `template` describes as the last child of its parent, or at the
+/// end of the top level. Returns the flat index of the new note.
+fn capture_note(
+	notes: &mut Vec<OrgNote>,
+	template: &CaptureTemplate,
+	now: NaiveDateTime,
+) -> Result<usize, String> {
+	let note = template.instantiate(now)?;
+	match &template.parent {
+		Some(parent) => resolve_goto_target(notes, parent)
+			.and_then(|idx| App::insert_child(notes, idx, note))
+			.ok_or_else(|| format!("No heading '{}' to capture under", parent)),
+		None => {
+			notes.push(note);
+			Ok(iter_notes(notes).count() - 1)
+		},
+	}
+}
+
+fn default_config_path() -> Option<PathBuf> {
+	let config_home = std::env::var_os("XDG_CONFIG_HOME")
+		.map(PathBuf::from)
+		.or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+	Some(config_home.join("rorg").join("config.toml"))
+}
+
+/// Applies the config in `text` on top of the defaults. Problems are returned as warnings
+/// and the affected settings keep their defaults.
+fn load_config(
+	text: &str,
+	keymap: &mut Keymap,
+	styles: &mut StatusStyles,
+	templates: &mut HashMap<String, CaptureTemplate>,
+	keywords: &mut Keywords,
+) -> Vec<String> {
+	let config: Config = match toml::from_str(text) {
+		Ok(config) => config,
+		Err(err) => return vec![format!("invalid config, using defaults: {}", err)],
+	};
+
+	if config.vim {
+		*keymap = Keymap::vim();
+	}
+
+	let mut warnings = Vec::new();
+	let mut keys: Vec<_> = config.keys.iter().collect();
+	keys.sort();
+	for (action, key) in keys {
+		if let Err(err) = keymap.bind(action, key) {
+			warnings.push(format!("keys.{}: {}", action, err));
+		}
+	}
+	for (keyword, color) in &config.status_colors {
+		match color.parse::<Color>() {
+			Ok(color) => styles.set(keyword, color),
+			Err(_) => warnings.push(format!(
+				"status_colors.{}: unknown color '{}'",
+				keyword, color
+			)),
+		}
+	}
+	templates.extend(config.templates);
+	if let Some(config_keywords) = config.keywords {
+		*keywords = config_keywords;
+	}
+	warnings
+}
+
+/// What woke the event loop up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LoopEvent {
+	Input,
+	Tick,
+}
+
+impl App {
+	fn new(notes: Vec<OrgNote>, file_path: String) -> Self {
+		let flat_notes = Self::flatten_notes(&notes, &HashSet::new());
+		let selected_id = notes.first().map(|note| note.id);
+		let mut list_state = ListState::default();
+		let status_message = if flat_notes.is_empty() {
+			"No notes yet - press n to add one, q to quit"
+		} else {
+			list_state.select(Some(0));
+			"Press Tab to switch panels, Enter to edit, q to quit"
+		};
+
+		Self {
+			notes,
+			flat_notes,
+			selected_id,
+			selected_note_idx: 0,
+			collapsed: HashSet::new(),
+			selected_field_idx: 0,
+			focus: Focus::Left,
+			edit_mode: EditMode::None,
+			edit_buffer: String::new(),
+			list_state,
+			file_path,
+			modified: false,
+			status_message: status_message.to_string(),
+			content_scroll: 0,
+			content_viewport: (0, 0),
+			tick_rate: DEFAULT_TICK_RATE,
+			serialize_options: SerializeOptions::default(),
+			status_styles: StatusStyles::default(),
+			keymap: Keymap::default(),
+			tag_cursor: None,
+			labels_before_edit: None,
+			show_tag_totals: false,
+			show_timeline: false,
+			timeline_days: DEFAULT_TIMELINE_DAYS,
+			renumber: false,
+			save_preview: None,
+			templates: HashMap::new(),
+			keywords: Keywords::default(),
+			keep_clock_durations: false,
+			reload_pending: false,
+			disk_hash: None,
+			preamble: String::new(),
+		}
+	}
+
+	/// Whether the selected note has a clock entry without an end.
+	fn selected_clock_running(&self) -> bool {
+		self.get_selected_note()
+			.and_then(|note| note.logbook.as_ref())
+			.is_some_and(|logbook| logbook.clock_entries.iter().any(|e| e.end.is_none()))
+	}
+
+	/// Lists the visible notes with their tree index, skipping the children of `collapsed` notes.
+	fn flatten_notes(notes: &[OrgNote], collapsed: &HashSet<usize>) -> Vec<(usize, String)> {
+		let mut flat = Vec::new();
+		Self::flatten_recursive(notes, collapsed, &mut flat, 0, &mut 0);
+		flat
+	}
+
+	fn flatten_recursive(
+		notes: &[OrgNote],
+		collapsed: &HashSet<usize>,
+		flat: &mut Vec<(usize, String)>,
+		depth: usize,
+		tree_idx: &mut usize,
+	) {
+		for note in notes.iter() {
+			let folded = collapsed.contains(tree_idx) && !note.children.is_empty();
+			let display = format!(
+				"{}{}{}",
+				heading_prefix(note, depth),
+				note.heading_text(),
+				if folded { " ..." } else { "" }
+			);
+			flat.push((*tree_idx, display));
+
+			if folded {
+				*tree_idx += Self::subtree_size(note);
+			} else {
+				*tree_idx += 1;
+				Self::flatten_recursive(&note.children, collapsed, flat, depth + 1, tree_idx);
+			}
+		}
+	}
+
+	fn subtree_size(note: &OrgNote) -> usize {
+		1 + iter_notes(&note.children).count()
+	}
+
+	/// Rebuilds the visible list, finding the selected note by id after the tree changed
+	/// and moving the selection to its nearest visible ancestor if it was folded away.
+	fn refresh_flat_notes(&mut self) {
+		self.flat_notes = Self::flatten_notes(&self.notes, &self.collapsed);
+
+		match self
+			.selected_id
+			.and_then(|id| iter_notes(&self.notes).position(|(_, note)| note.id == id))
+		{
+			Some(idx) => self.selected_note_idx = idx,
+			None => {
+				let total = iter_notes(&self.notes).count();
+				self.selected_note_idx = self.selected_note_idx.min(total.saturating_sub(1));
+			},
+		}
+
+		if !self.is_visible(self.selected_note_idx) {
+			let mut path = Vec::new();
+			Self::find_path_by_flat_index(&self.notes, self.selected_note_idx, &mut 0, &mut path);
+			if let Some(idx) = (1..path.len())
+				.rev()
+				.map(|len| Self::flat_index_of_path(&self.notes, &path[..len]))
+				.find(|&idx| self.is_visible(idx))
 			{
-				return Some(found);
+				self.selected_note_idx = idx;
+				self.selected_field_idx = 0;
+				self.content_scroll = 0;
 			}
 		}
-		None
+
+		let position = self
+			.flat_notes
+			.iter()
+			.position(|(idx, _)| *idx == self.selected_note_idx);
+		self.list_state.select(position);
+		self.selected_id = self.note_id_at(self.selected_note_idx);
+	}
+
+	fn note_id_at(&self, tree_idx: usize) -> Option<u64> {
+		iter_notes(&self.notes)
+			.nth(tree_idx)
+			.map(|(_, note)| note.id)
+	}
+
+	fn is_visible(&self, tree_idx: usize) -> bool {
+		self.flat_notes.iter().any(|(idx, _)| *idx == tree_idx)
+	}
+
+	/// Unfolds every ancestor of the note at `tree_idx`.
+	fn reveal(&mut self, tree_idx: usize) {
+		let mut path = Vec::new();
+		if Self::find_path_by_flat_index(&self.notes, tree_idx, &mut 0, &mut path) {
+			for len in 1..path.len() {
+				self.collapsed
+					.remove(&Self::flat_index_of_path(&self.notes, &path[..len]));
+			}
+		}
+	}
+
+	fn toggle_fold(&mut self) {
+		let has_children = self
+			.get_selected_note()
+			.is_some_and(|note| !note.children.is_empty());
+		if has_children && !self.collapsed.remove(&self.selected_note_idx) {
+			self.collapsed.insert(self.selected_note_idx);
+		}
+		self.refresh_flat_notes();
+	}
+
+	/// Folds every top-level note that has children.
+	fn collapse_all(&mut self) {
+		let mut tree_idx = 0;
+		for note in &self.notes {
+			if !note.children.is_empty() {
+				self.collapsed.insert(tree_idx);
+			}
+			tree_idx += Self::subtree_size(note);
+		}
+		self.refresh_flat_notes();
+	}
+
+	fn expand_all(&mut self) {
+		self.collapsed.clear();
+		self.refresh_flat_notes();
+	}
+
+	fn get_selected_note(&self) -> Option<&OrgNote> {
+		if self.flat_notes.is_empty() {
+			return None;
+		}
+
+		iter_notes(&self.notes)
+			.nth(self.selected_note_idx)
+			.map(|(_, note)| note)
+	}
+
+	fn get_selected_note_mut(&mut self) -> Option<&mut OrgNote> {
+		if self.flat_notes.is_empty() {
+			return None;
+		}
+
+		let target_idx = self.selected_note_idx;
+		Self::find_note_by_flat_index_mut(&mut self.notes, target_idx, &mut 0)
 	}
 
 	fn find_note_by_flat_index_mut<'a>(
@@ -673,35 +1015,276 @@ impl App {
 		None
 	}
 
-	fn add_note(&mut self) {
+	fn scroll_content(&mut self, delta: i32) {
+		let (width, height) = self.content_viewport;
+		let content_height = self
+			.get_selected_note()
+			.map(|note| wrapped_line_count(&note.content, width))
+			.unwrap_or(0);
+		let offset = (self.content_scroll as i32 + delta).clamp(0, u16::MAX as i32) as u16;
+		self.content_scroll = clamp_scroll(offset, content_height, height);
+	}
+
+	fn add_note(&mut self, as_child: bool) {
 		let new_note = OrgNote::new(1, "New Note".to_string());
-		self.notes.push(new_note);
-		self.flat_notes = Self::flatten_notes(&self.notes);
-		self.selected_note_idx = self.flat_notes.len() - 1;
-		self.list_state.select(Some(self.selected_note_idx));
+
+		let new_idx = if self.flat_notes.is_empty() {
+			self.notes.push(new_note);
+			Some(self.notes.len() - 1)
+		} else if as_child {
+			Self::insert_child(&mut self.notes, self.selected_note_idx, new_note)
+		} else {
+			Self::insert_sibling(&mut self.notes, self.selected_note_idx, new_note)
+		};
+
+		if let Some(idx) = new_idx {
+			self.select_inserted(idx);
+		}
+	}
+
+	/// Adds a note from the capture template called `name`, saving afterwards if
+	/// the template says so.
+	fn capture(&mut self, name: &str) {
+		let Some(template) = self.templates.get(name.trim()).cloned() else {
+			self.status_message = format!("No capture template '{}'", name.trim());
+			return;
+		};
+		match capture_note(&mut self.notes, &template, Local::now().naive_local()) {
+			Ok(idx) => {
+				self.select_inserted(idx);
+				self.status_message = format!("Captured '{}'", name.trim());
+				if template.save {
+					self.save();
+				}
+			},
+			Err(err) => self.status_message = err,
+		}
+	}
+
+	/// Opens the capture prompt, or captures right away when there is only one template.
+	fn start_capture(&mut self) {
+		match self.templates.len() {
+			0 => self.status_message = "No capture templates in the config".to_string(),
+			1 => {
+				let name = self.templates.keys().next().unwrap().clone();
+				self.capture(&name);
+			},
+			_ => {
+				self.edit_mode = EditMode::Capture;
+				self.edit_buffer.clear();
+			},
+		}
+	}
+
+	/// Selects the note just inserted at flat index `idx`, shifting folds after it.
+	fn select_inserted(&mut self, idx: usize) {
+		if self.renumber {
+			renumber_levels(&mut self.notes);
+		}
+		self.collapsed = self
+			.collapsed
+			.iter()
+			.map(|&folded| if folded >= idx { folded + 1 } else { folded })
+			.collect();
+		self.reveal(idx);
+		self.selected_id = self.note_id_at(idx);
+		self.selected_field_idx = 0;
+		self.content_scroll = 0;
+		self.refresh_flat_notes();
 		self.modified = true;
 	}
 
-	fn delete_selected_note(&mut self) {
-		if !self.flat_notes.is_empty() {
-			// Find and remove the note from the tree structure
-			Self::remove_note_by_flat_index(&mut self.notes, self.selected_note_idx, &mut 0);
-			self.flat_notes = Self::flatten_notes(&self.notes);
+	/// Inserts `note` right after the note at `target_idx` (and its subtree), at the same level.
+	/// Returns the flat index of the inserted note.
+	fn insert_sibling(
+		notes: &mut Vec<OrgNote>,
+		target_idx: usize,
+		mut note: OrgNote,
+	) -> Option<usize> {
+		let mut path = Vec::new();
+		if !Self::find_path_by_flat_index(notes, target_idx, &mut 0, &mut path) {
+			return None;
+		}
+
+		let (&position, parent_path) = path.split_last()?;
+		let siblings = Self::children_at_path_mut(notes, parent_path);
+		note.level = siblings[position].level;
+		siblings.insert(position + 1, note);
+
+		*path.last_mut()? += 1;
+		Some(Self::flat_index_of_path(notes, &path))
+	}
+
+	/// Appends `note` as the last child of the note at `target_idx`, one level deeper.
+	/// Returns the flat index of the inserted note.
+	fn insert_child(
+		notes: &mut Vec<OrgNote>,
+		target_idx: usize,
+		mut note: OrgNote,
+	) -> Option<usize> {
+		let mut path = Vec::new();
+		if !Self::find_path_by_flat_index(notes, target_idx, &mut 0, &mut path) {
+			return None;
+		}
+
+		let (&position, parent_path) = path.split_last()?;
+		let parent = &mut Self::children_at_path_mut(notes, parent_path)[position];
+		note.level = parent.level + 1;
+		parent.children.push(note);
+
+		path.push(parent.children.len() - 1);
+		Some(Self::flat_index_of_path(notes, &path))
+	}
 
-			if self.selected_note_idx >= self.flat_notes.len() && !self.flat_notes.is_empty() {
-				self.selected_note_idx = self.flat_notes.len() - 1;
+	/// Records in `path` the child indices leading from `notes` to the note at `target_idx`.
+	fn find_path_by_flat_index(
+		notes: &[OrgNote],
+		target_idx: usize,
+		current_idx: &mut usize,
+		path: &mut Vec<usize>,
+	) -> bool {
+		for (i, note) in notes.iter().enumerate() {
+			path.push(i);
+			if *current_idx == target_idx {
+				return true;
 			}
+			*current_idx += 1;
 
-			if self.flat_notes.is_empty() {
-				self.list_state.select(None);
-			} else {
-				self.list_state.select(Some(self.selected_note_idx));
+			if Self::find_path_by_flat_index(&note.children, target_idx, current_idx, path) {
+				return true;
+			}
+			path.pop();
+		}
+		false
+	}
+
+	/// Titles from the top level down to the note at `tree_idx`.
+	fn title_path(&self, tree_idx: usize) -> Vec<String> {
+		let mut path = Vec::new();
+		Self::find_path_by_flat_index(&self.notes, tree_idx, &mut 0, &mut path);
+		let mut siblings = &self.notes;
+		path.iter()
+			.map(|&i| {
+				let note = &siblings[i];
+				siblings = &note.children;
+				note.title.clone()
+			})
+			.collect()
+	}
+
+	fn children_at_path_mut<'a>(
+		notes: &'a mut Vec<OrgNote>,
+		path: &[usize],
+	) -> &'a mut Vec<OrgNote> {
+		let mut current = notes;
+		for &i in path {
+			current = &mut current[i].children;
+		}
+		current
+	}
+
+	fn flat_index_of_path(notes: &[OrgNote], path: &[usize]) -> usize {
+		let mut idx = 0;
+		let mut current = notes;
+		for (depth, &i) in path.iter().enumerate() {
+			idx += current[..i].iter().map(Self::subtree_size).sum::<usize>();
+			if depth + 1 < path.len() {
+				idx += 1;
+				current = &current[i].children;
 			}
+		}
+		idx
+	}
+
+	fn select_note(&mut self, idx: usize) {
+		self.reveal(idx);
+		self.selected_id = self.note_id_at(idx);
+		self.selected_field_idx = 0;
+		self.content_scroll = 0;
+		self.refresh_flat_notes();
+		self.status_message = get_field_name_at_index(self, self.selected_field_idx);
+	}
+
+	/// Selects the note `find` returns when searching from the selected one, or
+	/// shows `not_found`.
+	fn jump_to_next(
+		&mut self,
+		find: fn(&[OrgNote], &Keywords, u64) -> Option<u64>,
+		not_found: &str,
+	) {
+		let from_id = self.selected_id.unwrap_or_default();
+		match find(&self.notes, &self.keywords, from_id)
+			.and_then(|id| iter_notes(&self.notes).position(|(_, note)| note.id == id))
+		{
+			Some(idx) => self.select_note(idx),
+			None => self.status_message = not_found.to_string(),
+		}
+	}
+
+	fn delete_selected_note(&mut self) {
+		if let Some(removed) = self.get_selected_note().map(Self::subtree_size) {
+			// Select the note after the removed subtree, or else the one before it
+			let idx = self.selected_note_idx;
+			self.selected_id = self
+				.note_id_at(idx + removed)
+				.or_else(|| self.note_id_at(idx.checked_sub(1)?));
+
+			// Find and remove the note from the tree structure
+			Self::remove_note_by_flat_index(&mut self.notes, idx, &mut 0);
+			self.collapsed = self
+				.collapsed
+				.iter()
+				.filter(|&&folded| folded < idx || folded >= idx + removed)
+				.map(|&folded| {
+					if folded > idx {
+						folded - removed
+					} else {
+						folded
+					}
+				})
+				.collect();
+
+			self.refresh_flat_notes();
 
 			self.modified = true;
 		}
 	}
 
+	/// Moves the selected subtree under the archive heading, see
+	/// [`archive_to_heading`], and selects the note after it or else the one before.
+	fn archive_selected_note(&mut self) {
+		let idx = self.selected_note_idx;
+		let Some((id, removed)) = self
+			.get_selected_note()
+			.map(|note| (note.id, Self::subtree_size(note)))
+		else {
+			return;
+		};
+		let next_id = self
+			.note_id_at(idx + removed)
+			.or_else(|| self.note_id_at(idx.checked_sub(1)?));
+		let collapsed_ids: HashSet<u64> = self
+			.collapsed
+			.iter()
+			.filter_map(|&folded| self.note_id_at(folded))
+			.collect();
+
+		match archive_to_heading(&mut self.notes, id) {
+			Ok(()) => {
+				self.selected_id = next_id;
+				self.collapsed = iter_notes(&self.notes)
+					.enumerate()
+					.filter(|(_, (_, note))| collapsed_ids.contains(&note.id))
+					.map(|(idx, _)| idx)
+					.collect();
+				self.refresh_flat_notes();
+				self.modified = true;
+				self.status_message = "Archived".to_string();
+			},
+			Err(err) => self.status_message = format!("Cannot archive: {}", err),
+		}
+	}
+
 	fn remove_note_by_flat_index(
 		notes: &mut Vec<OrgNote>,
 		target_idx: usize,
@@ -725,22 +1308,13 @@ impl App {
 
 	fn clock_in(&mut self) {
 		if let Some(note) = self.get_selected_note_mut() {
-			let now = Local::now();
-			let timestamp = OrgTimestamp {
-				year: now.year() as u32,
-				month: now.month(),
-				day: now.day(),
-				hour: Some(now.hour()),
-				minute: Some(now.minute()),
-				day_name: Some(now.format("%a").to_string()),
-				raw: now.format("[%Y-%m-%d %a %H:%M]").to_string(),
-			};
-
+			let timestamp =
+				OrgTimestamp::from_datetime(Local::now().naive_local(), TimestampKind::Inactive);
 			let clock_entry = OrgClockEntry {
+				raw: format!("CLOCK: {}", timestamp.raw),
 				start: timestamp,
 				end: None,
 				duration: None,
-				raw: now.format("CLOCK: [%Y-%m-%d %a %H:%M]").to_string(),
 			};
 
 			if let Some(logbook) = &mut note.logbook {
@@ -749,6 +1323,7 @@ impl App {
 				note.logbook = Some(OrgLogbook {
 					clock_entries: vec![clock_entry],
 					raw_content: Vec::new(),
+					inline: false,
 				});
 			}
 
@@ -757,70 +1332,52 @@ impl App {
 	}
 
 	fn clock_out(&mut self) {
-		if let Some(note) = self.get_selected_note_mut() {
-			if let Some(logbook) = &mut note.logbook {
-				// Find the oldest running clock entry
-				for entry in &mut logbook.clock_entries {
-					if entry.end.is_none() {
-						let now = Local::now();
-						let end_timestamp = OrgTimestamp {
-							year: now.year() as u32,
-							month: now.month(),
-							day: now.day(),
-							hour: Some(now.hour()),
-							minute: Some(now.minute()),
-							day_name: Some(now.format("%a").to_string()),
-							raw: now.format("[%Y-%m-%d %a %H:%M]").to_string(),
-						};
-
-						entry.end = Some(end_timestamp);
-						// Calculate duration (simplified)
-						let start_time =
-							entry.start.hour.unwrap_or(0) * 60 + entry.start.minute.unwrap_or(0);
-						let end_time = now.hour() * 60 + now.minute();
-						let duration_mins = if end_time >= start_time {
-							end_time - start_time
-						} else {
-							(24 * 60) - start_time + end_time
-						};
-
-						entry.duration =
-							Some(format!("{}:{:02}", duration_mins / 60, duration_mins % 60));
-						entry.raw = format!(
-							"{}--{} =>  {}",
-							entry.start.raw,
-							now.format("[%Y-%m-%d %a %H:%M]"),
-							entry.duration.as_ref().unwrap()
-						);
-
-						self.modified = true;
-						break;
-					}
-				}
-			}
+		// Find the oldest running clock entry
+		let running = self
+			.get_selected_note()
+			.and_then(|note| note.logbook.as_ref())
+			.and_then(|logbook| logbook.clock_entries.iter().position(|e| e.end.is_none()));
+
+		if let Some(entry_idx) = running {
+			self.clock_out_entry(entry_idx);
+		}
+	}
+
+	/// Closes the running clock entry at `entry_idx` of the selected note at the current time.
+	/// Returns false if there is no such entry or it is already closed.
+	fn clock_out_entry(&mut self, entry_idx: usize) -> bool {
+		let Some(entry) = self
+			.get_selected_note_mut()
+			.and_then(|note| note.logbook.as_mut())
+			.and_then(|logbook| logbook.clock_entries.get_mut(entry_idx))
+		else {
+			return false;
+		};
+
+		if !entry.clock_out(Local::now().naive_local()) {
+			return false;
 		}
+		self.modified = true;
+		true
+	}
+
+	/// Closes every running clock in the file at the current time.
+	fn clock_out_everywhere(&mut self) {
+		let closed = clock_out_all(&mut self.notes, Local::now().naive_local());
+		if closed > 0 {
+			self.modified = true;
+		}
+		self.status_message = clocked_out_message(closed);
 	}
 
 	fn set_current_time(&mut self, field: &str) {
 		if let Some(note) = self.get_selected_note_mut() {
-			let now = Local::now();
-			let timestamp = OrgTimestamp {
-				year: now.year() as u32,
-				month: now.month(),
-				day: now.day(),
-				hour: Some(now.hour()),
-				minute: Some(now.minute()),
-				day_name: Some(now.format("%a").to_string()),
-				raw: format!(
-					"<{}-{:02}-{:02} {} {:02}:{:02}>",
-					now.year(),
-					now.month(),
-					now.day(),
-					now.format("%a"),
-					now.hour(),
-					now.minute(),
-				),
+			let kind = if field == "closed" {
+				TimestampKind::Inactive
+			} else {
+				TimestampKind::Active
 			};
+			let timestamp = OrgTimestamp::from_datetime(Local::now().naive_local(), kind);
 
 			if note.planning.is_none() {
 				note.planning = Some(OrgPlanning {
@@ -843,88 +1400,168 @@ impl App {
 		}
 	}
 
-	fn save_to_file(&self) -> io::Result<()> {
-		let content = self.serialize_to_org_format();
-		fs::write(&self.file_path, content)
+	/// The file as saving would write it.
+	fn serialized(&self) -> String {
+		rorg::serialize_document(&self.preamble, &self.notes, &self.serialize_options)
 	}
 
-	fn serialize_to_org_format(&self) -> String {
-		let mut output = String::new();
+	fn save_to_file(&mut self) -> io::Result<()> {
+		let content = self.serialized();
+		fs::write(&self.file_path, &content)?;
+		self.disk_hash = Some(content_hash(&content));
+		Ok(())
+	}
 
-		for note in &self.notes {
-			Self::serialize_note(&mut output, note);
+	/// Replaces the notes with those parsed from `content`, keeping the selected
+	/// note if it still exists and dropping unsaved edits.
+	fn reload(&mut self, content: &str) {
+		// Parsing gives every note a new id, so the selection is found again by
+		// its titles, or else stays at the same position
+		let selected_path = self.title_path(self.selected_note_idx);
+		let mut notes = OrgParser::new(content)
+			.preserve_clock_durations(self.keep_clock_durations)
+			.parse();
+		if self.renumber {
+			renumber_levels(&mut notes);
 		}
-
-		output
+		self.notes = notes;
+		self.preamble = rorg::preamble(content).to_string();
+		self.collapsed.clear();
+		self.edit_mode = EditMode::None;
+		self.edit_buffer.clear();
+		self.save_preview = None;
+		self.modified = false;
+		self.reload_pending = false;
+		self.disk_hash = Some(content_hash(content));
+		let titles: Vec<&str> = selected_path.iter().map(String::as_str).collect();
+		self.selected_id = find_by_path(&self.notes, &titles).map(|note| note.id);
+		self.refresh_flat_notes();
+		self.selected_id = self.note_id_at(self.selected_note_idx);
+		self.status_message = format!("Reloaded {}", self.file_path);
 	}
 
-	fn serialize_note(output: &mut String, note: &OrgNote) {
-		// Write heading
-		let stars = "*".repeat(note.level);
-		let status = if let Some(s) = &note.status {
-			format!(" {}", s)
-		} else {
-			String::new()
-		};
-		let labels = if !note.labels.is_empty() {
-			format!(" :{}:", note.labels.join(":"))
-		} else {
-			String::new()
+	/// Acts on the file having changed on disk; see [`reload_decision`].
+	fn file_changed(&mut self) {
+		let Ok(content) = fs::read_to_string(&self.file_path) else {
+			return;
 		};
-
-		output.push_str(&format!("{}{} {}{}\n", stars, status, note.title, labels));
-
-		// Write planning
-		if let Some(planning) = &note.planning {
-			if let Some(scheduled) = &planning.scheduled {
-				output.push_str(&format!("SCHEDULED: {}\n", scheduled.raw));
-			}
-			if let Some(deadline) = &planning.deadline {
-				output.push_str(&format!("DEADLINE: {}\n", deadline.raw));
-			}
-			if let Some(closed) = &planning.closed {
-				output.push_str(&format!("CLOSED: {}\n", closed.raw));
-			}
+		let in_memory = self.serialized();
+		match reload_decision(&content, &in_memory, self.modified) {
+			ReloadDecision::Ignore => self.disk_hash = Some(content_hash(&content)),
+			ReloadDecision::Reload => self.reload(&content),
+			ReloadDecision::Ask => {
+				self.reload_pending = true;
+				self.status_message = format!(
+					"{} changed on disk: r reloads and drops your edits, any other key keeps them",
+					self.file_path
+				);
+			},
 		}
+	}
 
-		// Write logbook
-		if let Some(logbook) = &note.logbook {
-			if !logbook.clock_entries.is_empty() {
-				output.push_str(":LOGBOOK:\n");
-				for entry in &logbook.clock_entries {
-					output.push_str(&format!("{}\n", entry.raw));
-				}
-				output.push_str(":END:\n");
+	/// Saves the file, unless it changed on disk since it was loaded or last
+	/// saved. Then the save preview shows what saving would overwrite instead.
+	fn save(&mut self) {
+		let on_disk = fs::read_to_string(&self.file_path).ok();
+		if changed_on_disk(self.disk_hash, on_disk.as_deref()) {
+			self.open_save_preview();
+			if self.save_preview.is_some() {
+				self.status_message = format!(
+					"{} changed on disk since it was loaded: Enter overwrites it, Esc cancels",
+					self.file_path
+				);
+				return;
 			}
 		}
-
-		// Write content
-		if !note.content.trim().is_empty() {
-			output.push_str(&format!("{}\n", note.content));
+		if let Err(err) = self.save_anyway() {
+			self.status_message = format!("Error saving {}: {}", self.file_path, err);
 		}
+	}
 
-		output.push('\n');
+	/// Saves the file without checking it for changes made outside the TUI.
+	fn save_anyway(&mut self) -> io::Result<()> {
+		self.save_to_file()?;
+		self.modified = false;
+		Ok(())
+	}
 
-		// Write children
-		for child in &note.children {
-			Self::serialize_note(output, child);
+	/// Shows what saving would change in the file on disk, or says that it would not.
+	fn open_save_preview(&mut self) {
+		let original = fs::read_to_string(&self.file_path).unwrap_or_default();
+		let updated = self.serialized();
+		let diff = save_diff(&original, &updated, &self.file_path);
+		if diff.is_empty() {
+			self.status_message = "Saving would not change the file".to_string();
+		} else {
+			self.save_preview = Some(SavePreview {
+				lines: diff.lines().map(str::to_string).collect(),
+				scroll: 0,
+			});
+			self.status_message = "Save preview: Enter saves, Esc cancels".to_string();
 		}
 	}
 }
 
-fn run_tui(notes: Vec<OrgNote>, file_path: String) -> Result<(), Box<dyn std::error::Error>> {
-	// Setup terminal
-	enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
-	let mut stdout = io::stdout();
-	execute!(stdout, EnterAlternateScreen, EnableMouseCapture)
-		.map_err(|e| format!("Failed to setup terminal: {}", e))?;
+/// A unified diff from `original` to `updated`, empty if they are the same.
+fn save_diff(original: &str, updated: &str, path: &str) -> String {
+	TextDiff::from_lines(original, updated)
+		.unified_diff()
+		.header(path, path)
+		.to_string()
+}
 
-	let backend = CrosstermBackend::new(stdout);
-	let mut terminal =
-		Terminal::new(backend).map_err(|e| format!("Failed to create terminal: {}", e))?;
+fn handle_save_preview_input(app: &mut App, code: KeyCode, action: Option<Action>) {
+	let Some(preview) = &mut app.save_preview else {
+		return;
+	};
+
+	match (code, action) {
+		(KeyCode::Enter, _) | (_, Some(Action::Save)) => {
+			app.save_preview = None;
+			app.status_message = match app.save_anyway() {
+				Ok(()) => "Saved".to_string(),
+				Err(err) => format!("Error saving {}: {}", app.file_path, err),
+			};
+		},
+		(KeyCode::Esc, _) | (_, Some(Action::Quit)) => {
+			app.save_preview = None;
+			app.status_message = "Save cancelled".to_string();
+		},
+		(KeyCode::Up, _) | (_, Some(Action::MoveUp)) => {
+			preview.scroll = preview.scroll.saturating_sub(1);
+		},
+		(KeyCode::Down, _) | (_, Some(Action::MoveDown)) => {
+			preview.scroll = (preview.scroll + 1).min(preview.lines.len().saturating_sub(1));
+		},
+		_ => {},
+	}
+}
+
+fn handle_reload_prompt(app: &mut App, code: KeyCode) {
+	app.reload_pending = false;
+	match (code, fs::read_to_string(&app.file_path)) {
+		(KeyCode::Char('r' | 'R'), Ok(content)) => app.reload(&content),
+		(KeyCode::Char('r' | 'R'), Err(err)) => {
+			app.status_message = format!("Error reading {}: {}", app.file_path, err)
+		},
+		_ => {
+			app.status_message = "Kept your edits; saving shows what it would overwrite".to_string()
+		},
+	}
+}
 
-	let mut app = App::new(notes, file_path);
-	let res = run_app(&mut terminal, &mut app);
+fn run_tui(mut app: App, watch: Option<FileWatch>) -> Result<(), Box<dyn std::error::Error>> {
+	// Setup terminal
+	enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
+	let mut stdout = io::stdout();
+	execute!(stdout, EnterAlternateScreen, EnableMouseCapture)
+		.map_err(|e| format!("Failed to setup terminal: {}", e))?;
+
+	let backend = CrosstermBackend::new(stdout);
+	let mut terminal =
+		Terminal::new(backend).map_err(|e| format!("Failed to create terminal: {}", e))?;
+
+	let res = run_app(&mut terminal, &mut app, watch);
 
 	// Cleanup terminal
 	let _ = disable_raw_mode();
@@ -938,62 +1575,115 @@ fn run_tui(notes: Vec<OrgNote>, file_path: String) -> Result<(), Box<dyn std::er
 	Ok(res?)
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
+fn run_app<B: Backend>(
+	terminal: &mut Terminal<B>,
+	app: &mut App,
+	mut watch: Option<FileWatch>,
+) -> io::Result<()> {
+	let mut redraw = true;
+	// A watched file is checked more often than the clock display needs
+	let timeout = match watch {
+		Some(_) => app.tick_rate.min(WATCH_DEBOUNCE),
+		None => app.tick_rate,
+	};
 	loop {
-		terminal.draw(|f| ui(f, app))?;
+		if let Some(watch) = &mut watch
+			&& watch.poll(Instant::now())
+		{
+			app.file_changed();
+			redraw = true;
+		}
+		if redraw {
+			terminal.draw(|f| ui(f, app))?;
+		}
+
+		if !event::poll(timeout)? {
+			redraw = needs_redraw(app, LoopEvent::Tick);
+			continue;
+		}
+		redraw = needs_redraw(app, LoopEvent::Input);
 
 		match event::read() {
 			Ok(Event::Key(key)) => {
+				if app.reload_pending {
+					handle_reload_prompt(app, key.code);
+					continue;
+				}
+				if app.save_preview.is_some() {
+					let action = app.keymap.resolve(key.code, key.modifiers);
+					handle_save_preview_input(app, key.code, action);
+					continue;
+				}
 				match app.edit_mode {
-					EditMode::None => {
-						match (key.code, key.modifiers) {
-							(KeyCode::Char('q'), KeyModifiers::NONE) => return Ok(()),
-							(KeyCode::Tab, KeyModifiers::NONE) => {
-								app.focus = match app.focus {
-									Focus::Left => Focus::Right,
-									Focus::Right => Focus::Left,
-								};
-							},
-							(KeyCode::Char('s'), KeyModifiers::CONTROL) => {
-								if let Err(_) = app.save_to_file() {
-									// Handle save error
-								} else {
-									app.modified = false;
-								}
-							},
-							(KeyCode::Char('n'), KeyModifiers::NONE) => {
-								app.add_note();
-							},
-							(KeyCode::Delete, KeyModifiers::NONE) => {
-								app.delete_selected_note();
-							},
-							(KeyCode::Char('i'), KeyModifiers::NONE) => {
-								app.clock_in();
-							},
-							(KeyCode::Char('o'), KeyModifiers::NONE) => {
-								app.clock_out();
-							},
-							(KeyCode::Char('k'), KeyModifiers::NONE) => {
-								app.set_current_time("scheduled");
-							},
-							(KeyCode::Char('l'), KeyModifiers::NONE) => {
-								app.set_current_time("deadline");
-							},
-							(KeyCode::Char('='), KeyModifiers::NONE) => {
-								match app.focus {
-									Focus::Right => {
-										// Set current time for selected field
-										// Implementation depends on selected field
-									},
-									_ => {},
-								}
-							},
-							_ => match app.focus {
-								Focus::Left => handle_left_panel_input(app, key.code),
-								Focus::Right => handle_right_panel_input(app, key.code),
-							},
-						}
+					EditMode::None => match app.keymap.resolve(key.code, key.modifiers) {
+						Some(Action::Quit) => return Ok(()),
+						Some(Action::SwitchPanel) => {
+							app.focus = match app.focus {
+								Focus::Left => Focus::Right,
+								Focus::Right => Focus::Left,
+							};
+						},
+						Some(Action::Save) => app.save(),
+						Some(Action::PreviewSave) => app.open_save_preview(),
+						Some(Action::Capture) => app.start_capture(),
+						Some(Action::Archive) => app.archive_selected_note(),
+						Some(Action::NextTodo) => app.jump_to_next(next_todo, "No open tasks"),
+						Some(Action::PrevTodo) => app.jump_to_next(prev_todo, "No open tasks"),
+						Some(Action::NextOverdue) => {
+							app.jump_to_next(next_overdue, "No overdue deadlines")
+						},
+						Some(Action::AddNote) => {
+							app.add_note(false);
+						},
+						Some(Action::AddChild) => {
+							app.add_note(true);
+						},
+						Some(Action::Delete) => {
+							app.delete_selected_note();
+						},
+						Some(Action::ClockIn) => {
+							app.clock_in();
+						},
+						Some(Action::ClockOut) => {
+							app.clock_out();
+						},
+						Some(Action::ClockOutAll) => app.clock_out_everywhere(),
+						Some(Action::ToggleTagTotals) => app.show_tag_totals = !app.show_tag_totals,
+						Some(Action::ToggleTimeline) => app.show_timeline = !app.show_timeline,
+						Some(Action::ScheduleNow) => {
+							app.set_current_time("scheduled");
+						},
+						Some(Action::DeadlineNow) => {
+							app.set_current_time("deadline");
+						},
+						Some(Action::Goto) => {
+							app.edit_mode = EditMode::GotoPath;
+							app.edit_buffer.clear();
+						},
+						Some(Action::SetNow) => {
+							if let Focus::Right = app.focus {
+								set_focused_field_to_now(app);
+							}
+						},
+						Some(Action::FocusLeft) => app.focus = Focus::Left,
+						Some(Action::FocusRight) => app.focus = Focus::Right,
+						Some(action @ (Action::MoveUp | Action::MoveDown)) => {
+							let code = if action == Action::MoveUp {
+								KeyCode::Up
+							} else {
+								KeyCode::Down
+							};
+							match app.focus {
+								Focus::Left => handle_left_panel_input(app, code),
+								Focus::Right => handle_right_panel_input(app, code),
+							}
+						},
+						None => match app.focus {
+							Focus::Left => handle_left_panel_input(app, key.code),
+							Focus::Right => handle_right_panel_input(app, key.code),
+						},
 					},
+					EditMode::Labels => handle_tag_edit_input(app, key.code),
 					_ => match key.code {
 						KeyCode::Enter => {
 							if matches!(app.edit_mode, EditMode::Content) {
@@ -1002,6 +1692,12 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<
 								commit_edit(app);
 							}
 						},
+						KeyCode::Esc
+							if matches!(app.edit_mode, EditMode::GotoPath | EditMode::Capture) =>
+						{
+							app.edit_mode = EditMode::None;
+							app.edit_buffer.clear();
+						},
 						KeyCode::Esc => {
 							commit_edit(app);
 						},
@@ -1022,34 +1718,36 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<
 }
 
 fn handle_left_panel_input(app: &mut App, key: KeyCode) {
+	let position = app.list_state.selected().unwrap_or(0);
+
 	match key {
-		KeyCode::Up => {
-			if app.selected_note_idx > 0 {
-				app.selected_note_idx -= 1;
-				app.list_state.select(Some(app.selected_note_idx));
-				app.selected_field_idx = 0;
-				app.status_message = get_field_name_at_index(app, app.selected_field_idx);
-			}
+		KeyCode::Up if position > 0 => {
+			app.select_note(app.flat_notes[position - 1].0);
 		},
-		KeyCode::Down => {
-			if app.selected_note_idx < app.flat_notes.len().saturating_sub(1) {
-				app.selected_note_idx += 1;
-				app.list_state.select(Some(app.selected_note_idx));
-				app.selected_field_idx = 0;
-				app.status_message = get_field_name_at_index(app, app.selected_field_idx);
-			}
+		KeyCode::Down if position + 1 < app.flat_notes.len() => {
+			app.select_note(app.flat_notes[position + 1].0);
 		},
+		KeyCode::Char('z') => app.toggle_fold(),
+		KeyCode::Char('Z') => app.collapse_all(),
+		KeyCode::Char('E') => app.expand_all(),
 		_ => {},
 	}
 }
 
 fn handle_right_panel_input(app: &mut App, key: KeyCode) {
+	// The content field is always last; arrow keys scroll it once it is selected
+	let on_content = selected_field(app) == Some(FieldRef::Content);
+
 	match key {
-		KeyCode::Up => {
-			if app.selected_field_idx > 0 {
-				app.selected_field_idx -= 1;
-				app.status_message = get_field_name_at_index(app, app.selected_field_idx);
-			}
+		KeyCode::Up if on_content && app.content_scroll > 0 => {
+			app.scroll_content(-1);
+		},
+		KeyCode::Up if app.selected_field_idx > 0 => {
+			app.selected_field_idx -= 1;
+			app.status_message = get_field_name_at_index(app, app.selected_field_idx);
+		},
+		KeyCode::Down if on_content => {
+			app.scroll_content(1);
 		},
 		KeyCode::Down => {
 			let max_fields = count_visible_fields(app);
@@ -1058,341 +1756,865 @@ fn handle_right_panel_input(app: &mut App, key: KeyCode) {
 				app.status_message = get_field_name_at_index(app, app.selected_field_idx);
 			}
 		},
+		KeyCode::PageUp => {
+			let page = app.content_viewport.1.max(1) as i32;
+			app.scroll_content(-page);
+		},
+		KeyCode::PageDown => {
+			let page = app.content_viewport.1.max(1) as i32;
+			app.scroll_content(page);
+		},
 		KeyCode::Enter => {
 			start_editing(app);
 		},
+		KeyCode::Char('S') => {
+			start_planning_edit(app, EditMode::Scheduled);
+		},
+		KeyCode::Char('D') => {
+			start_planning_edit(app, EditMode::Deadline);
+		},
+		KeyCode::Char('C') => {
+			start_planning_edit(app, EditMode::Closed);
+		},
 		_ => {},
 	}
 }
 
-fn count_visible_fields(app: &App) -> usize {
-	let mut count = 0;
-	if let Some(note) = app.get_selected_note() {
-		if note.status.is_some() {
-			count += 1;
+/// A field of a note as listed in the right panel, in navigation order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldRef {
+	Status,
+	Title,
+	Labels,
+	Scheduled,
+	Deadline,
+	Closed,
+	ClockEntry(usize),
+	Content,
+}
+
+impl FieldRef {
+	fn edit_mode(self) -> EditMode {
+		match self {
+			FieldRef::Status => EditMode::Status,
+			FieldRef::Title => EditMode::Title,
+			FieldRef::Labels => EditMode::Labels,
+			FieldRef::Scheduled => EditMode::Scheduled,
+			FieldRef::Deadline => EditMode::Deadline,
+			FieldRef::Closed => EditMode::Closed,
+			FieldRef::ClockEntry(i) => EditMode::ClockEntry(i),
+			FieldRef::Content => EditMode::Content,
 		}
-		count += 1; // title always visible
-		if !note.labels.is_empty() {
-			count += 1;
+	}
+}
+
+/// The visible fields of `note`, in the order they are navigated with the right panel.
+fn note_fields(note: &OrgNote) -> Vec<FieldRef> {
+	let mut fields = Vec::new();
+
+	if note.status.is_some() {
+		fields.push(FieldRef::Status);
+	}
+	fields.push(FieldRef::Title); // title always visible
+	if !note.labels.is_empty() {
+		fields.push(FieldRef::Labels);
+	}
+	if let Some(planning) = &note.planning {
+		if planning.scheduled.is_some() {
+			fields.push(FieldRef::Scheduled);
 		}
-		if let Some(planning) = &note.planning {
-			if planning.scheduled.is_some() {
-				count += 1;
-			}
-			if planning.deadline.is_some() {
-				count += 1;
-			}
-			if planning.closed.is_some() {
-				count += 1;
-			}
+		if planning.deadline.is_some() {
+			fields.push(FieldRef::Deadline);
 		}
-		if let Some(logbook) = &note.logbook {
-			count += logbook.clock_entries.len();
+		if planning.closed.is_some() {
+			fields.push(FieldRef::Closed);
 		}
-		count += 1; // content always visible
 	}
-	count
+	if let Some(logbook) = &note.logbook {
+		fields.extend((0..logbook.clock_entries.len()).map(FieldRef::ClockEntry));
+	}
+	fields.push(FieldRef::Content); // content always visible
+
+	fields
+}
+
+fn selected_field(app: &App) -> Option<FieldRef> {
+	let note = app.get_selected_note()?;
+	note_fields(note).get(app.selected_field_idx).copied()
+}
+
+fn count_visible_fields(app: &App) -> usize {
+	app.get_selected_note()
+		.map(|note| note_fields(note).len())
+		.unwrap_or(0)
 }
 
 fn get_field_name_at_index(app: &App, field_idx: usize) -> String {
-	if let Some(note) = app.get_selected_note() {
-		let mut current_idx = 0;
+	let Some(note) = app.get_selected_note() else {
+		return "Unknown field".to_string();
+	};
+	let planning = note.planning.clone().unwrap_or_default();
+	let raw = |timestamp: Option<OrgTimestamp>| timestamp.map(|t| t.raw).unwrap_or_default();
+
+	match note_fields(note).get(field_idx) {
+		Some(FieldRef::Status) => format!("Status: {}", note.status.clone().unwrap_or_default()),
+		Some(FieldRef::Title) => format!("Title: {}", note.title),
+		Some(FieldRef::Labels) => format!("Labels: :{}:", note.labels.join(":")),
+		Some(FieldRef::Scheduled) => format!("Scheduled: {}", raw(planning.scheduled)),
+		Some(FieldRef::Deadline) => format!("Deadline: {}", raw(planning.deadline)),
+		Some(FieldRef::Closed) => format!("Closed: {}", raw(planning.closed)),
+		Some(FieldRef::ClockEntry(i)) => {
+			let entry = &note.logbook.as_ref().unwrap().clock_entries[*i];
+			format!(
+				"Clock {}: {}{}",
+				i + 1,
+				entry.start.to_datetime_string(),
+				clock_duration_text(entry)
+			)
+		},
+		Some(FieldRef::Content) => "Content".to_string(),
+		None => "Unknown field".to_string(),
+	}
+}
 
-		if let Some(status) = &note.status {
-			if current_idx == field_idx {
-				return format!("Status: {}", status);
-			}
-			current_idx += 1;
-		}
+/// What to do once the file open in the TUI has changed on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReloadDecision {
+	/// The file holds what the TUI would save, e.g. after saving from it.
+	Ignore,
+	Reload,
+	/// There are unsaved edits a reload would drop.
+	Ask,
+}
 
-		if current_idx == field_idx {
-			return format!("Title: {}", note.title);
-		}
-		current_idx += 1;
+fn reload_decision(on_disk: &str, in_memory: &str, modified: bool) -> ReloadDecision {
+	if on_disk == in_memory {
+		ReloadDecision::Ignore
+	} else if modified {
+		ReloadDecision::Ask
+	} else {
+		ReloadDecision::Reload
+	}
+}
 
-		if !note.labels.is_empty() {
-			if current_idx == field_idx {
-				return format!("Labels: :{}:", note.labels.join(":"));
-			}
-			current_idx += 1;
-		}
+fn content_hash(content: &str) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	content.hash(&mut hasher);
+	hasher.finish()
+}
 
-		if let Some(planning) = &note.planning {
-			if let Some(scheduled) = &planning.scheduled {
-				if current_idx == field_idx {
-					return format!("Scheduled: {}", scheduled.raw);
-				}
-				current_idx += 1;
-			}
-			if let Some(deadline) = &planning.deadline {
-				if current_idx == field_idx {
-					return format!("Deadline: {}", deadline.raw);
-				}
-				current_idx += 1;
-			}
-			if let Some(closed) = &planning.closed {
-				if current_idx == field_idx {
-					return format!("Closed: {}", closed.raw);
-				}
-				current_idx += 1;
-			}
-		}
+/// Whether the file now holds something other than `loaded`, the
+/// [`content_hash`] of what was read or written last. A file that is gone or
+/// unreadable has nothing to overwrite, nor has one whose load is unknown.
+fn changed_on_disk(loaded: Option<u64>, on_disk: Option<&str>) -> bool {
+	match (loaded, on_disk) {
+		(Some(loaded), Some(content)) => content_hash(content) != loaded,
+		_ => false,
+	}
+}
 
-		if let Some(logbook) = &note.logbook {
-			for (i, entry) in logbook.clock_entries.iter().enumerate() {
-				if current_idx == field_idx {
-					let duration_text = if let Some(duration) = &entry.duration {
-						format!(" => {}", duration)
-					} else {
-						" (running)".to_string()
-					};
-					return format!(
-						"Clock {}: {}{}",
-						i + 1,
-						entry.start.to_datetime_string(),
-						duration_text
-					);
-				}
-				current_idx += 1;
-			}
+/// Turns a burst of change events into one, `delay` after the last of them.
+#[derive(Debug)]
+struct Debounce {
+	delay: Duration,
+	last_change: Option<Instant>,
+}
+
+impl Debounce {
+	fn new(delay: Duration) -> Self {
+		Self {
+			delay,
+			last_change: None,
 		}
+	}
+
+	fn changed(&mut self, at: Instant) {
+		self.last_change = Some(at);
+	}
 
-		if current_idx == field_idx {
-			return "Content".to_string();
+	/// Whether the changes have settled by `now`. True once per burst.
+	fn settled(&mut self, now: Instant) -> bool {
+		match self.last_change {
+			Some(at) if now.duration_since(at) >= self.delay => {
+				self.last_change = None;
+				true
+			},
+			_ => false,
 		}
 	}
-	"Unknown field".to_string()
 }
 
-fn start_editing(app: &mut App) {
-	let selected_field_idx = app.selected_field_idx;
+/// Watches an org file, or every `.org` file below a directory, for changes.
+struct FileWatch {
+	_watcher: RecommendedWatcher,
+	events: Receiver<()>,
+	debounce: Debounce,
+}
 
-	// Clone the data we need to avoid borrowing conflicts
-	let (status, title, labels, content, planning, logbook) =
-		if let Some(note) = app.get_selected_note() {
+impl FileWatch {
+	fn new(path: &Path) -> notify::Result<Self> {
+		let (sender, events) = mpsc::channel();
+		// Editors often save by replacing the file, which ends a watch on the file
+		// itself, so a single file is watched through its directory
+		let (watched, mode, file_name) = if path.is_dir() {
+			(path.to_path_buf(), RecursiveMode::Recursive, None)
+		} else {
+			let dir = match path.parent() {
+				Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+				_ => PathBuf::from("."),
+			};
 			(
-				note.status.clone(),
-				note.title.clone(),
-				note.labels.clone(),
-				note.content.clone(),
-				note.planning.clone(),
-				note.logbook.clone(),
+				dir,
+				RecursiveMode::NonRecursive,
+				path.file_name().map(OsStr::to_os_string),
 			)
-		} else {
-			return;
 		};
+		let mut watcher =
+			notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+				let Ok(event) = event else {
+					return;
+				};
+				let relevant = event.paths.iter().any(|changed| match &file_name {
+					Some(name) => changed.file_name() == Some(name.as_os_str()),
+					None => changed.extension().is_some_and(|ext| ext == "org"),
+				});
+				if relevant && !event.kind.is_access() {
+					let _ = sender.send(());
+				}
+			})?;
+		watcher.watch(&watched, mode)?;
+		Ok(Self {
+			_watcher: watcher,
+			events,
+			debounce: Debounce::new(WATCH_DEBOUNCE),
+		})
+	}
 
-	let mut field_idx = 0;
+	/// Whether the watched files changed and have been quiet since.
+	fn poll(&mut self, now: Instant) -> bool {
+		while self.events.try_recv().is_ok() {
+			self.debounce.changed(now);
+		}
+		self.debounce.settled(now)
+	}
 
-	if let Some(status_val) = status {
-		if field_idx == selected_field_idx {
-			app.edit_mode = EditMode::Status;
-			app.edit_buffer = status_val;
-			app.status_message = "Editing Status - Press Enter to save, Esc to cancel".to_string();
-			return;
+	/// Blocks until the watched files changed and have been quiet since.
+	fn wait(&mut self) {
+		while !self.poll(Instant::now()) {
+			std::thread::sleep(WATCH_DEBOUNCE / 4);
 		}
-		field_idx += 1;
 	}
+}
 
-	if field_idx == selected_field_idx {
-		app.edit_mode = EditMode::Title;
-		app.edit_buffer = title;
-		app.status_message = "Editing Title - Press Enter to save, Esc to cancel".to_string();
-		return;
+/// Whether the screen is stale after `event`. Input can change anything, while a
+/// tick only matters when the selected note shows a running clock.
+fn needs_redraw(app: &App, event: LoopEvent) -> bool {
+	match event {
+		LoopEvent::Input => true,
+		LoopEvent::Tick => app.selected_clock_running(),
 	}
-	field_idx += 1;
+}
 
-	if !labels.is_empty() {
-		if field_idx == selected_field_idx {
-			app.edit_mode = EditMode::Labels;
-			app.edit_buffer = format!(":{}:", labels.join(":"));
-			app.status_message = "Editing Labels - Press Enter to save, Esc to cancel".to_string();
-			return;
+fn clock_duration_text(entry: &OrgClockEntry) -> String {
+	if let Some(duration) = &entry.duration {
+		format!(" => {}", duration)
+	} else {
+		match running_elapsed_text(entry, Local::now().naive_local()) {
+			Some(elapsed) => format!(" (running, {})", elapsed),
+			None => " (running)".to_string(),
 		}
-		field_idx += 1;
 	}
+}
 
-	// Add planning fields
-	if let Some(planning_data) = planning {
-		if let Some(scheduled) = &planning_data.scheduled {
-			if field_idx == selected_field_idx {
-				app.edit_mode = EditMode::Scheduled;
-				app.edit_buffer = scheduled.raw.clone();
-				app.status_message =
-					"Editing Scheduled - Press Enter to save, Esc to cancel".to_string();
-				return;
-			}
-			field_idx += 1;
-		}
-		if let Some(deadline) = &planning_data.deadline {
-			if field_idx == selected_field_idx {
-				app.edit_mode = EditMode::Deadline;
-				app.edit_buffer = deadline.raw.clone();
-				app.status_message =
-					"Editing Deadline - Press Enter to save, Esc to cancel".to_string();
-				return;
-			}
-			field_idx += 1;
-		}
-		if let Some(closed) = &planning_data.closed {
-			if field_idx == selected_field_idx {
-				app.edit_mode = EditMode::Closed;
-				app.edit_buffer = closed.raw.clone();
-				app.status_message =
-					"Editing Closed - Press Enter to save, Esc to cancel".to_string();
-				return;
-			}
-			field_idx += 1;
-		}
+/// How long an open clock entry has been running at `now`, e.g. `1h 23m`.
+fn running_elapsed_text(entry: &OrgClockEntry, now: NaiveDateTime) -> Option<String> {
+	let minutes = (now - entry.start.to_naive_datetime()?)
+		.num_minutes()
+		.max(0);
+	Some(format!("{}h {}m", minutes / 60, minutes % 60))
+}
+
+/// How far `timestamp` is from `now`: `in 3 days`, `2 days ago` or `today`, and
+/// for a timed timestamp on the same day `in 1h 30m`, `now` or `0h 45m ago`.
+fn relative_date_label(timestamp: &OrgTimestamp, now: NaiveDateTime) -> Option<String> {
+	let days = (timestamp.to_naive_date()? - now.date()).num_days();
+	let plural = |count: i64| if count == 1 { "" } else { "s" };
+	Some(match days {
+		0 => match timestamp.hour {
+			Some(_) => {
+				let minutes = (timestamp.to_naive_datetime()? - now).num_minutes();
+				let span = format!("{}h {}m", minutes.abs() / 60, minutes.abs() % 60);
+				if minutes == 0 {
+					"now".to_string()
+				} else if minutes > 0 {
+					format!("in {}", span)
+				} else {
+					format!("{} ago", span)
+				}
+			},
+			None => "today".to_string(),
+		},
+		1.. => format!("in {} day{}", days, plural(days)),
+		_ => format!("{} day{} ago", -days, plural(-days)),
+	})
+}
+
+/// What `=` does for the focused metadata field.
+#[derive(Debug, PartialEq)]
+enum NowAction {
+	SetPlanning(&'static str),
+	ClockOut(usize),
+}
+
+fn now_action_for_field(note: &OrgNote, field_idx: usize) -> Option<NowAction> {
+	match note_fields(note).get(field_idx)? {
+		FieldRef::Scheduled => Some(NowAction::SetPlanning("scheduled")),
+		FieldRef::Deadline => Some(NowAction::SetPlanning("deadline")),
+		FieldRef::Closed => Some(NowAction::SetPlanning("closed")),
+		FieldRef::ClockEntry(i) => Some(NowAction::ClockOut(*i)),
+		_ => None,
 	}
+}
 
-	// Add logbook fields (clock entries)
-	if let Some(logbook_data) = logbook {
-		for (i, entry) in logbook_data.clock_entries.iter().enumerate() {
-			if field_idx == selected_field_idx {
-				app.edit_mode = EditMode::Content; // Reuse content mode for clock entries
-				app.edit_buffer = entry.raw.clone();
-				app.status_message = format!(
-					"Editing Clock Entry {} - Press Esc to save, Enter for new line",
-					i + 1
-				);
-				return;
-			}
-			field_idx += 1;
-		}
+fn set_focused_field_to_now(app: &mut App) {
+	let action = app
+		.get_selected_note()
+		.and_then(|note| now_action_for_field(note, app.selected_field_idx));
+
+	match action {
+		Some(NowAction::SetPlanning(field)) => {
+			app.set_current_time(field);
+			app.status_message = get_field_name_at_index(app, app.selected_field_idx);
+		},
+		Some(NowAction::ClockOut(entry_idx)) => {
+			app.status_message = if app.clock_out_entry(entry_idx) {
+				get_field_name_at_index(app, app.selected_field_idx)
+			} else {
+				"Clock entry is not running".to_string()
+			};
+		},
+		None => {
+			app.status_message =
+				"Only planning fields and clock entries can be set to now".to_string();
+		},
 	}
+}
+
+fn start_editing(app: &mut App) {
+	let Some(field) = selected_field(app) else {
+		return;
+	};
+	let Some(note) = app.get_selected_note() else {
+		return;
+	};
+
+	let (name, buffer) = match field {
+		FieldRef::Status => (
+			"Status".to_string(),
+			note.status.clone().unwrap_or_default(),
+		),
+		FieldRef::Title => ("Title".to_string(), note.title.clone()),
+		FieldRef::Labels => {
+			app.labels_before_edit = Some((note.labels.clone(), app.modified));
+			app.edit_mode = EditMode::Labels;
+			app.edit_buffer.clear();
+			app.tag_cursor = None;
+			app.status_message = "Editing Labels - Enter adds a tag, Left/Right select one, Delete removes it, Enter again when done, Esc cancels".to_string();
+			return;
+		},
+		FieldRef::Scheduled | FieldRef::Deadline | FieldRef::Closed => {
+			start_planning_edit(app, field.edit_mode());
+			return;
+		},
+		FieldRef::ClockEntry(i) => (
+			format!("Clock Entry {}", i + 1),
+			note.logbook.as_ref().unwrap().clock_entries[i]
+				.raw
+				.trim()
+				.to_string(),
+		),
+		FieldRef::Content => ("Content".to_string(), note.content.clone()),
+	};
+
+	app.edit_mode = field.edit_mode();
+	app.edit_buffer = buffer;
+	app.status_message = format!("Editing {} - Press Enter to save, Esc to cancel", name);
+}
+
+/// Starts editing a planning field, whether or not the selected note already has it.
+/// Committing an empty buffer removes the field.
+fn start_planning_edit(app: &mut App, mode: EditMode) {
+	let Some(note) = app.get_selected_note() else {
+		return;
+	};
+	let planning = note.planning.clone().unwrap_or_default();
+	let (name, existing) = match mode {
+		EditMode::Scheduled => ("Scheduled", planning.scheduled),
+		EditMode::Deadline => ("Deadline", planning.deadline),
+		EditMode::Closed => ("Closed", planning.closed),
+		_ => return,
+	};
+
+	app.edit_mode = mode;
+	app.edit_buffer = existing.map(|t| t.raw).unwrap_or_default();
+	app.status_message = format!(
+		"Editing {} - Press Enter to save (empty to remove), Esc to cancel",
+		name
+	);
+}
 
-	if field_idx == selected_field_idx {
-		app.edit_mode = EditMode::Content;
-		app.edit_buffer = content;
-		app.status_message = "Editing Content - Press Enter to save, Esc to cancel".to_string();
+fn planning_slot<'a>(
+	planning: &'a mut OrgPlanning,
+	mode: &EditMode,
+) -> Option<&'a mut Option<OrgTimestamp>> {
+	match mode {
+		EditMode::Scheduled => Some(&mut planning.scheduled),
+		EditMode::Deadline => Some(&mut planning.deadline),
+		EditMode::Closed => Some(&mut planning.closed),
+		_ => None,
 	}
 }
 
+/// Index of the field edited in `mode` in the right panel, if the note has it.
+fn field_index_for_mode(note: &OrgNote, mode: &EditMode) -> Option<usize> {
+	note_fields(note)
+		.iter()
+		.position(|field| field.edit_mode() == *mode)
+}
+
 fn commit_edit(app: &mut App) {
 	let edit_mode = app.edit_mode.clone();
 	let edit_buffer = app.edit_buffer.clone();
 
+	if edit_mode == EditMode::Capture {
+		app.edit_mode = EditMode::None;
+		app.edit_buffer.clear();
+		app.capture(&edit_buffer);
+		return;
+	}
+
+	if edit_mode == EditMode::GotoPath {
+		app.edit_mode = EditMode::None;
+		app.edit_buffer.clear();
+		match resolve_goto_target(&app.notes, &edit_buffer) {
+			Some(idx) => app.select_note(idx),
+			None => app.status_message = format!("Not found: {}", edit_buffer.trim()),
+		}
+		return;
+	}
+
 	// Parse timestamps outside the mutable borrow
-	let scheduled_timestamp = if matches!(edit_mode, EditMode::Scheduled) {
-		parse_timestamp_from_text(&edit_buffer)
-	} else {
-		None
-	};
-	let deadline_timestamp = if matches!(edit_mode, EditMode::Deadline) {
-		parse_timestamp_from_text(&edit_buffer)
+	let planning_timestamp = if matches!(
+		edit_mode,
+		EditMode::Scheduled | EditMode::Deadline | EditMode::Closed
+	) {
+		let kind = if edit_mode == EditMode::Closed {
+			TimestampKind::Inactive
+		} else {
+			TimestampKind::Active
+		};
+		parse_timestamp_input(&edit_buffer, kind, Local::now().naive_local())
 	} else {
 		None
 	};
-	let closed_timestamp = if matches!(edit_mode, EditMode::Closed) {
-		parse_timestamp_from_text(&edit_buffer)
+	let clock_entry = if matches!(edit_mode, EditMode::ClockEntry(_)) {
+		Some(parse_clock_entry(&edit_buffer))
 	} else {
 		None
 	};
+	let mut error = None;
+	let mut finished = false;
+	let mut repeated = None;
+	let keywords = app.keywords.clone();
 
 	if let Some(note) = app.get_selected_note_mut() {
 		match edit_mode {
 			EditMode::Status => {
-				note.status = if edit_buffer.is_empty() {
-					None
+				finished = keywords.is_done(&edit_buffer) && !keywords.is_done_task(note);
+				// A repeating task moves to its next date and stays open instead
+				if finished && note.repeat_task(Local::now().naive_local()) {
+					let todo = note
+						.status
+						.clone()
+						.unwrap_or_else(|| keywords.repeat_to().to_string());
+					repeated = Some(format!("{} repeats, moved to its next date", note.title));
+					note.status = Some(todo);
 				} else {
-					Some(edit_buffer)
-				};
+					note.status = if edit_buffer.is_empty() {
+						None
+					} else {
+						Some(edit_buffer)
+					};
+				}
 			},
 			EditMode::Title => {
 				note.title = edit_buffer;
 			},
 			EditMode::Labels => {
-				let labels_str = edit_buffer.trim_start_matches(':').trim_end_matches(':');
-				note.labels = if labels_str.is_empty() {
-					Vec::new()
-				} else {
-					labels_str.split(':').map(|s| s.to_string()).collect()
-				};
-			},
-			EditMode::Scheduled => {
-				if let Some(timestamp) = scheduled_timestamp {
-					if note.planning.is_none() {
-						note.planning = Some(OrgPlanning {
-							scheduled: None,
-							deadline: None,
-							closed: None,
-						});
-					}
-					if let Some(planning) = &mut note.planning {
-						planning.scheduled = Some(timestamp);
-					}
+				// Tags still being typed when the edit ends are added too
+				if let Err(message) = add_tags(&mut note.labels, &edit_buffer) {
+					error = Some(message);
 				}
 			},
-			EditMode::Deadline => {
-				if let Some(timestamp) = deadline_timestamp {
-					if note.planning.is_none() {
-						note.planning = Some(OrgPlanning {
-							scheduled: None,
-							deadline: None,
-							closed: None,
-						});
-					}
+			EditMode::Scheduled | EditMode::Deadline | EditMode::Closed => {
+				if edit_buffer.trim().is_empty() {
 					if let Some(planning) = &mut note.planning {
-						planning.deadline = Some(timestamp);
+						if let Some(slot) = planning_slot(planning, &edit_mode) {
+							*slot = None;
+						}
+						if planning.is_empty() {
+							note.planning = None;
+						}
 					}
+				} else if let Some(timestamp) = planning_timestamp {
+					let planning = note.planning.get_or_insert_with(OrgPlanning::default);
+					if let Some(slot) = planning_slot(planning, &edit_mode) {
+						*slot = Some(timestamp);
+					}
+				} else {
+					error = Some(format!("Invalid timestamp: {}", edit_buffer.trim()));
 				}
 			},
-			EditMode::Closed => {
-				if let Some(timestamp) = closed_timestamp {
-					if note.planning.is_none() {
-						note.planning = Some(OrgPlanning {
-							scheduled: None,
-							deadline: None,
-							closed: None,
-						});
-					}
-					if let Some(planning) = &mut note.planning {
-						planning.closed = Some(timestamp);
+			EditMode::ClockEntry(i) => match clock_entry {
+				Some(Ok(entry)) => {
+					if let Some(existing) = note
+						.logbook
+						.as_mut()
+						.and_then(|logbook| logbook.clock_entries.get_mut(i))
+					{
+						*existing = entry;
 					}
-				}
+				},
+				Some(Err(message)) => error = Some(message),
+				None => {},
 			},
 			EditMode::Content => {
 				note.content = edit_buffer;
+				note.links = extract_links(&note.content);
+				note.tables = extract_tables(&note.content);
 			},
 			_ => {},
 		}
 
-		app.modified = true;
-		app.flat_notes = App::flatten_notes(&app.notes);
+		if error.is_none() {
+			app.modified = true;
+			app.refresh_flat_notes();
+		}
+	}
+
+	// Like org-clock-out-when-done, finishing a task stops its running clock
+	if finished {
+		app.clock_out();
 	}
 
+	// Keep the selection on the edited field (e.g. a planning field that was just added),
+	// and in range after removal
+	if let Some(note) = app.get_selected_note()
+		&& let Some(idx) = field_index_for_mode(note, &edit_mode)
+	{
+		app.selected_field_idx = idx;
+	}
+	app.selected_field_idx = app
+		.selected_field_idx
+		.min(count_visible_fields(app).saturating_sub(1));
+
 	app.edit_mode = EditMode::None;
 	app.edit_buffer.clear();
-	app.status_message = get_field_name_at_index(app, app.selected_field_idx);
-}
-
-fn parse_timestamp_from_text(text: &str) -> Option<OrgTimestamp> {
-	let parser = OrgParser::new("");
-	parser.parse_timestamp_from_text(text)
+	app.status_message = error
+		.or(repeated)
+		.unwrap_or_else(|| get_field_name_at_index(app, app.selected_field_idx));
 }
 
-fn ui(f: &mut Frame, app: &App) {
-	let chunks = Layout::default()
-		.direction(Direction::Vertical)
-		.constraints([Constraint::Min(0), Constraint::Length(3)])
-		.split(f.size());
+/// Keys while editing labels: Enter adds the typed tags (or finishes on empty input),
+/// Left/Right select an existing tag and Delete removes it.
+fn handle_tag_edit_input(app: &mut App, key: KeyCode) {
+	let tag_count = app.get_selected_note().map_or(0, |note| note.labels.len());
 
-	let main_chunks = Layout::default()
-		.direction(Direction::Horizontal)
-		.constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+	match key {
+		KeyCode::Enter if !app.edit_buffer.trim().is_empty() => {
+			let buffer = std::mem::take(&mut app.edit_buffer);
+			let Some(note) = app.get_selected_note_mut() else {
+				return;
+			};
+			match add_tags(&mut note.labels, &buffer) {
+				Ok(0) => app.status_message = "Tag already present".to_string(),
+				Ok(_) => {
+					app.status_message = format!("Labels: :{}:", note.labels.join(":"));
+					app.modified = true;
+				},
+				Err(message) => {
+					app.status_message = message;
+					app.edit_buffer = buffer;
+				},
+			}
+		},
+		KeyCode::Enter => {
+			app.tag_cursor = None;
+			app.labels_before_edit = None;
+			commit_edit(app);
+		},
+		KeyCode::Esc => {
+			if let Some((labels, modified)) = app.labels_before_edit.take()
+				&& let Some(note) = app.get_selected_note_mut()
+			{
+				note.labels = labels;
+				app.modified = modified;
+			}
+			app.tag_cursor = None;
+			app.edit_mode = EditMode::None;
+			app.edit_buffer.clear();
+			app.status_message = "Label changes discarded".to_string();
+		},
+		KeyCode::Left if tag_count > 0 => {
+			app.tag_cursor = Some(
+				app.tag_cursor
+					.map_or(tag_count - 1, |i| i.saturating_sub(1)),
+			);
+		},
+		KeyCode::Right if tag_count > 0 => {
+			app.tag_cursor = app.tag_cursor.map(|i| i + 1).filter(|&i| i < tag_count);
+		},
+		KeyCode::Delete => {
+			if let Some(i) = app.tag_cursor
+				&& let Some(note) = app.get_selected_note_mut()
+				&& let Some(removed) = remove_tag(&mut note.labels, i)
+			{
+				let remaining = note.labels.len();
+				app.tag_cursor = (remaining > 0).then(|| i.min(remaining - 1));
+				app.modified = true;
+				app.status_message = format!("Removed tag {}", removed);
+			}
+		},
+		KeyCode::Char(c) => app.edit_buffer.push(c),
+		KeyCode::Backspace => {
+			app.edit_buffer.pop();
+		},
+		_ => {},
+	}
+}
+
+/// Adds the tags in `input` (separated by spaces or colons) that are not there yet,
+/// returning how many were added. Nothing is added if any tag is invalid.
+fn add_tags(labels: &mut Vec<String>, input: &str) -> Result<usize, String> {
+	let tags: Vec<&str> = input
+		.split(|c: char| c == ':' || c.is_whitespace())
+		.filter(|tag| !tag.is_empty())
+		.collect();
+	if let Some(invalid) = tags.iter().find(|tag| !is_valid_tag(tag)) {
+		return Err(format!("Invalid tag: {}", invalid));
+	}
+
+	let before = labels.len();
+	for tag in tags {
+		if !labels.iter().any(|label| label == tag) {
+			labels.push(tag.to_string());
+		}
+	}
+	Ok(labels.len() - before)
+}
+
+fn remove_tag(labels: &mut Vec<String>, idx: usize) -> Option<String> {
+	(idx < labels.len()).then(|| labels.remove(idx))
+}
+
+/// The next overdue task after the note with `from_id`, see [`next_matching`].
+fn next_overdue(notes: &[OrgNote], keywords: &Keywords, from_id: u64) -> Option<u64> {
+	let now = Local::now().naive_local();
+	next_matching(notes, from_id, |note| keywords.is_overdue(note, now))
+}
+
+/// Flat list index for `g` input: a 1-based heading number, or an outline path
+/// such as `Work/ProjectX/Design` resolved with [`find_by_path`].
+fn resolve_goto_target(notes: &[OrgNote], input: &str) -> Option<usize> {
+	let input = input.trim();
+	if let Ok(number) = input.parse::<usize>() {
+		let count = iter_notes(notes).count();
+		return (1..=count).contains(&number).then(|| number - 1);
+	}
+
+	let path: Vec<&str> = input
+		.split('/')
+		.map(str::trim)
+		.filter(|segment| !segment.is_empty())
+		.collect();
+	let target = find_by_path(notes, &path)?;
+	iter_notes(notes).position(|(_, note)| std::ptr::eq(note, target))
+}
+
+/// Flat list index for `--goto`: the first heading titled `title` anywhere in
+/// the tree, or else a heading number or outline path as for `g`.
+fn resolve_goto_title(notes: &[OrgNote], title: &str) -> Option<usize> {
+	iter_notes(notes)
+		.position(|(_, note)| note.title == title.trim())
+		.or_else(|| resolve_goto_target(notes, title))
+}
+
+/// Flat list index for `--goto-line`: the heading whose section holds the
+/// 1-based `line`, or `None` before the first heading. Needs source lines.
+fn resolve_goto_line(notes: &[OrgNote], line: usize) -> Option<usize> {
+	iter_notes(notes)
+		.enumerate()
+		.filter(|(_, (_, note))| note.source_line.is_some_and(|start| start <= line))
+		.map(|(idx, _)| idx)
+		.last()
+}
+
+/// Parses an edited CLOCK line, re-deriving its duration and raw text from the timestamps.
+fn parse_clock_entry(text: &str) -> Result<OrgClockEntry, String> {
+	let parser = OrgParser::new("");
+	let mut entry = parser
+		.parse_clock_line(text)
+		.ok_or_else(|| format!("Invalid clock entry: {}", text.trim()))?;
+
+	if entry.end.is_some() {
+		let minutes = entry
+			.elapsed_minutes()
+			.ok_or_else(|| format!("Invalid clock entry: {}", text.trim()))?;
+		if minutes < 0 {
+			return Err("Clock entry ends before it starts".to_string());
+		}
+
+		entry.recompute_duration();
+	} else {
+		entry.raw = format!("CLOCK: {}", entry.start.to_inactive_raw());
+	}
+
+	Ok(entry)
+}
+
+/// Number of rows `text` occupies when word-wrapped to `width` display columns.
+fn wrapped_line_count(text: &str, width: u16) -> u16 {
+	let width = width.max(1) as usize;
+	let mut rows = 0usize;
+
+	for line in text.lines() {
+		let mut line_rows = 1;
+		let mut current = 0;
+		for word in line.split_whitespace() {
+			let word_len = display_width(word);
+			let needed = if current == 0 {
+				word_len
+			} else {
+				current + 1 + word_len
+			};
+			if needed <= width {
+				current = needed;
+			} else if current == 0 {
+				// A single word longer than the viewport is broken across rows
+				line_rows += (word_len - 1) / width;
+				current = (word_len - 1) % width + 1;
+			} else {
+				line_rows += 1 + word_len.saturating_sub(1) / width;
+				current = (word_len - 1) % width + 1;
+			}
+		}
+		rows += line_rows;
+	}
+
+	rows.min(u16::MAX as usize) as u16
+}
+
+/// Clamps a scroll offset so the last page of content stays in view.
+fn clamp_scroll(offset: u16, content_height: u16, viewport_height: u16) -> u16 {
+	offset.min(content_height.saturating_sub(viewport_height))
+}
+
+fn ui(f: &mut Frame, app: &mut App) {
+	let chunks = Layout::default()
+		.direction(Direction::Vertical)
+		.constraints([Constraint::Min(0), Constraint::Length(3)])
+		.split(f.size());
+
+	let main_chunks = Layout::default()
+		.direction(Direction::Horizontal)
+		.constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
 		.split(chunks[0]);
 
-	render_left_panel(f, app, main_chunks[0]);
-	render_right_panel(f, app, main_chunks[1]);
+	if app.show_tag_totals {
+		let totals = clock_totals_by_tag(&app.notes, &DateRange::default(), &Rounding::default());
+		let left_chunks = Layout::default()
+			.direction(Direction::Vertical)
+			.constraints([
+				Constraint::Min(3),
+				Constraint::Length(totals.len().max(1) as u16 + 2),
+			])
+			.split(main_chunks[0]);
+		render_left_panel(f, app, left_chunks[0]);
+		render_tag_totals(f, &totals, left_chunks[1]);
+	} else {
+		render_left_panel(f, app, main_chunks[0]);
+	}
+	if app.show_timeline {
+		render_timeline(f, app, Local::now().date_naive(), main_chunks[1]);
+	} else {
+		render_right_panel(f, app, main_chunks[1]);
+	}
 	render_status_bar(f, app, chunks[1]);
+	if let Some(preview) = &app.save_preview {
+		render_save_preview(f, preview, chunks[0]);
+	}
+}
+
+fn render_save_preview(f: &mut Frame, preview: &SavePreview, area: Rect) {
+	let lines: Vec<Line> = preview
+		.lines
+		.iter()
+		.skip(preview.scroll)
+		.map(|line| {
+			let color = if line.starts_with("@@") {
+				Color::Cyan
+			} else if line.starts_with('+') {
+				Color::Green
+			} else if line.starts_with('-') {
+				Color::Red
+			} else {
+				Color::Reset
+			};
+			Line::styled(line.clone(), Style::default().fg(color))
+		})
+		.collect();
+
+	let paragraph = Paragraph::new(lines).block(
+		Block::default()
+			.borders(Borders::ALL)
+			.title("Changes on save")
+			.border_style(Style::default().fg(Color::Yellow)),
+	);
+	f.render_widget(Clear, area);
+	f.render_widget(paragraph, area);
 }
 
 fn render_left_panel(f: &mut Frame, app: &App, area: Rect) {
+	let by_tree_idx: Vec<(usize, &OrgNote)> = iter_notes(&app.notes).collect();
+
 	let items: Vec<ListItem> = app
 		.flat_notes
 		.iter()
-		.map(|(_, display)| ListItem::new(Line::from(display.clone())))
+		.map(|&(idx, _)| {
+			let (depth, note) = by_tree_idx[idx];
+			let mut spans = vec![Span::raw(heading_prefix(note, depth))];
+			if let Some(status) = &note.status {
+				spans.push(Span::styled(
+					status.clone(),
+					app.status_styles.style_for(status, &app.keywords),
+				));
+				spans.push(Span::raw(" "));
+			}
+			if let Some(priority) = note.priority {
+				spans.push(Span::raw(format!("[#{}] ", priority)));
+			}
+			spans.push(Span::raw(note.title.clone()));
+			if !note.labels.is_empty() {
+				spans.push(Span::styled(
+					format!(" :{}:", note.labels.join(":")),
+					Style::default().fg(TAG_COLOR),
+				));
+			}
+			if app.collapsed.contains(&idx) && !note.children.is_empty() {
+				spans.push(Span::raw(" ..."));
+			}
+			ListItem::new(Line::from(spans))
+		})
 		.collect();
 
 	let border_style = if matches!(app.focus, Focus::Left) {
@@ -1401,6 +2623,15 @@ fn render_left_panel(f: &mut Frame, app: &App, area: Rect) {
 		Style::default()
 	};
 
+	let items = if items.is_empty() {
+		vec![ListItem::new(Line::styled(
+			"No notes",
+			Style::default().fg(Color::DarkGray),
+		))]
+	} else {
+		items
+	};
+
 	let list = List::new(items)
 		.block(
 			Block::default()
@@ -1413,12 +2644,134 @@ fn render_left_panel(f: &mut Frame, app: &App, area: Rect) {
 	f.render_stateful_widget(list, area, &mut app.list_state.clone());
 }
 
-fn render_right_panel(f: &mut Frame, app: &App, area: Rect) {
+fn render_tag_totals(f: &mut Frame, totals: &[(String, u32)], area: Rect) {
+	let lines: Vec<Line> = if totals.is_empty() {
+		vec![Line::raw("No tagged time")]
+	} else {
+		totals
+			.iter()
+			.map(|(tag, minutes)| {
+				Line::from(vec![
+					Span::styled(format!(":{}:", tag), Style::default().fg(TAG_COLOR)),
+					Span::raw(format!(" {}h {}m", minutes / 60, minutes % 60)),
+				])
+			})
+			.collect()
+	};
+	let paragraph =
+		Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Time by tag"));
+	f.render_widget(paragraph, area);
+}
+
+/// Column of `date` in a timeline of `days` days starting at `first_day`, or
+/// `None` outside it.
+fn date_column(date: NaiveDate, first_day: NaiveDate, days: usize) -> Option<usize> {
+	let offset = (date - first_day).num_days();
+	(0..days as i64)
+		.contains(&offset)
+		.then_some(offset as usize)
+}
+
+/// First and last column of `note`'s timeline bar, which runs from its scheduled
+/// date to its deadline (either alone gives a one-day bar), cut to the days
+/// shown. `None` for a note without dates or with all of them out of range.
+fn timeline_bar(note: &OrgNote, first_day: NaiveDate, days: usize) -> Option<(usize, usize)> {
+	let planning = note.planning.as_ref()?;
+	let date =
+		|timestamp: &Option<OrgTimestamp>| timestamp.as_ref().and_then(OrgTimestamp::to_naive_date);
+	let (start, end) = match (date(&planning.scheduled), date(&planning.deadline)) {
+		(Some(scheduled), Some(deadline)) => (scheduled.min(deadline), scheduled.max(deadline)),
+		(Some(day), None) | (None, Some(day)) => (day, day),
+		(None, None) => return None,
+	};
+	let first = (start - first_day).num_days().max(0);
+	let last = (end - first_day).num_days().min(days as i64 - 1);
+	(first <= last).then_some((first as usize, last as usize))
+}
+
+/// One row per top-level note with a scheduled date or deadline in the next
+/// `app.timeline_days` days from `today`, with a bar over its dates and its
+/// deadline marked `◆`.
+fn render_timeline(f: &mut Frame, app: &App, today: NaiveDate, area: Rect) {
+	let days = app.timeline_days;
+	let rows: Vec<(&OrgNote, (usize, usize))> = app
+		.notes
+		.iter()
+		.filter_map(|note| Some((note, timeline_bar(note, today, days)?)))
+		.collect();
+	let title_width = rows
+		.iter()
+		.map(|(note, _)| note.title.width())
+		.max()
+		.unwrap_or(0)
+		.min(24);
+
+	let weekdays: String = (0..days)
+		.map(|day| {
+			let date = today + chrono::Duration::days(day as i64);
+			date.format("%a").to_string().chars().next().unwrap_or(' ')
+		})
+		.collect();
+	let mut lines = vec![Line::styled(
+		format!("{:title_width$} {}", "", weekdays),
+		Style::default().fg(Color::DarkGray),
+	)];
+	for (note, (first, last)) in &rows {
+		let deadline = note
+			.planning
+			.as_ref()
+			.and_then(|planning| planning.deadline.as_ref())
+			.and_then(OrgTimestamp::to_naive_date)
+			.and_then(|date| date_column(date, today, days));
+		let bar_style = match &note.status {
+			Some(status) => app.status_styles.style_for(status, &app.keywords),
+			None => Style::default(),
+		};
+		let mut title = note.title.clone();
+		while title.width() > title_width {
+			title.pop();
+		}
+		let mut spans = vec![Span::raw(format!(
+			"{}{} ",
+			title,
+			" ".repeat(title_width - title.width())
+		))];
+		for column in 0..days {
+			spans.push(if Some(column) == deadline {
+				Span::styled("◆", Style::default().fg(Color::Red))
+			} else if (*first..=*last).contains(&column) {
+				Span::styled("█", bar_style)
+			} else {
+				Span::styled("·", Style::default().fg(Color::DarkGray))
+			});
+		}
+		lines.push(Line::from(spans));
+	}
+	if rows.is_empty() {
+		lines.push(Line::raw("Nothing scheduled"));
+	}
+
+	let paragraph = Paragraph::new(lines).block(
+		Block::default()
+			.borders(Borders::ALL)
+			.title(format!("Timeline, next {} days", days)),
+	);
+	f.render_widget(paragraph, area);
+}
+
+fn render_right_panel(f: &mut Frame, app: &mut App, area: Rect) {
 	let chunks = Layout::default()
 		.direction(Direction::Vertical)
 		.constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
 		.split(area);
 
+	// An empty file has no note to show, but keeps the panels' frames
+	if app.get_selected_note().is_none() {
+		for (title, area) in [("Metadata", chunks[0]), ("Content", chunks[1])] {
+			f.render_widget(Block::default().borders(Borders::ALL).title(title), area);
+		}
+		return;
+	}
 	render_metadata_panel(f, app, chunks[0]);
 	render_content_panel(f, app, chunks[1]);
 }
@@ -1432,9 +2785,26 @@ fn render_metadata_panel(f: &mut Frame, app: &App, area: Rect) {
 
 	if let Some(note) = app.get_selected_note() {
 		let mut lines = Vec::new();
-		let mut field_idx = 0;
+		let planning = note.planning.clone().unwrap_or_default();
+		let raw = |timestamp: &Option<OrgTimestamp>| {
+			timestamp
+				.as_ref()
+				.map(|t| t.raw.clone())
+				.unwrap_or_default()
+		};
+		let now = Local::now().naive_local();
+		// A passed deadline of an open task reads `overdue by 2 days`
+		let hint = |timestamp: &Option<OrgTimestamp>, is_deadline: bool| {
+			let label = relative_date_label(timestamp.as_ref()?, now)?;
+			Some(match label.strip_suffix(" ago") {
+				Some(span) if is_deadline && app.keywords.is_overdue(note, now) => {
+					format!(" (overdue by {})", span)
+				},
+				_ => format!(" ({})", label),
+			})
+		};
 
-		if let Some(status) = &note.status {
+		for (field_idx, field) in note_fields(note).into_iter().enumerate() {
 			let style = if field_idx == app.selected_field_idx && matches!(app.focus, Focus::Right)
 			{
 				Style::default().add_modifier(Modifier::BOLD)
@@ -1442,135 +2812,89 @@ fn render_metadata_panel(f: &mut Frame, app: &App, area: Rect) {
 				Style::default()
 			};
 
-			let text = if matches!(app.edit_mode, EditMode::Status) {
-				format!("Status: {}", app.edit_buffer)
-			} else {
-				format!("Status: {}", status)
+			let editing = app.edit_mode == field.edit_mode();
+			let value = |current: String| {
+				if editing {
+					app.edit_buffer.clone()
+				} else {
+					current
+				}
 			};
 
-			lines.push(Line::from(Span::styled(text, style)));
-			field_idx += 1;
-		}
-
-		let style = if field_idx == app.selected_field_idx && matches!(app.focus, Focus::Right) {
-			Style::default().add_modifier(Modifier::BOLD)
-		} else {
-			Style::default()
-		};
-
-		let text = if matches!(app.edit_mode, EditMode::Title) {
-			format!("Title: {}", app.edit_buffer)
-		} else {
-			format!("Title: {}", note.title)
-		};
-
-		lines.push(Line::from(Span::styled(text, style)));
-		field_idx += 1;
-
-		if !note.labels.is_empty() {
-			let style = if field_idx == app.selected_field_idx && matches!(app.focus, Focus::Right)
-			{
-				Style::default().add_modifier(Modifier::BOLD)
-			} else {
-				Style::default()
+			// Status and tags are colored unless they are being edited
+			let colored = |label: &str, text: String, color: Style| {
+				let value_style = if editing { style } else { style.patch(color) };
+				Line::from(vec![
+					Span::styled(format!("{}: ", label), style),
+					Span::styled(text, value_style),
+				])
 			};
 
-			let text = if matches!(app.edit_mode, EditMode::Labels) {
-				format!("Labels: {}", app.edit_buffer)
-			} else {
-				format!("Labels: :{}:", note.labels.join(":"))
+			let text = match field {
+				FieldRef::Status => {
+					let status = note.status.clone().unwrap_or_default();
+					let color = app.status_styles.style_for(&status, &app.keywords);
+					lines.push(colored("Status", value(status), color));
+					continue;
+				},
+				FieldRef::Title => format!("Title: {}", value(note.title.clone())),
+				FieldRef::Labels if editing => {
+					// Each tag on its own, the one selected for removal highlighted
+					let mut spans = vec![Span::styled("Labels: ", style)];
+					for (i, tag) in note.labels.iter().enumerate() {
+						let mut tag_style = Style::default().fg(TAG_COLOR);
+						if app.tag_cursor == Some(i) {
+							tag_style = tag_style.add_modifier(Modifier::REVERSED);
+						}
+						spans.push(Span::styled(format!(":{}", tag), tag_style));
+					}
+					if !note.labels.is_empty() {
+						spans.push(Span::styled(":", Style::default().fg(TAG_COLOR)));
+					}
+					spans.push(Span::raw(format!(" + {}", app.edit_buffer)));
+					lines.push(Line::from(spans));
+					continue;
+				},
+				FieldRef::Labels => {
+					let labels = format!(":{}:", note.labels.join(":"));
+					lines.push(colored("Labels", labels, Style::default().fg(TAG_COLOR)));
+					continue;
+				},
+				FieldRef::Scheduled if editing => format!("Scheduled: {}", app.edit_buffer),
+				FieldRef::Scheduled => format!(
+					"Scheduled: {}{}",
+					raw(&planning.scheduled),
+					hint(&planning.scheduled, false).unwrap_or_default()
+				),
+				FieldRef::Deadline if editing => format!("Deadline: {}", app.edit_buffer),
+				FieldRef::Deadline => format!(
+					"Deadline: {}{}",
+					raw(&planning.deadline),
+					hint(&planning.deadline, true).unwrap_or_default()
+				),
+				FieldRef::Closed => format!("Closed: {}", value(raw(&planning.closed))),
+				FieldRef::ClockEntry(i) => {
+					if i == 0 {
+						lines.push(Line::from("Time Tracking:"));
+					}
+					let entry = &note.logbook.as_ref().unwrap().clock_entries[i];
+					format!(
+						"  Clock: {}{}",
+						entry.start.to_datetime_string(),
+						clock_duration_text(entry)
+					)
+				},
+				FieldRef::Content => continue, // shown in its own panel
 			};
 
 			lines.push(Line::from(Span::styled(text, style)));
-			field_idx += 1;
 		}
 
-		if let Some(planning) = &note.planning {
-			if let Some(scheduled) = &planning.scheduled {
-				let style =
-					if field_idx == app.selected_field_idx && matches!(app.focus, Focus::Right) {
-						Style::default().add_modifier(Modifier::BOLD)
-					} else {
-						Style::default()
-					};
-
-				let text = if matches!(app.edit_mode, EditMode::Scheduled) {
-					format!("Scheduled: {}", app.edit_buffer)
-				} else {
-					format!("Scheduled: {}", scheduled.raw)
-				};
-
-				lines.push(Line::from(Span::styled(text, style)));
-				field_idx += 1;
-			}
-			if let Some(deadline) = &planning.deadline {
-				let style =
-					if field_idx == app.selected_field_idx && matches!(app.focus, Focus::Right) {
-						Style::default().add_modifier(Modifier::BOLD)
-					} else {
-						Style::default()
-					};
-
-				let text = if matches!(app.edit_mode, EditMode::Deadline) {
-					format!("Deadline: {}", app.edit_buffer)
-				} else {
-					format!("Deadline: {}", deadline.raw)
-				};
-
-				lines.push(Line::from(Span::styled(text, style)));
-				field_idx += 1;
-			}
-			if let Some(closed) = &planning.closed {
-				let style =
-					if field_idx == app.selected_field_idx && matches!(app.focus, Focus::Right) {
-						Style::default().add_modifier(Modifier::BOLD)
-					} else {
-						Style::default()
-					};
-
-				let text = if matches!(app.edit_mode, EditMode::Closed) {
-					format!("Closed: {}", app.edit_buffer)
-				} else {
-					format!("Closed: {}", closed.raw)
-				};
-
-				lines.push(Line::from(Span::styled(text, style)));
-				field_idx += 1;
-			}
-		}
-
-		if let Some(logbook) = &note.logbook {
-			if !logbook.clock_entries.is_empty() {
-				lines.push(Line::from("Time Tracking:"));
-				for entry in &logbook.clock_entries {
-					let style = if field_idx == app.selected_field_idx
-						&& matches!(app.focus, Focus::Right)
-					{
-						Style::default().add_modifier(Modifier::BOLD)
-					} else {
-						Style::default()
-					};
-
-					let duration_text = if let Some(duration) = &entry.duration {
-						format!(" => {}", duration)
-					} else {
-						" (running)".to_string()
-					};
-
-					lines.push(Line::from(Span::styled(
-						format!(
-							"  Clock: {}{}",
-							entry.start.to_datetime_string(),
-							duration_text
-						),
-						style,
-					)));
-					field_idx += 1;
-				}
-
-				let total = logbook.format_total_time();
-				lines.push(Line::from(format!("  Total: {}", total)));
-			}
+		if let Some(logbook) = &note.logbook
+			&& !logbook.clock_entries.is_empty()
+		{
+			let total = logbook.format_total_time();
+			lines.push(Line::from(format!("  Total: {}", total)));
 		}
 
 		let paragraph = Paragraph::new(lines)
@@ -1586,22 +2910,75 @@ fn render_metadata_panel(f: &mut Frame, app: &App, area: Rect) {
 	}
 }
 
+/// Name of the field being edited, shown before the edit buffer in the status bar.
+fn edit_mode_label(mode: &EditMode) -> &'static str {
+	match mode {
+		EditMode::Status => "STATUS",
+		EditMode::Title => "TITLE",
+		EditMode::Labels => "LABELS",
+		EditMode::Scheduled => "SCHEDULED",
+		EditMode::Deadline => "DEADLINE",
+		EditMode::Closed => "CLOSED",
+		EditMode::Content => "CONTENT",
+		EditMode::ClockEntry(_) => "CLOCK",
+		EditMode::GotoPath => "GO TO",
+		EditMode::Capture => "CAPTURE",
+		EditMode::None => "",
+	}
+}
+
+/// Terminal columns `text` takes up: wide characters such as CJK count twice,
+/// combining marks not at all.
+fn display_width(text: &str) -> usize {
+	UnicodeWidthStr::width(text)
+}
+
+/// Columns between tab stops when tabs in content are shown.
+const TAB_WIDTH: usize = 8;
+
+/// `text` with each tab replaced by spaces up to the next tab stop, for display.
+/// Notes keep their tabs; only what is drawn is expanded.
+fn expand_tabs(text: &str) -> String {
+	if !text.contains('\t') {
+		return text.to_string();
+	}
+	let mut expanded = String::with_capacity(text.len());
+	let mut column = 0;
+	for c in text.chars() {
+		match c {
+			'\t' => {
+				let spaces = TAB_WIDTH - column % TAB_WIDTH;
+				expanded.extend(std::iter::repeat_n(' ', spaces));
+				column += spaces;
+			},
+			'\n' => {
+				expanded.push(c);
+				column = 0;
+			},
+			_ => {
+				expanded.push(c);
+				column += UnicodeWidthChar::width(c).unwrap_or(0);
+			},
+		}
+	}
+	expanded
+}
+
+/// Where the cursor goes after typing `buffer`, as (column, row) from the start
+/// of the text, with the column in display columns rather than bytes and tabs
+/// expanded as [`expand_tabs`] shows them.
+fn cursor_offset(buffer: &str) -> (u16, u16) {
+	let last_line = buffer.rsplit('\n').next().unwrap_or_default();
+	let rows = buffer.matches('\n').count();
+	(
+		display_width(&expand_tabs(last_line)).min(u16::MAX as usize) as u16,
+		rows.min(u16::MAX as usize) as u16,
+	)
+}
+
 fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
 	let status_text = if app.edit_mode != EditMode::None {
-		format!(
-			"{}: {}",
-			match app.edit_mode {
-				EditMode::Status => "STATUS",
-				EditMode::Title => "TITLE",
-				EditMode::Labels => "LABELS",
-				EditMode::Scheduled => "SCHEDULED",
-				EditMode::Deadline => "DEADLINE",
-				EditMode::Closed => "CLOSED",
-				EditMode::Content => "CONTENT",
-				EditMode::None => "",
-			},
-			app.edit_buffer
-		)
+		format!("{}: {}", edit_mode_label(&app.edit_mode), app.edit_buffer)
 	} else {
 		app.status_message.clone()
 	};
@@ -1625,35 +3002,37 @@ fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
 
 	// Show cursor when editing non-content fields
 	if app.edit_mode != EditMode::None && !matches!(app.edit_mode, EditMode::Content) {
-		let prefix_len = match app.edit_mode {
-			EditMode::Status => 8,     // "STATUS: ".len()
-			EditMode::Title => 7,      // "TITLE: ".len()
-			EditMode::Labels => 8,     // "LABELS: ".len()
-			EditMode::Scheduled => 11, // "SCHEDULED: ".len()
-			EditMode::Deadline => 10,  // "DEADLINE: ".len()
-			EditMode::Closed => 8,     // "CLOSED: ".len()
-			_ => 0,
-		};
-		let cursor_x = area.x
-			+ 1 + prefix_len
-			+ (app.edit_buffer.len() as u16).min(area.width.saturating_sub(prefix_len + 3));
+		let prefix_len = display_width(edit_mode_label(&app.edit_mode)) as u16 + 2;
+		let (column, _) = cursor_offset(&app.edit_buffer);
+		let cursor_x =
+			area.x + 1 + prefix_len + column.min(area.width.saturating_sub(prefix_len + 3));
 		let cursor_y = area.y + 1;
 		f.set_cursor(cursor_x, cursor_y);
 	}
 }
 
-fn render_content_panel(f: &mut Frame, app: &App, area: Rect) {
+fn render_content_panel(f: &mut Frame, app: &mut App, area: Rect) {
 	let border_style = if matches!(app.focus, Focus::Right) {
 		Style::default().fg(Color::Yellow)
 	} else {
 		Style::default()
 	};
 
+	app.content_viewport = (area.width.saturating_sub(2), area.height.saturating_sub(2));
+
 	if let Some(note) = app.get_selected_note() {
-		let text = if matches!(app.edit_mode, EditMode::Content) {
-			app.edit_buffer.clone()
+		let editing = matches!(app.edit_mode, EditMode::Content);
+		let text = expand_tabs(if editing {
+			&app.edit_buffer
+		} else {
+			&note.content
+		});
+
+		let scroll = if editing {
+			0
 		} else {
-			note.content.clone()
+			let (width, height) = app.content_viewport;
+			clamp_scroll(app.content_scroll, wrapped_line_count(&text, width), height)
 		};
 
 		let paragraph = Paragraph::new(text)
@@ -1663,19 +3042,16 @@ fn render_content_panel(f: &mut Frame, app: &App, area: Rect) {
 					.title("Content")
 					.border_style(border_style),
 			)
-			.wrap(Wrap { trim: true });
+			.wrap(Wrap { trim: true })
+			.scroll((scroll, 0));
 
 		f.render_widget(paragraph, area);
 
 		// Show cursor when editing content
 		if matches!(app.edit_mode, EditMode::Content) && matches!(app.focus, Focus::Right) {
-			let lines: Vec<&str> = app.edit_buffer.lines().collect();
-			let cursor_y = area.y + 1 + (lines.len() as u16).saturating_sub(1);
-			let cursor_x = if let Some(last_line) = lines.last() {
-				area.x + 1 + (last_line.len() as u16).min(area.width.saturating_sub(3))
-			} else {
-				area.x + 1
-			};
+			let (column, row) = cursor_offset(&app.edit_buffer);
+			let cursor_y = area.y + 1 + row;
+			let cursor_x = area.x + 1 + column.min(area.width.saturating_sub(3));
 			f.set_cursor(
 				cursor_x.min(area.x + area.width - 2),
 				cursor_y.min(area.y + area.height - 2),
@@ -1684,13 +3060,13 @@ fn render_content_panel(f: &mut Frame, app: &App, area: Rect) {
 	}
 }
 
-fn main() {
-	let matches = Command::new("rorg")
+fn cli() -> Command {
+	Command::new("rorg")
 		.version("0.1.0")
 		.about("A Rust org-mode file parser")
 		.arg(
 			Arg::new("file")
-				.help("The org-mode file to parse")
+				.help("The org-mode file to parse, or a directory to read every .org file below, at any depth")
 				.required(true)
 				.index(1),
 		)
@@ -1705,8 +3081,8 @@ fn main() {
 			Arg::new("format")
 				.short('f')
 				.long("format")
-				.help("Output format (yaml or json)")
-				.value_parser(["yaml", "json"])
+				.help("Output format (yaml, json, org, markdown or tree)")
+				.value_parser(["yaml", "json", "org", "markdown", "tree"])
 				.default_value("yaml"),
 		)
 		.arg(
@@ -1716,74 +3092,906 @@ fn main() {
 				.help("Show time tracking summary statistics")
 				.action(clap::ArgAction::SetTrue),
 		)
+		.arg(
+			Arg::new("wordcount")
+				.long("wordcount")
+				.help("Show word and character counts per top-level note")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("links")
+				.long("links")
+				.help("List every link with the heading it belongs to instead of the notes")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("grep")
+				.long("grep")
+				.value_name("PATTERN")
+				.help(
+					"List headings whose title or content matches the regular expression PATTERN, ignoring case",
+				)
+				.value_parser(clap::value_parser!(String)),
+		)
+		.arg(
+			Arg::new("case-sensitive")
+				.long("case-sensitive")
+				.help("With --grep, match case exactly")
+				.requires("grep")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("flat")
+				.long("flat")
+				.help("Output a flat list of notes with parent references instead of a tree")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("shallow")
+				.long("shallow")
+				.help("Output only the top-level notes, each with a children_count instead of its children")
+				.conflicts_with("flat")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("sort")
+				.long("sort")
+				.help("Sort top-level notes in the output")
+				.value_parser(["date", "deadline", "scheduled", "title", "priority"]),
+		)
+		.arg(
+			Arg::new("sort-recursive")
+				.long("sort-recursive")
+				.help("With --sort, also sort children at every level")
+				.action(clap::ArgAction::SetTrue)
+				.requires("sort"),
+		)
+		.arg(
+			Arg::new("recursive")
+				.long("recursive")
+				.help("Read .org files in subdirectories too; directories are always read this way")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("todo-only")
+				.long("todo-only")
+				.help("Only output unfinished tasks and their ancestors")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("query")
+				.long("query")
+				.value_name("QUERY")
+				.help("Only output notes matching QUERY, e.g. status:TODO|status:NEXT,!tag:work, and their ancestors")
+				.value_parser(|value: &str| value.parse::<Query>()),
+		)
+		.arg(
+			Arg::new("max-depth")
+				.long("max-depth")
+				.help(
+					"Leave out notes nested deeper than this many levels (1 keeps top-level notes)",
+				)
+				.value_parser(clap::value_parser!(u64).range(1..)),
+		)
+		.arg(
+			Arg::new("count-pruned")
+				.long("count-pruned")
+				.help("With --max-depth, record how many children each pruned note had")
+				.requires("max-depth")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("first")
+				.long("first")
+				.value_name("N")
+				.help("Output only the first N top-level notes, with their children")
+				.value_parser(clap::value_parser!(u64))
+				.conflicts_with("last"),
+		)
+		.arg(
+			Arg::new("last")
+				.long("last")
+				.value_name("N")
+				.help("Output only the last N top-level notes, with their children")
+				.value_parser(clap::value_parser!(u64)),
+		)
+		.arg(
+			Arg::new("tick-rate")
+				.long("tick-rate")
+				.help("Milliseconds the TUI waits for input before refreshing running clocks")
+				.value_parser(clap::value_parser!(u64).range(1..))
+				.default_value("1000"),
+		)
+		.arg(
+			Arg::new("timeline-days")
+				.long("timeline-days")
+				.help("Days the TUI's timeline (t) shows, starting today")
+				.value_parser(clap::value_parser!(u64).range(1..=366))
+				.default_value("14"),
+		)
+		.arg(
+			Arg::new("clock-indent")
+				.long("clock-indent")
+				.help(
+					"Spaces to indent CLOCK lines with when the TUI saves, instead of their note's indentation",
+				)
+				.value_parser(clap::value_parser!(usize)),
+		)
+		.arg(
+			Arg::new("align-tables")
+				.long("align-tables")
+				.help("Re-align table columns when saving or writing org output")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("odd-levels")
+				.long("odd-levels")
+				.help("Write headings with 1, 3, 5, ... stars by depth when saving or writing org output")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("body-indent")
+				.long("body-indent")
+				.help(
+					"Indent planning lines, drawers and content under the heading text, or flush left, when saving or writing org output",
+				)
+				.value_parser(["heading", "flush"]),
+		)
+		.arg(
+			Arg::new("blank-lines")
+				.long("blank-lines")
+				.help("Follow every note with one blank line, or none, when saving or writing org output")
+				.value_parser(["always", "never"]),
+		)
+		.arg(
+			Arg::new("keep-clock-durations")
+				.long("keep-clock-durations")
+				.help(
+					"Keep CLOCK durations as written instead of recomputing them from the timestamps",
+				)
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("status-color")
+				.long("status-color")
+				.value_name("KEYWORD=COLOR")
+				.help("Color for a status keyword in the TUI, e.g. NEXT=blue (repeatable)")
+				.value_parser(parse_status_color)
+				.action(clap::ArgAction::Append),
+		)
+		.arg(
+			Arg::new("vim")
+				.long("vim")
+				.help("Use hjkl to move and switch panels in the TUI")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("config")
+				.long("config")
+				.value_name("PATH")
+				.help("TUI config file (default: ~/.config/rorg/config.toml)"),
+		)
+		.arg(
+			Arg::new("since")
+				.long("since")
+				.value_name("DATE")
+				.help(
+					"With --summary, only count clock entries started on or after DATE (YYYY-MM-DD)",
+				)
+				.value_parser(parse_date_arg),
+		)
+		.arg(
+			Arg::new("until")
+				.long("until")
+				.value_name("DATE")
+				.help(
+					"With --summary, only count clock entries started on or before DATE (YYYY-MM-DD)",
+				)
+				.value_parser(parse_date_arg),
+		)
+		.arg(
+			Arg::new("group")
+				.long("group")
+				.help("With --summary, also break tracked time down by day, ISO week or month")
+				.value_parser(["day", "week", "month"]),
+		)
+		.arg(
+			Arg::new("round")
+				.long("round")
+				.value_name("MINUTES")
+				.help("With --summary, round each clock entry's duration to a multiple of MINUTES")
+				.value_parser(clap::value_parser!(u32).range(1..)),
+		)
+		.arg(
+			Arg::new("round-mode")
+				.long("round-mode")
+				.help("Direction for --round")
+				.value_parser(["nearest", "up", "down"])
+				.default_value("up"),
+		)
+		.arg(
+			Arg::new("time-format")
+				.long("time-format")
+				.help(
+					"How --summary writes times: 50h 0m (hours), 2d 2h 0m (days) or 50:00 (clock)",
+				)
+				.value_parser(["hours", "days", "clock"])
+				.default_value("hours"),
+		)
+		.arg(
+			Arg::new("round-write")
+				.long("round-write")
+				.help("Also write the rounded durations to CLOCK lines when the TUI saves")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("check-overlaps")
+				.long("check-overlaps")
+				.help("List pairs of clock entries whose times overlap instead of the notes")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("exclude")
+				.long("exclude")
+				.value_name("GLOB")
+				.help(
+					"With a directory, skip files and directories whose path below it matches GLOB",
+				)
+				.value_parser(|value: &str| Pattern::new(value).map_err(|err| err.to_string()))
+				.action(clap::ArgAction::Append),
+		)
+		.arg(
+			Arg::new("with-source")
+				.long("with-source")
+				.help("Include each note's source_file and source_line in json and yaml output")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("check")
+				.long("check")
+				.help(
+					"List structural problems instead of the notes, exiting with 2 if there are any",
+				)
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("max-clock-hours")
+				.long("max-clock-hours")
+				.value_name("HOURS")
+				.help("With --check, report clocks that have been running longer than this")
+				.value_parser(clap::value_parser!(u32))
+				.default_value("24"),
+		)
+		.arg(
+			Arg::new("clock-out-all")
+				.long("clock-out-all")
+				.help("Clock out every running clock at the current time and save, without the TUI")
+				.action(clap::ArgAction::SetTrue)
+				.conflicts_with_all(["capture", "diff"]),
+		)
+		.arg(
+			Arg::new("capture")
+				.long("capture")
+				.value_name("TEMPLATE")
+				.help(
+					"Add a note from a capture template in the config file and save, without the TUI",
+				)
+				.conflicts_with("diff"),
+		)
+		.arg(
+			Arg::new("renumber")
+				.long("renumber")
+				.help(
+					"Set every heading's level to one below its parent's, in the output and when the TUI saves",
+				)
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("diff")
+				.long("diff")
+				.help(
+					"Print a diff of what saving the parsed file would change, without writing it",
+				)
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("output")
+				.short('o')
+				.long("output")
+				.value_name("FILE")
+				.help("Write the output to FILE instead of stdout")
+				.value_parser(clap::value_parser!(PathBuf)),
+		)
+		.arg(
+			Arg::new("backup")
+				.long("backup")
+				.help("Copy an existing output file to FILE.bak before overwriting it")
+				.action(clap::ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("goto")
+				.long("goto")
+				.value_name("TITLE")
+				.help("Start the TUI on the first heading with this title, or a heading number or path as for g")
+				.conflicts_with("goto-line"),
+		)
+		.arg(
+			Arg::new("goto-line")
+				.long("goto-line")
+				.value_name("N")
+				.help("Start the TUI on the heading whose section holds line N of the file")
+				.value_parser(clap::value_parser!(u64).range(1..)),
+		)
 		.arg(
 			Arg::new("no-tui")
 				.long("no-tui")
 				.help("Disable TUI interface and use text output")
 				.action(clap::ArgAction::SetTrue),
 		)
-		.get_matches();
+		.arg(
+			Arg::new("watch")
+				.long("watch")
+				.help("Print the output again, or reload the TUI, when the file changes on disk")
+				.action(clap::ArgAction::SetTrue)
+				.conflicts_with_all(["capture", "clock-out-all"]),
+		)
+}
+
+fn main() -> ExitCode {
+	let matches = match cli().try_get_matches() {
+		Ok(matches) => matches,
+		Err(err) => {
+			let _ = err.print();
+			return if err.use_stderr() {
+				ExitCode::from(ERROR_EXIT_CODE)
+			} else {
+				ExitCode::SUCCESS
+			};
+		},
+	};
+	if matches.get_flag("watch") && output_is_watched(&matches) {
+		eprintln!("--watch can't write the output into the file or directory it watches");
+		return ExitCode::from(ERROR_EXIT_CODE);
+	}
+	if matches.get_flag("watch") && matches.get_flag("no-tui") {
+		return watch_output(&matches);
+	}
+	match run(&matches) {
+		Ok(status) => status.into(),
+		Err(message) => {
+			eprintln!("{}", message);
+			ExitCode::from(ERROR_EXIT_CODE)
+		},
+	}
+}
+
+/// Whether writing `-o` would change what `--watch` watches: the input file
+/// itself, or anything below the input directory.
+fn output_is_watched(matches: &ArgMatches) -> bool {
+	let input = Path::new(matches.get_one::<String>("file").unwrap());
+	let Some(output) = matches.get_one::<PathBuf>("output") else {
+		return false;
+	};
+	match (resolved_path(input), resolved_path(output)) {
+		(Some(input), Some(output)) if input.is_dir() => output.starts_with(&input),
+		(Some(input), Some(output)) => output == input,
+		_ => output == input,
+	}
+}
+
+/// `path` with symlinks and `.`/`..` resolved, also for a file that doesn't
+/// exist yet as long as its directory does.
+fn resolved_path(path: &Path) -> Option<PathBuf> {
+	fs::canonicalize(path).ok().or_else(|| {
+		let dir = match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => dir,
+			_ => Path::new("."),
+		};
+		Some(fs::canonicalize(dir).ok()?.join(path.file_name()?))
+	})
+}
+
+/// Prints the output for `matches` again each time the input changes, until
+/// interrupted. Errors such as a half-written file are shown and watching goes on.
+fn watch_output(matches: &ArgMatches) -> ExitCode {
+	let path = Path::new(matches.get_one::<String>("file").unwrap());
+	let mut watch = match FileWatch::new(path) {
+		Ok(watch) => watch,
+		Err(err) => {
+			eprintln!("Error watching '{}': {}", path.display(), err);
+			return ExitCode::from(ERROR_EXIT_CODE);
+		},
+	};
+	loop {
+		if io::stdout().is_terminal() {
+			let _ = execute!(io::stdout(), terminal::Clear(ClearType::All), MoveTo(0, 0));
+		}
+		if let Err(message) = run(matches) {
+			eprintln!("{}", message);
+		}
+		watch.wait();
+	}
+}
 
+/// How a run ended, which scripts see as the exit code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunStatus {
+	/// There was something to output, or the TUI closed normally. Exits with 0.
+	Success,
+	/// Nothing matched, e.g. `--todo-only` left no notes or `--links` found no links.
+	/// Exits with 1.
+	NoMatches,
+	/// `--check` found problems. Exits with 2.
+	Problems,
+}
+
+/// Exit code for bad arguments, files that cannot be read and output that cannot
+/// be written.
+const ERROR_EXIT_CODE: u8 = 3;
+
+impl RunStatus {
+	fn matched(found: bool) -> Self {
+		if found {
+			RunStatus::Success
+		} else {
+			RunStatus::NoMatches
+		}
+	}
+}
+
+impl From<RunStatus> for ExitCode {
+	fn from(status: RunStatus) -> Self {
+		ExitCode::from(match status {
+			RunStatus::Success => 0,
+			RunStatus::NoMatches => 1,
+			RunStatus::Problems => 2,
+		})
+	}
+}
+
+/// Runs the mode selected by the command line.
+fn run(matches: &ArgMatches) -> Result<RunStatus, String> {
 	let file_path = matches.get_one::<String>("file").unwrap();
 	let verbose = matches.get_flag("verbose");
 	let format = matches.get_one::<String>("format").unwrap();
 	let show_summary = matches.get_flag("summary");
+	let show_wordcount = matches.get_flag("wordcount");
+	let list_links = matches.get_flag("links");
+	let check_overlaps = matches.get_flag("check-overlaps");
+	let check = matches.get_flag("check");
+	let flat = matches.get_flag("flat");
+	let shallow = matches.get_flag("shallow");
+	let backup = matches.get_flag("backup");
 	let use_tui = !matches.get_flag("no-tui");
+	let tick_rate = Duration::from_millis(*matches.get_one::<u64>("tick-rate").unwrap());
+	let mut status_styles = StatusStyles::default();
+	let rounding = match matches.get_one::<u32>("round") {
+		Some(&step) => Rounding {
+			step,
+			mode: matches
+				.get_one::<String>("round-mode")
+				.unwrap()
+				.parse()
+				.unwrap(),
+		},
+		None => Rounding::default(),
+	};
+	let serialize_options = SerializeOptions {
+		clock_indent: matches
+			.get_one::<usize>("clock-indent")
+			.map(|&spaces| " ".repeat(spaces)),
+		round_durations: if matches.get_flag("round-write") {
+			rounding
+		} else {
+			Rounding::default()
+		},
+		align_tables: matches.get_flag("align-tables"),
+		odd_levels: matches.get_flag("odd-levels"),
+		indent_body: matches
+			.get_one::<String>("body-indent")
+			.map(|indent| indent == "heading"),
+		blank_line_after_notes: matches
+			.get_one::<String>("blank-lines")
+			.map(|blank| blank == "always"),
+	};
+	let sort_key = matches
+		.get_one::<String>("sort")
+		.map(|key| key.parse::<SortKey>().unwrap());
+	let sort_recursive = matches.get_flag("sort-recursive");
+	let todo_only = matches.get_flag("todo-only");
+	let mut clock_report = ClockReport {
+		range: DateRange {
+			since: matches.get_one::<NaiveDate>("since").copied(),
+			until: matches.get_one::<NaiveDate>("until").copied(),
+		},
+		rounding,
+		group: matches
+			.get_one::<String>("group")
+			.map(|period| period.parse::<ClockPeriod>().unwrap()),
+		keywords: Keywords::default(),
+		time_format: matches
+			.get_one::<String>("time-format")
+			.unwrap()
+			.parse()
+			.unwrap(),
+	};
 
 	if !Path::new(file_path).exists() {
-		eprintln!("Error: File '{}' does not exist", file_path);
-		std::process::exit(1);
+		return Err(format!("Error: File '{}' does not exist", file_path));
 	}
 
-	let content = match fs::read_to_string(file_path) {
-		Ok(content) => content,
-		Err(err) => {
-			eprintln!("Error reading file '{}': {}", file_path, err);
-			std::process::exit(1);
-		},
+	let keep_clock_durations = matches.get_flag("keep-clock-durations");
+	let (mut notes, content) = if Path::new(file_path).is_dir() {
+		let exclude: Vec<Pattern> = matches
+			.get_many::<Pattern>("exclude")
+			.into_iter()
+			.flatten()
+			.cloned()
+			.collect();
+		let files = org_files_in(Path::new(file_path), &exclude)
+			.map_err(|err| format!("Error reading directory '{}': {}", file_path, err))?;
+		let mut notes = Vec::new();
+		for file in files {
+			notes.extend(read_notes(&file, keep_clock_durations, verbose)?);
+		}
+		(notes, None)
+	} else {
+		let content = fs::read_to_string(file_path)
+			.map_err(|err| format!("Error reading file '{}': {}", file_path, err))?;
+		let mut notes = parse_notes(file_path, &content, keep_clock_durations, verbose);
+		if matches.get_flag("with-source") {
+			for note in &mut notes {
+				note.set_source_file(file_path);
+			}
+		}
+		(notes, Some(content))
+	};
+	// --grep points at each heading's line
+	let grep = match matches.get_one::<String>("grep") {
+		Some(pattern) => Some(
+			RegexBuilder::new(pattern)
+				.case_insensitive(!matches.get_flag("case-sensitive"))
+				.build()
+				.map_err(|err| format!("Error: Invalid --grep pattern: {}", err))?,
+		),
+		None => None,
+	};
+	// ... as does --check, and --goto-line finds the heading by them
+	if !matches.get_flag("with-source")
+		&& grep.is_none()
+		&& !check
+		&& !matches.contains_id("goto-line")
+	{
+		clear_source_lines(&mut notes);
+	}
+
+	let renumber = matches.get_flag("renumber");
+	if renumber {
+		renumber_levels(&mut notes);
+	}
+
+	let mut keymap = if matches.get_flag("vim") {
+		Keymap::vim()
+	} else {
+		Keymap::default()
 	};
+	let mut templates = HashMap::new();
+	let config_path = matches
+		.get_one::<String>("config")
+		.map(PathBuf::from)
+		.or_else(default_config_path);
+	if let Some(path) = config_path
+		&& let Ok(text) = fs::read_to_string(&path)
+	{
+		for warning in load_config(
+			&text,
+			&mut keymap,
+			&mut status_styles,
+			&mut templates,
+			&mut clock_report.keywords,
+		) {
+			eprintln!("Warning: {}: {}", path.display(), warning);
+		}
+	}
+	apply_status_colors(&mut status_styles, matches);
+
+	// Only a single file has text before its first heading to write back
+	let preamble = content.as_deref().map_or("", rorg::preamble);
+
+	if let Some(name) = matches.get_one::<String>("capture") {
+		if content.is_none() {
+			return Err("Error: --capture needs a single file".to_string());
+		}
+		let template = templates
+			.get(name)
+			.ok_or_else(|| format!("Error: No capture template '{}'", name))?;
+		capture_note(&mut notes, template, Local::now().naive_local())
+			.map_err(|err| format!("Error: {}", err))?;
+		if renumber {
+			renumber_levels(&mut notes);
+		}
+		write_notes(file_path, preamble, &notes, &serialize_options)?;
+		return Ok(RunStatus::Success);
+	}
+
+	if matches.get_flag("clock-out-all") {
+		if content.is_none() {
+			return Err("Error: --clock-out-all needs a single file".to_string());
+		}
+		let closed = clock_out_all(&mut notes, Local::now().naive_local());
+		if closed > 0 {
+			write_notes(file_path, preamble, &notes, &serialize_options)?;
+		}
+		println!("{}", clocked_out_message(closed));
+		return Ok(RunStatus::matched(closed > 0));
+	}
+
+	if matches.get_flag("diff") {
+		let Some(content) = &content else {
+			return Err("Error: --diff needs a single file".to_string());
+		};
+		let updated = rorg::serialize_document(preamble, &notes, &serialize_options);
+		print!("{}", save_diff(content, &updated, file_path));
+		return Ok(RunStatus::Success);
+	}
+
+	// The TUI saves back to one file, so a directory is always printed
+	if use_tui && content.is_some() {
+		let mut app = App::new(notes, file_path.to_string());
+		app.renumber = renumber;
+		app.tick_rate = tick_rate;
+		app.timeline_days = *matches.get_one::<u64>("timeline-days").unwrap() as usize;
+		app.serialize_options = serialize_options;
+		app.status_styles = status_styles;
+		app.keymap = keymap;
+		app.templates = templates;
+		app.keywords = clock_report.keywords;
+		app.keep_clock_durations = keep_clock_durations;
+		app.disk_hash = content.as_deref().map(content_hash);
+		app.preamble = preamble.to_string();
+		let start = match (
+			matches.get_one::<String>("goto"),
+			matches.get_one::<u64>("goto-line"),
+		) {
+			(Some(title), _) => Some((
+				resolve_goto_title(&app.notes, title),
+				format!("No heading '{}', starting at the top", title.trim()),
+			)),
+			(None, Some(&line)) => Some((
+				resolve_goto_line(&app.notes, line as usize),
+				format!("No heading at line {}, starting at the top", line),
+			)),
+			(None, None) => None,
+		};
+		match start {
+			Some((Some(idx), _)) => app.select_note(idx),
+			Some((None, not_found)) => app.status_message = not_found,
+			None => {},
+		}
+		let watch = if matches.get_flag("watch") {
+			let watch = FileWatch::new(Path::new(file_path))
+				.map_err(|err| format!("Error watching '{}': {}", file_path, err))?;
+			Some(watch)
+		} else {
+			None
+		};
+		run_tui(app, watch).map_err(|e| format!("Error running TUI: {}", e))?;
+		Ok(RunStatus::Success)
+	} else {
+		if todo_only {
+			let keywords = &clock_report.keywords;
+			notes = filter_notes(&notes, &|note: &OrgNote| keywords.is_active_task(note));
+		}
+
+		if let Some(query) = matches.get_one::<Query>("query") {
+			notes = query_notes(&notes, query);
+		}
+
+		if let Some(&max_depth) = matches.get_one::<u64>("max-depth") {
+			prune_depth(
+				&mut notes,
+				max_depth as usize,
+				matches.get_flag("count-pruned"),
+			);
+		}
 
+		if let Some(key) = sort_key {
+			sort_notes(&mut notes, key, sort_recursive);
+		}
+
+		if let Some(&first) = matches.get_one::<u64>("first") {
+			notes.truncate(first as usize);
+		}
+		if let Some(&last) = matches.get_one::<u64>("last") {
+			notes.drain(..notes.len().saturating_sub(last as usize));
+		}
+
+		let mut output = Vec::new();
+		let status = (|| -> io::Result<RunStatus> {
+			if show_summary {
+				print_time_summary(&mut output, &notes, &clock_report)?;
+			}
+
+			if show_wordcount {
+				print_word_count_summary(&mut output, &notes)?;
+			}
+
+			if let Some(pattern) = &grep {
+				let found = grep_notes(&notes, pattern);
+				print_text_matches(&mut output, &found, file_path)?;
+				return Ok(RunStatus::matched(!found.is_empty()));
+			}
+
+			if list_links {
+				print_links(&mut output, &notes)?;
+				let any_links = iter_notes(&notes).any(|(_, note)| !note.links.is_empty());
+				return Ok(RunStatus::matched(any_links));
+			}
+
+			if check_overlaps {
+				let overlaps = find_clock_overlaps(&notes, Local::now().naive_local());
+				print_clock_overlaps(&mut output, &overlaps)?;
+				return Ok(RunStatus::matched(!overlaps.is_empty()));
+			}
+
+			if check {
+				let max_clock_hours = *matches.get_one::<u32>("max-clock-hours").unwrap();
+				let findings = lint_notes(
+					&notes,
+					&clock_report.keywords,
+					Local::now().naive_local(),
+					max_clock_hours,
+				);
+				print_lint_findings(&mut output, &findings, file_path)?;
+				return Ok(if findings.is_empty() {
+					RunStatus::Success
+				} else {
+					RunStatus::Problems
+				});
+			}
+
+			if format == "org" {
+				let org = rorg::serialize_document(preamble, &notes, &serialize_options);
+				output.write_all(org.as_bytes())?;
+			} else if format == "markdown" {
+				output.write_all(rorg::to_markdown(&notes).as_bytes())?;
+			} else if format == "tree" {
+				output.write_all(rorg::render_tree(&notes).as_bytes())?;
+			} else if flat {
+				print_notes(&mut output, &flatten_notes(&notes), format)?;
+			} else if shallow {
+				print_notes(&mut output, &shallow_notes(&notes)?, format)?;
+			} else {
+				print_notes(&mut output, &notes, format)?;
+			}
+			Ok(RunStatus::matched(!notes.is_empty()))
+		})();
+
+		let output_path = matches.get_one::<PathBuf>("output");
+		status
+			.and_then(|status| {
+				emit_output(&output, output_path.map(PathBuf::as_path), backup)?;
+				Ok(status)
+			})
+			.map_err(|err| format!("Error writing output: {}", err))
+	}
+}
+
+/// Saves `notes` over the file they were read from, after its `preamble`.
+fn write_notes(
+	file_path: &str,
+	preamble: &str,
+	notes: &[OrgNote],
+	options: &SerializeOptions,
+) -> Result<(), String> {
+	fs::write(
+		file_path,
+		rorg::serialize_document(preamble, notes, options),
+	)
+	.map_err(|err| format!("Error writing file '{}': {}", file_path, err))
+}
+
+fn clocked_out_message(closed: usize) -> String {
+	match closed {
+		0 => "No running clocks".to_string(),
+		1 => "Clocked out 1 running clock".to_string(),
+		_ => format!("Clocked out {} running clocks", closed),
+	}
+}
+
+/// Every `.org` file below `dir`, sorted by path. Entries whose path relative to
+/// `dir` matches one of `exclude` are skipped, and so is everything below an
+/// excluded directory. Symlinked directories are followed, but each directory
+/// is read only once, so a link back to an ancestor doesn't loop.
+fn org_files_in(dir: &Path, exclude: &[Pattern]) -> io::Result<Vec<PathBuf>> {
+	let mut files = Vec::new();
+	let mut visited = HashSet::new();
+	let mut pending = vec![dir.to_path_buf()];
+	while let Some(current) = pending.pop() {
+		if !visited.insert(fs::canonicalize(&current)?) {
+			continue;
+		}
+		for entry in fs::read_dir(&current)? {
+			let path = entry?.path();
+			let relative = path.strip_prefix(dir).unwrap_or(&path);
+			if exclude.iter().any(|pattern| pattern.matches_path(relative)) {
+				continue;
+			}
+			if path.is_dir() {
+				pending.push(path);
+			} else if path.extension().is_some_and(|extension| extension == "org") {
+				files.push(path);
+			}
+		}
+	}
+	files.sort();
+	Ok(files)
+}
+
+/// Reads and parses one file of a directory, marking every note with the file
+/// it came from.
+fn read_notes(
+	path: &Path,
+	keep_clock_durations: bool,
+	verbose: bool,
+) -> Result<Vec<OrgNote>, String> {
+	let name = path.display().to_string();
+	let content = fs::read_to_string(path)
+		.map_err(|err| format!("Error reading file '{}': {}", name, err))?;
+	let mut notes = parse_notes(&name, &content, keep_clock_durations, verbose);
+	for note in &mut notes {
+		note.set_source_file(&name);
+	}
+	Ok(notes)
+}
+
+fn clear_source_lines(notes: &mut [OrgNote]) {
+	for note in notes {
+		note.source_line = None;
+		clear_source_lines(&mut note.children);
+	}
+}
+
+fn parse_notes(
+	name: &str,
+	content: &str,
+	keep_clock_durations: bool,
+	verbose: bool,
+) -> Vec<OrgNote> {
 	if verbose {
-		eprintln!("Parsing file: {}", file_path);
+		eprintln!("Parsing file: {}", name);
 		eprintln!("File size: {} bytes", content.len());
 		eprintln!("Lines: {}", content.lines().count());
 		eprintln!();
 	}
 
-	let mut parser = OrgParser::new(&content);
-	let notes = parser.parse();
+	let (notes, warnings) = OrgParser::new(content)
+		.preserve_clock_durations(keep_clock_durations)
+		.parse_with_diagnostics();
 
 	if verbose {
 		eprintln!("Found {} top-level notes", notes.len());
+		for warning in &warnings {
+			eprintln!("Warning: {}", warning);
+		}
 		eprintln!();
 	}
+	notes
+}
 
-	if use_tui {
-		if let Err(e) = run_tui(notes, file_path.to_string()) {
-			eprintln!("Error running TUI: {}", e);
-			std::process::exit(1);
-		}
-	} else {
-		if show_summary {
-			print_time_summary(&notes);
-		}
-
-		match format.as_str() {
-			"json" => match serde_json::to_string_pretty(&notes) {
-				Ok(json_output) => println!("{}", json_output),
-				Err(err) => {
-					eprintln!("Error serializing to JSON: {}", err);
-					std::process::exit(1);
-				},
-			},
-			"yaml" => match serde_yaml::to_string(&notes) {
-				Ok(yaml_output) => println!("{}", yaml_output),
-				Err(err) => {
-					eprintln!("Error serializing to YAML: {}", err);
-					std::process::exit(1);
-				},
-			},
-			_ => unreachable!(),
-		}
+/// Writes `output` to `path`, or to stdout when there is none. With `backup`,
+/// a file already at `path` is first copied to the same name with `.bak`
+/// appended.
+fn emit_output(output: &[u8], path: Option<&Path>, backup: bool) -> io::Result<()> {
+	match path {
+		Some(path) => {
+			if backup && path.exists() {
+				let mut backup_path = path.as_os_str().to_owned();
+				backup_path.push(".bak");
+				fs::copy(path, backup_path)?;
+			}
+			fs::write(path, output)
+		},
+		None => io::stdout().write_all(output),
 	}
 }
@@ -1,225 +1,2763 @@
-#[cfg(test)]
-mod tests {
-	use crate::{OrgClockEntry, OrgParser, OrgTimestamp};
+use crate::{
+	ClockPeriod, DEFAULT_ARCHIVE_HEADING, DateRange, Keywords, LintRule, OrgClockEntry, OrgNote,
+	OrgParser, OrgPlanning, OrgTable, OrgTimestamp, ParseWarning, Query, RepeatStyle, RepeatUnit,
+	Repeater, RoundMode, Rounding, SerializeOptions, SortKey, TimeFormat, TimestampKind,
+	agenda_sort_key, align_table, archive_to_heading, blocked_by_order, clock_out_all,
+	clock_totals_by_period, clock_totals_by_tag, compare_efforts, extract_links, extract_tables,
+	filter_notes, find_by_path, find_by_title, find_clock_overlaps, flatten_notes, grep_notes,
+	iter_notes, lint_notes, next_matching, next_todo, parse, parse_relative_date, preamble,
+	prev_todo, prune_depth, query_notes, render_tree, renumber_levels, serialize,
+	serialize_document, serialize_with_options, sort_notes, to_markdown,
+};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use proptest::prelude::*;
+use regex::Regex;
 
-	#[test]
-	fn test_count_asterisks() {
-		let parser = OrgParser::new("");
-
-		assert_eq!(parser.count_asterisks("* Heading"), Some(1));
-		assert_eq!(parser.count_asterisks("** Subheading"), Some(2));
-		assert_eq!(parser.count_asterisks("*** Deep heading"), Some(3));
-		assert_eq!(parser.count_asterisks("  * Indented heading"), Some(1));
-		assert_eq!(parser.count_asterisks("*No space"), None);
-		assert_eq!(parser.count_asterisks("Not a heading"), None);
-		assert_eq!(parser.count_asterisks(""), None);
-	}
+#[test]
+fn test_count_asterisks() {
+	let parser = OrgParser::new("");
 
-	#[test]
-	fn test_parse_header_parts_with_status() {
-		let parser = OrgParser::new("");
+	assert_eq!(parser.count_asterisks("* Heading"), Some(1));
+	assert_eq!(parser.count_asterisks("** Subheading"), Some(2));
+	assert_eq!(parser.count_asterisks("*** Deep heading"), Some(3));
+	assert_eq!(parser.count_asterisks("  * Indented heading"), Some(1));
+	assert_eq!(parser.count_asterisks("*No space"), None);
+	assert_eq!(parser.count_asterisks("Not a heading"), None);
+	assert_eq!(parser.count_asterisks(""), None);
+}
 
-		let (status, title, labels) = parser.parse_header_parts("TODO My task");
-		assert_eq!(status, Some("TODO".to_string()));
-		assert_eq!(title, "My task");
-		assert_eq!(labels, Vec::<String>::new());
-	}
+#[test]
+fn test_parse_header_parts_with_status() {
+	let parser = OrgParser::new("");
 
-	#[test]
-	fn test_parse_header_parts_with_tags() {
-		let parser = OrgParser::new("");
+	let (status, title, labels) = parser.parse_header_parts("TODO My task");
+	assert_eq!(status, Some("TODO".to_string()));
+	assert_eq!(title, "My task");
+	assert_eq!(labels, Vec::<String>::new());
+}
 
-		let (status, title, labels) = parser.parse_header_parts("TODO My task :urgent:important:");
-		assert_eq!(status, Some("TODO".to_string()));
-		assert_eq!(title, "My task");
-		assert_eq!(labels, vec!["urgent".to_string(), "important".to_string()]);
-	}
+#[test]
+fn test_parse_header_parts_with_tags() {
+	let parser = OrgParser::new("");
 
-	#[test]
-	fn test_parse_header_parts_no_status() {
-		let parser = OrgParser::new("");
+	let (status, title, labels) = parser.parse_header_parts("TODO My task :urgent:important:");
+	assert_eq!(status, Some("TODO".to_string()));
+	assert_eq!(title, "My task");
+	assert_eq!(labels, vec!["urgent".to_string(), "important".to_string()]);
+}
+
+#[test]
+fn test_parse_header_parts_no_status() {
+	let parser = OrgParser::new("");
+
+	let (status, title, labels) = parser.parse_header_parts("Just a heading :tag:");
+	assert_eq!(status, None);
+	assert_eq!(title, "Just a heading");
+	assert_eq!(labels, vec!["tag".to_string()]);
+}
+
+#[test]
+fn test_parse_header_parts_no_tags() {
+	let parser = OrgParser::new("");
+
+	let (status, title, labels) = parser.parse_header_parts("DONE Completed task");
+	assert_eq!(status, Some("DONE".to_string()));
+	assert_eq!(title, "Completed task");
+	assert_eq!(labels, Vec::<String>::new());
+}
+
+#[test]
+fn test_parse_header_parts_single_tag_group() {
+	let parser = OrgParser::new("");
+
+	let (status, title, labels) = parser.parse_header_parts("TODO Task :a:b:");
+	assert_eq!(status, Some("TODO".to_string()));
+	assert_eq!(title, "Task");
+	assert_eq!(labels, vec!["a".to_string(), "b".to_string()]);
+}
+
+#[test]
+fn test_parse_header_parts_multiple_tag_groups() {
+	let parser = OrgParser::new("");
+
+	let (status, title, labels) = parser.parse_header_parts("Task :a: :b: ");
+	assert_eq!(status, None);
+	assert_eq!(title, "Task");
+	assert_eq!(labels, vec!["a".to_string(), "b".to_string()]);
+
+	let (_, title, labels) = parser.parse_header_parts("Task\t:a:b:   :c:\t");
+	assert_eq!(title, "Task");
+	assert_eq!(
+		labels,
+		vec!["a".to_string(), "b".to_string(), "c".to_string()]
+	);
+
+	// A trailing category-like group is one more group, and repeats are kept
+	let (_, title, labels) = parser.parse_header_parts("Call Bob :phone:work: :@office: :work:");
+	assert_eq!(title, "Call Bob");
+	assert_eq!(labels, vec!["phone", "work", "@office", "work"]);
+	// Only the groups at the very end are tags
+	let (_, title, labels) = parser.parse_header_parts("Fix :a: in docs :b:");
+	assert_eq!(title, "Fix :a: in docs");
+	assert_eq!(labels, vec!["b"]);
+}
+
+#[test]
+fn test_parse_header_parts_colon_word_is_not_a_tag() {
+	let parser = OrgParser::new("");
+
+	let (_, title, labels) = parser.parse_header_parts("Agenda: discuss plans");
+	assert_eq!(title, "Agenda: discuss plans");
+	assert_eq!(labels, Vec::<String>::new());
+
+	let (_, title, labels) = parser.parse_header_parts("Read chapter :intro: notes:");
+	assert_eq!(title, "Read chapter :intro: notes:");
+	assert_eq!(labels, Vec::<String>::new());
+
+	let (_, title, labels) = parser.parse_header_parts("Fix :bad tag: :ok:");
+	assert_eq!(title, "Fix :bad tag:");
+	assert_eq!(labels, vec!["ok".to_string()]);
+}
 
-		let (status, title, labels) = parser.parse_header_parts("Just a heading :tag:");
-		assert_eq!(status, None);
-		assert_eq!(title, "Just a heading");
-		assert_eq!(labels, vec!["tag".to_string()]);
+#[test]
+fn test_parse_header_parts_status_only_and_empty() {
+	let parser = OrgParser::new("");
+
+	let (status, title, labels) = parser.parse_header_parts("TODO");
+	assert_eq!(status, Some("TODO".to_string()));
+	assert_eq!(title, "");
+	assert!(labels.is_empty());
+
+	let (status, title, labels) = parser.parse_header_parts("DONE :work:");
+	assert_eq!(status, Some("DONE".to_string()));
+	assert_eq!(title, "");
+	assert_eq!(labels, vec!["work".to_string()]);
+
+	for header in ["", "   ", ":a:b:"] {
+		let (status, title, _) = parser.parse_header_parts(header);
+		assert_eq!(status, None, "{:?}", header);
+		assert_eq!(title, "", "{:?}", header);
 	}
 
-	#[test]
-	fn test_parse_header_parts_no_tags() {
-		let parser = OrgParser::new("");
+	let notes = parse("* TODO\n* \n** [#A]\n");
+	assert_eq!(notes[0].status.as_deref(), Some("TODO"));
+	assert_eq!(notes[0].title, "");
+	assert_eq!(notes[1].title, "");
+	assert_eq!(notes[1].children[0].priority, Some('A'));
+	assert_eq!(serialize(&notes), "* TODO\n* \n** [#A]\n");
+}
+
+#[test]
+fn test_parse_header_parts_colons_in_titles() {
+	let parser = OrgParser::new("");
 
-		let (status, title, labels) = parser.parse_header_parts("DONE Completed task");
-		assert_eq!(status, Some("DONE".to_string()));
-		assert_eq!(title, "Completed task");
-		assert_eq!(labels, Vec::<String>::new());
+	// Times and ratios contain colons but are never tag groups
+	for header in [
+		"Meeting 3:30 with team",
+		"Meeting at 15:30:",
+		"Mix ratio 1:2:1",
+		"Deploy at 10:00 :",
+		"Progress 50%:",
+	] {
+		let (status, title, labels) = parser.parse_header_parts(header);
+		assert_eq!(status, None, "{}", header);
+		assert_eq!(title, header.trim(), "{}", header);
+		assert_eq!(labels, Vec::<String>::new(), "{}", header);
 	}
 
-	#[test]
-	fn test_parse_simple_org_content() {
-		let content = r#"* TODO First task
+	// Malformed groups are kept as part of the title
+	for header in ["Broken ::", "Broken :a::b:", "Broken :a b:", "Broken :a!:"] {
+		let (_, title, labels) = parser.parse_header_parts(header);
+		assert_eq!(title, header, "{}", header);
+		assert_eq!(labels, Vec::<String>::new(), "{}", header);
+	}
+}
+
+#[test]
+fn test_parse_header_parts_genuine_tags_after_colons() {
+	let parser = OrgParser::new("");
+
+	let (status, title, labels) = parser.parse_header_parts("TODO Meeting 3:30 with team :work:");
+	assert_eq!(status, Some("TODO".to_string()));
+	assert_eq!(title, "Meeting 3:30 with team");
+	assert_eq!(labels, vec!["work".to_string()]);
+
+	let (_, title, labels) = parser.parse_header_parts("Ratio 1:2 :math:50%:");
+	assert_eq!(title, "Ratio 1:2");
+	assert_eq!(labels, vec!["math".to_string(), "50%".to_string()]);
+
+	let (_, title, labels) = parser.parse_header_parts("Call @home :@home:phone_call:#x:");
+	assert_eq!(title, "Call @home");
+	assert_eq!(
+		labels,
+		vec![
+			"@home".to_string(),
+			"phone_call".to_string(),
+			"#x".to_string()
+		]
+	);
+}
+
+#[test]
+fn test_parse_simple_org_content() {
+	let content = r#"* TODO First task
 Some content here.
 ** DONE Subtask :work:
 Subtask content.
 * CANCELLED Another task :cancelled:
 Final content."#;
 
-		let mut parser = OrgParser::new(content);
-		let notes = parser.parse();
-
-		assert_eq!(notes.len(), 2);
-
-		// First note
-		assert_eq!(notes[0].level, 1);
-		assert_eq!(notes[0].status, Some("TODO".to_string()));
-		assert_eq!(notes[0].title, "First task");
-		assert_eq!(notes[0].labels, Vec::<String>::new());
-		assert_eq!(notes[0].content, "Some content here.");
-		assert_eq!(notes[0].children.len(), 1);
-
-		// Child note
-		assert_eq!(notes[0].children[0].level, 2);
-		assert_eq!(notes[0].children[0].status, Some("DONE".to_string()));
-		assert_eq!(notes[0].children[0].title, "Subtask");
-		assert_eq!(notes[0].children[0].labels, vec!["work".to_string()]);
-		assert_eq!(notes[0].children[0].content, "Subtask content.");
-
-		// Second note
-		assert_eq!(notes[1].level, 1);
-		assert_eq!(notes[1].status, Some("CANCELLED".to_string()));
-		assert_eq!(notes[1].title, "Another task");
-		assert_eq!(notes[1].labels, vec!["cancelled".to_string()]);
-		assert_eq!(notes[1].content, "Final content.");
-	}
+	let mut parser = OrgParser::new(content);
+	let notes = parser.parse();
 
-	#[test]
-	fn test_parse_timestamp() {
-		let parser = OrgParser::new("");
-
-		let timestamp = parser
-			.parse_timestamp_from_text("[2024-01-01 Mon 10:30]")
-			.unwrap();
-		assert_eq!(timestamp.year, 2024);
-		assert_eq!(timestamp.month, 1);
-		assert_eq!(timestamp.day, 1);
-		assert_eq!(timestamp.hour, Some(10));
-		assert_eq!(timestamp.minute, Some(30));
-		assert_eq!(timestamp.day_name, Some("Mon".to_string()));
-
-		let timestamp2 = parser
-			.parse_timestamp_from_text("<2023-12-25 Mon>")
-			.unwrap();
-		assert_eq!(timestamp2.year, 2023);
-		assert_eq!(timestamp2.month, 12);
-		assert_eq!(timestamp2.day, 25);
-		assert_eq!(timestamp2.hour, None);
-		assert_eq!(timestamp2.minute, None);
-	}
+	assert_eq!(notes.len(), 2);
 
-	#[test]
-	fn test_parse_planning_keywords() {
-		let content = r#"* TODO Task with planning
+	// First note
+	assert_eq!(notes[0].level, 1);
+	assert_eq!(notes[0].status, Some("TODO".to_string()));
+	assert_eq!(notes[0].title, "First task");
+	assert_eq!(notes[0].labels, Vec::<String>::new());
+	assert_eq!(notes[0].content, "Some content here.");
+	assert_eq!(notes[0].children.len(), 1);
+
+	// Child note
+	assert_eq!(notes[0].children[0].level, 2);
+	assert_eq!(notes[0].children[0].status, Some("DONE".to_string()));
+	assert_eq!(notes[0].children[0].title, "Subtask");
+	assert_eq!(notes[0].children[0].labels, vec!["work".to_string()]);
+	assert_eq!(notes[0].children[0].content, "Subtask content.");
+
+	// Second note
+	assert_eq!(notes[1].level, 1);
+	assert_eq!(notes[1].status, Some("CANCELLED".to_string()));
+	assert_eq!(notes[1].title, "Another task");
+	assert_eq!(notes[1].labels, vec!["cancelled".to_string()]);
+	assert_eq!(notes[1].content, "Final content.");
+}
+
+#[test]
+fn test_parse_timestamp() {
+	let parser = OrgParser::new("");
+
+	let timestamp = parser
+		.parse_timestamp_from_text("[2024-01-01 Mon 10:30]")
+		.unwrap();
+	assert_eq!(timestamp.year, 2024);
+	assert_eq!(timestamp.month, 1);
+	assert_eq!(timestamp.day, 1);
+	assert_eq!(timestamp.hour, Some(10));
+	assert_eq!(timestamp.minute, Some(30));
+	assert_eq!(timestamp.day_name, Some("Mon".to_string()));
+
+	let timestamp2 = parser
+		.parse_timestamp_from_text("<2023-12-25 Mon>")
+		.unwrap();
+	assert_eq!(timestamp2.year, 2023);
+	assert_eq!(timestamp2.month, 12);
+	assert_eq!(timestamp2.day, 25);
+	assert_eq!(timestamp2.hour, None);
+	assert_eq!(timestamp2.minute, None);
+}
+
+#[test]
+fn test_parse_planning_keywords() {
+	let content = r#"* TODO Task with planning
 SCHEDULED: <2024-01-01 Mon 09:00>
 DEADLINE: <2024-01-10 Wed>
 Some content here."#;
 
-		let mut parser = OrgParser::new(content);
-		let notes = parser.parse();
+	let mut parser = OrgParser::new(content);
+	let notes = parser.parse();
 
-		assert_eq!(notes.len(), 1);
-		let note = &notes[0];
+	assert_eq!(notes.len(), 1);
+	let note = &notes[0];
 
-		assert!(note.planning.is_some());
-		let planning = note.planning.as_ref().unwrap();
+	assert!(note.planning.is_some());
+	let planning = note.planning.as_ref().unwrap();
 
-		assert!(planning.scheduled.is_some());
-		assert_eq!(planning.scheduled.as_ref().unwrap().year, 2024);
-		assert_eq!(planning.scheduled.as_ref().unwrap().hour, Some(9));
+	assert!(planning.scheduled.is_some());
+	assert_eq!(planning.scheduled.as_ref().unwrap().year, 2024);
+	assert_eq!(planning.scheduled.as_ref().unwrap().hour, Some(9));
 
-		assert!(planning.deadline.is_some());
-		assert_eq!(planning.deadline.as_ref().unwrap().month, 1);
-		assert_eq!(planning.deadline.as_ref().unwrap().day, 10);
-	}
+	assert!(planning.deadline.is_some());
+	assert_eq!(planning.deadline.as_ref().unwrap().month, 1);
+	assert_eq!(planning.deadline.as_ref().unwrap().day, 10);
+}
 
-	#[test]
-	fn test_parse_logbook() {
-		let content = r#"* DONE Task with time tracking
+#[test]
+fn test_parse_logbook() {
+	let content = r#"* DONE Task with time tracking
 :LOGBOOK:
 CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 12:00] =>  3:00
 CLOCK: [2024-01-02 Tue 14:00]--[2024-01-02 Tue 16:30] =>  2:30
 :END:
 Task completed with time tracking."#;
 
-		let mut parser = OrgParser::new(content);
-		let notes = parser.parse();
+	let mut parser = OrgParser::new(content);
+	let notes = parser.parse();
 
-		assert_eq!(notes.len(), 1);
-		let note = &notes[0];
+	assert_eq!(notes.len(), 1);
+	let note = &notes[0];
 
-		assert!(note.logbook.is_some());
-		let logbook = note.logbook.as_ref().unwrap();
+	assert!(note.logbook.is_some());
+	let logbook = note.logbook.as_ref().unwrap();
 
-		assert_eq!(logbook.clock_entries.len(), 2);
-		assert_eq!(logbook.clock_entries[0].duration, Some("3:00".to_string()));
-		assert_eq!(logbook.clock_entries[1].duration, Some("2:30".to_string()));
+	assert_eq!(logbook.clock_entries.len(), 2);
+	assert_eq!(logbook.clock_entries[0].duration, Some("3:00".to_string()));
+	assert_eq!(logbook.clock_entries[1].duration, Some("2:30".to_string()));
 
-		// Content should not include logbook
-		assert_eq!(note.content, "Task completed with time tracking.");
+	// Content should not include logbook
+	assert_eq!(note.content, "Task completed with time tracking.");
 
-		// Test total time calculation
-		assert_eq!(logbook.total_minutes(), 330); // 3:00 + 2:30 = 5:30 = 330 minutes
-		assert_eq!(logbook.format_total_time(), "5h 30m");
-	}
+	// Test total time calculation
+	assert_eq!(logbook.total_minutes(), 330); // 3:00 + 2:30 = 5:30 = 330 minutes
+	assert_eq!(logbook.format_total_time(), "5h 30m");
+}
 
-	#[test]
-	fn test_timestamp_formatting() {
-		let timestamp = OrgTimestamp {
+#[test]
+fn test_timestamp_formatting() {
+	let timestamp = OrgTimestamp {
+		year: 2024,
+		month: 1,
+		day: 15,
+		hour: Some(14),
+		minute: Some(30),
+		day_name: Some("Mon".to_string()),
+		raw: "[2024-01-15 Mon 14:30]".to_string(),
+	};
+
+	assert_eq!(timestamp.to_date_string(), "2024-01-15");
+	assert_eq!(timestamp.to_datetime_string(), "2024-01-15 14:30");
+}
+
+#[test]
+fn test_duration_parsing() {
+	let clock_entry = OrgClockEntry {
+		start: OrgTimestamp {
 			year: 2024,
 			month: 1,
-			day: 15,
-			hour: Some(14),
-			minute: Some(30),
+			day: 1,
+			hour: Some(9),
+			minute: Some(0),
 			day_name: Some("Mon".to_string()),
-			raw: "[2024-01-15 Mon 14:30]".to_string(),
-		};
+			raw: "[2024-01-01 Mon 09:00]".to_string(),
+		},
+		end: None,
+		duration: Some("2:30".to_string()),
+		raw: "CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 11:30] =>  2:30".to_string(),
+	};
+
+	assert_eq!(clock_entry.parse_duration_minutes(), Some(150)); // 2:30 = 150 minutes
+	assert_eq!(clock_entry.format_duration(), "2:30 (150 minutes)");
+}
+
+#[test]
+fn test_parse_empty_content() {
+	let mut parser = OrgParser::new("");
+	let notes = parser.parse();
+	assert_eq!(notes.len(), 0);
+}
+
+#[test]
+fn test_parse_no_headings() {
+	let content = "Just some text\nwithout any headings\nat all.";
+	let mut parser = OrgParser::new(content);
+	let notes = parser.parse();
+	assert_eq!(notes.len(), 0);
+}
+
+#[test]
+fn test_warning_for_unterminated_logbook() {
+	let content = r#"* TODO Task
+Intro line.
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00"#;
+
+	let (notes, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	assert_eq!(notes.len(), 1);
+	assert_eq!(
+		warnings,
+		vec![ParseWarning {
+			line: 3,
+			message: "unterminated LOGBOOK drawer".to_string(),
+		}]
+	);
+	assert_eq!(
+		warnings[0].to_string(),
+		"line 3: unterminated LOGBOOK drawer"
+	);
+}
+
+#[test]
+fn test_warning_for_unparseable_planning_line() {
+	let content = r#"* TODO Task
+** Child
+SCHEDULED: sometime next week
+DEADLINE: <2024-01-10 Wed>"#;
+
+	let (notes, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	let child = &notes[0].children[0];
+	assert!(child.planning.as_ref().unwrap().scheduled.is_none());
+	assert!(child.planning.as_ref().unwrap().deadline.is_some());
+
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(warnings[0].line, 3);
+	assert!(warnings[0].message.contains("SCHEDULED:"));
+}
+
+#[test]
+fn test_no_warnings_for_well_formed_input() {
+	let content = r#"* DONE Task
+CLOSED: [2024-01-01 Mon 10:00]
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+:END:
+Body"#;
+
+	let (_, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_truncated_logbook_keeps_clock_entries() {
+	let content = r#"* TODO Task
+Intro line.
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+CLOCK: [2024-01-02 Tue 09:00]--[2024-01-02 Tue 09:30] =>  0:30
+* Next heading"#;
+
+	let (notes, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	assert_eq!(notes.len(), 2);
+	assert_eq!(warnings.len(), 1);
+
+	let logbook = notes[0].logbook.as_ref().unwrap();
+	assert_eq!(logbook.clock_entries.len(), 2);
+	assert_eq!(logbook.raw_content.len(), 2);
+	assert_eq!(logbook.total_minutes(), 90);
+	assert_eq!(notes[0].content, "Intro line.");
+}
+
+#[test]
+fn test_custom_drawer_round_trip() {
+	let content = r#"* TODO Task
+SCHEDULED: <2024-01-20 Sat 09:00>
+:NOTES:
+Remember the milk.
+  - indented item
+:END:
+Body text.
+"#;
+
+	let notes = parse(content);
+	let note = &notes[0];
+	assert_eq!(note.drawers.len(), 1);
+	assert_eq!(note.drawers[0].name, "NOTES");
+	assert_eq!(
+		note.drawers[0].lines,
+		vec![
+			"Remember the milk.".to_string(),
+			"  - indented item".to_string()
+		]
+	);
+	assert_eq!(note.content, "Body text.");
+
+	let serialized = serialize(&notes);
+	assert!(serialized.contains(":NOTES:\nRemember the milk.\n  - indented item\n:END:\n"));
+
+	let reparsed = parse(&serialized);
+	assert_eq!(reparsed[0].drawers[0].name, "NOTES");
+	assert_eq!(reparsed[0].drawers[0].lines, note.drawers[0].lines);
+	assert_eq!(reparsed[0].content, "Body text.");
+}
+
+#[test]
+fn test_unclosed_drawer_like_line_stays_in_content() {
+	let notes = parse("* Task\n:not_a_drawer:\nMore text.");
+	assert!(notes[0].drawers.is_empty());
+	assert_eq!(notes[0].content, ":not_a_drawer:\nMore text.");
+}
+
+#[test]
+fn test_round_trip_preserves_block_order() {
+	let content = r#"* DONE Task :work:
+Notes written before the clock.
+
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+:END:
+CLOSED: [2024-01-01 Mon 10:00]
+More notes after.
+
+** Child
+:PROPERTIES:
+:ID: abc
+:END:
+SCHEDULED: <2024-01-20 Sat 09:00>
+Child body.
+* Second
+"#;
+
+	let notes = parse(content);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_serialize_places_new_blocks_before_parsed_layout() {
+	let mut notes = parse("* TODO Task\nBody.\n");
+	notes[0].planning = OrgParser::new("")
+		.parse_timestamp_from_text("<2024-01-20 Sat>")
+		.map(|deadline| OrgPlanning {
+			deadline: Some(deadline),
+			..Default::default()
+		});
+
+	assert_eq!(
+		serialize(&notes),
+		"* TODO Task\nDEADLINE: <2024-01-20 Sat>\nBody.\n"
+	);
+}
+
+fn titles(notes: &[crate::OrgNote]) -> Vec<&str> {
+	notes.iter().map(|note| note.title.as_str()).collect()
+}
+
+#[test]
+fn test_sort_by_deadline_puts_missing_deadlines_last() {
+	let content = r#"* No deadline
+* Later
+DEADLINE: <2024-03-01 Fri>
+* Also none
+* Sooner
+DEADLINE: <2024-01-15 Mon 10:00>
+* Same day earlier time
+DEADLINE: <2024-01-15 Mon 08:00>
+"#;
+
+	let mut notes = parse(content);
+	sort_notes(&mut notes, SortKey::Deadline, false);
+
+	assert_eq!(
+		titles(&notes),
+		vec![
+			"Same day earlier time",
+			"Sooner",
+			"Later",
+			"Also none",
+			"No deadline"
+		]
+	);
+}
+
+#[test]
+fn test_sort_recursive_sorts_children() {
+	let content = r#"* Beta parent
+** Child late
+SCHEDULED: <2024-02-01 Thu>
+** Child none
+** Child early
+SCHEDULED: <2024-01-01 Mon>
+* alpha parent
+"#;
+
+	let mut notes = parse(content);
+	sort_notes(&mut notes, SortKey::Title, false);
+	assert_eq!(titles(&notes), vec!["alpha parent", "Beta parent"]);
+	assert_eq!(
+		titles(&notes[1].children),
+		vec!["Child late", "Child none", "Child early"]
+	);
+
+	sort_notes(&mut notes, SortKey::Scheduled, true);
+	assert_eq!(
+		titles(&notes[1].children),
+		vec!["Child early", "Child late", "Child none"]
+	);
+}
+
+#[test]
+fn test_sort_by_priority() {
+	let mut notes = parse("* TODO Plain\n* TODO [#C] Low\n* TODO [#A] High\n* TODO [#B] Mid\n");
+	sort_notes(&mut notes, SortKey::Priority, false);
+
+	assert_eq!(titles(&notes), vec!["High", "Mid", "Low", "Plain"]);
+
+	notes.push(OrgNote::builder(1, "Numbered").priority('1').build());
+	sort_notes(&mut notes, SortKey::Priority, false);
+	assert_eq!(
+		titles(&notes),
+		vec!["High", "Mid", "Low", "Numbered", "Plain"]
+	);
+}
+
+#[test]
+fn test_parse_priority_cookie() {
+	let notes = parse(
+		"* TODO [#B] Write report :work:
+* [#A] Untracked
+* TODO [#AB] Not a cookie
+",
+	);
+
+	assert_eq!(notes[0].status.as_deref(), Some("TODO"));
+	assert_eq!(notes[0].priority, Some('B'));
+	assert_eq!(notes[0].title, "Write report");
+	assert_eq!(notes[1].status, None);
+	assert_eq!(notes[1].priority, Some('A'));
+	assert_eq!(notes[1].title, "Untracked");
+	assert_eq!(notes[2].priority, None);
+	assert_eq!(notes[2].title, "[#AB] Not a cookie");
+
+	let output = serialize(&notes);
+	assert!(output.contains("* TODO [#B] Write report :work:\n"));
+	assert!(output.contains("* [#A] Untracked\n"));
+}
+
+#[test]
+fn test_same_day_items_sort_by_priority_then_title() {
+	let content = r#"* TODO zeta none
+DEADLINE: <2024-01-15 Mon 08:00>
+* TODO [#B] later day
+DEADLINE: <2024-01-16 Tue>
+* TODO [#B] beta
+SCHEDULED: <2024-01-15 Mon 17:00>
+* TODO [#A] alpha
+DEADLINE: <2024-01-15 Mon 18:00>
+* TODO [#A] undated
+"#;
+
+	let mut notes = parse(content);
+	sort_notes(&mut notes, SortKey::Date, false);
+	assert_eq!(
+		titles(&notes),
+		vec!["alpha", "beta", "zeta none", "later day", "undated"]
+	);
+	assert!(agenda_sort_key(&notes[0]) < agenda_sort_key(&notes[1]));
+
+	sort_notes(&mut notes, SortKey::Deadline, false);
+	assert_eq!(
+		titles(&notes),
+		vec!["alpha", "zeta none", "later day", "undated", "beta"]
+	);
+}
+
+#[test]
+fn test_filter_active_tasks_keeps_ancestors() {
+	let content = r#"* Project
+** DONE Finished step
+** TODO Next step
+* DONE Old project
+** Notes
+* CANCELLED Dropped
+"#;
+
+	let notes = parse(content);
+	let keywords = Keywords::default();
+	let filtered = filter_notes(&notes, &|note: &crate::OrgNote| {
+		keywords.is_active_task(note)
+	});
+
+	assert_eq!(titles(&filtered), vec!["Project"]);
+	assert_eq!(titles(&filtered[0].children), vec!["Next step"]);
+}
+
+#[test]
+fn test_word_and_char_counts() {
+	let content = r#"* Chapter
+DEADLINE: <2024-01-20 Sat>
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00
+:END:
+First paragraph has five words.
+
+- Second paragraph, a list
+#+BEGIN_QUOTE
+Quoted.
+#+END_QUOTE
+** Section
+Two words.
+"#;
+
+	let notes = parse(content);
+	let chapter = &notes[0];
+
+	assert_eq!(chapter.word_count(), 10);
+	assert_eq!(chapter.char_count(), 31 + 26 + 7);
+	assert_eq!(chapter.total_word_count(), 12);
+	assert_eq!(chapter.total_char_count(), 31 + 26 + 7 + 10);
+}
+
+#[test]
+fn test_extract_links() {
+	let links = extract_links(
+		"See [[https://orgmode.org][the org manual]] and [[file:notes.org]].\n\
+		 Also https://example.com/page, or (http://example.org).",
+	);
+
+	let found: Vec<(&str, Option<&str>)> = links
+		.iter()
+		.map(|link| (link.target.as_str(), link.description.as_deref()))
+		.collect();
+	assert_eq!(
+		found,
+		vec![
+			("https://orgmode.org", Some("the org manual")),
+			("file:notes.org", None),
+			("https://example.com/page", None),
+			("http://example.org", None),
+		]
+	);
+}
+
+#[test]
+fn test_links_are_collected_without_touching_content() {
+	let content = "* Reading\nStart at [[https://example.com][Example site]].\n";
+	let notes = parse(content);
+
+	assert_eq!(notes[0].links.len(), 1);
+	assert_eq!(
+		notes[0].links[0].description.as_deref(),
+		Some("Example site")
+	);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_flatten_notes_references_parents() {
+	let content = r#"* First
+** Child one
+** Child two
+* Second
+** Child three
+"#;
+
+	let flat = flatten_notes(&parse(content));
+
+	assert_eq!(flat.len(), 5);
+	assert!(flat.iter().all(|entry| entry.note.children.is_empty()));
+	let parents: Vec<(&str, Option<usize>, Option<&str>)> = flat
+		.iter()
+		.map(|entry| {
+			(
+				entry.note.title.as_str(),
+				entry.parent_index,
+				entry.parent_title.as_deref(),
+			)
+		})
+		.collect();
+	assert_eq!(
+		parents,
+		vec![
+			("First", None, None),
+			("Child one", Some(0), Some("First")),
+			("Child two", Some(0), Some("First")),
+			("Second", None, None),
+			("Child three", Some(3), Some("Second")),
+		]
+	);
+}
+
+#[test]
+fn test_category_is_inherited_unless_overridden() {
+	let content = r#"* Work
+:PROPERTIES:
+:CATEGORY: work
+:END:
+** Project
+*** TODO Task
+** Errands
+:PROPERTIES:
+:category: home
+:END:
+*** TODO Post letter
+* Loose
+"#;
+	let notes = parse(content);
+
+	assert_eq!(notes[0].category(None).as_deref(), Some("work"));
+	assert_eq!(
+		notes[0].children[0].category(Some("work")).as_deref(),
+		Some("work")
+	);
+	assert_eq!(
+		notes[0].children[1].category(Some("work")).as_deref(),
+		Some("home")
+	);
+	assert_eq!(notes[1].category(None), None);
+
+	let flat = flatten_notes(&notes);
+	let categories: Vec<(&str, Option<&str>)> = flat
+		.iter()
+		.map(|entry| (entry.note.title.as_str(), entry.category.as_deref()))
+		.collect();
+	assert_eq!(
+		categories,
+		vec![
+			("Work", Some("work")),
+			("Project", Some("work")),
+			("Task", Some("work")),
+			("Errands", Some("home")),
+			("Post letter", Some("home")),
+			("Loose", None),
+		]
+	);
+}
+
+#[test]
+fn test_status_only_headings_round_trip() {
+	let content = "* DONE\n* TODO :tag:\n** \n";
+	let notes = parse(content);
+
+	assert_eq!(notes[0].status.as_deref(), Some("DONE"));
+	assert_eq!(notes[0].title, "");
+	assert_eq!(notes[0].heading_text(), "DONE");
+	assert_eq!(notes[1].status.as_deref(), Some("TODO"));
+	assert_eq!(notes[1].title, "");
+	assert_eq!(notes[1].labels, vec!["tag"]);
+
+	let serialized = serialize(&notes);
+	assert_eq!(serialized, content);
+	assert_eq!(serialize(&parse(&serialized)), serialized);
+}
+
+#[test]
+fn test_invalid_clock_entries_are_excluded_from_totals() {
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00
+CLOCK: [2024-01-15 Mon 14:00]--[2024-01-15 Mon 12:00] =>  2:00
+CLOCK: [2024-01-16 Tue 09:00]
+:END:
+"#;
+
+	let (notes, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	let logbook = notes[0].logbook.as_ref().unwrap();
+
+	let valid: Vec<bool> = logbook.clock_entries.iter().map(|e| e.is_valid()).collect();
+	assert_eq!(valid, vec![true, false, true]);
+	assert_eq!(logbook.total_minutes(), 60);
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(warnings[0].line, 4);
+	// The entry is kept so the file is written back unchanged
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_clock_minutes_in_date_range() {
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2023-12-31 Sun 23:00]--[2024-01-01 Mon 01:00] =>  2:00
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+CLOCK: [2024-01-31 Wed 09:00]--[2024-01-31 Wed 09:30] =>  0:30
+CLOCK: [2024-02-01 Thu 09:00]--[2024-02-01 Thu 13:00] =>  4:00
+CLOCK: [2024-02-30 Fri 09:00]--[2024-02-30 Fri 10:00] =>  1:00
+:END:
+"#;
+
+	let notes = parse(content);
+	let logbook = notes[0].logbook.as_ref().unwrap();
+	let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+	let january = DateRange {
+		since: Some(date("2024-01-01")),
+		until: Some(date("2024-01-31")),
+	};
+	assert_eq!(logbook.minutes_in(&january, &Rounding::default()), 90);
+
+	let from_february = DateRange {
+		since: Some(date("2024-02-01")),
+		until: None,
+	};
+	assert_eq!(
+		logbook.minutes_in(&from_february, &Rounding::default()),
+		240
+	);
+	assert_eq!(
+		logbook.minutes_in(&DateRange::default(), &Rounding::default()),
+		logbook.total_minutes()
+	);
+	assert_eq!(logbook.total_minutes(), 450);
+}
+
+#[test]
+fn test_clock_entries_and_minutes_on_a_date() {
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2024-01-02 Tue 08:00]
+CLOCK: [2024-01-01 Mon 23:00]--[2024-01-02 Tue 01:00] =>  2:00
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:30] =>  1:30
+CLOCK: [2024-01-01 Mon 14:00]--[2024-01-01 Mon 12:00] =>  2:00
+:END:
+"#;
+
+	let notes = parse(content);
+	let logbook = notes[0].logbook.as_ref().unwrap();
+	let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+	let starts = |day| {
+		logbook
+			.entries_on(date(day))
+			.iter()
+			.map(|entry| {
+				entry
+					.start
+					.to_naive_datetime()
+					.unwrap()
+					.format("%H:%M")
+					.to_string()
+			})
+			.collect::<Vec<_>>()
+	};
+	// The entry crossing midnight counts on the day it started, the
+	// backwards one on no day
+	assert_eq!(starts("2024-01-01"), vec!["23:00", "09:00"]);
+	assert_eq!(logbook.minutes_on(date("2024-01-01")), 210);
+	// A running clock is listed but adds no time yet
+	assert_eq!(starts("2024-01-02"), vec!["08:00"]);
+	assert_eq!(logbook.minutes_on(date("2024-01-02")), 0);
+	assert!(logbook.entries_on(date("2024-01-03")).is_empty());
+}
+
+#[test]
+fn test_stale_clock_durations_are_recomputed() {
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:30] =>  3:00
+CLOCK: [2024-01-15 Mon 14:00]--[2024-01-15 Mon 15:00] =>  1:00
+:END:
+"#;
+
+	let (notes, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	let entries = &notes[0].logbook.as_ref().unwrap().clock_entries;
+	assert_eq!(entries[0].duration.as_deref(), Some("1:30"));
+	assert_eq!(
+		entries[0].raw,
+		"CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:30] =>  1:30"
+	);
+	assert_eq!(notes[0].logbook.as_ref().unwrap().total_minutes(), 150);
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(warnings[0].line, 3);
+
+	let (kept, warnings) = OrgParser::new(content)
+		.preserve_clock_durations(true)
+		.parse_with_diagnostics();
+	assert_eq!(
+		kept[0].logbook.as_ref().unwrap().clock_entries[0]
+			.duration
+			.as_deref(),
+		Some("3:00")
+	);
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(serialize(&kept), content);
+}
+
+#[test]
+fn test_skipped_levels_nest_under_nearest_shallower_heading() {
+	let content = "* Alpha\n*** Beta\n** Gamma\n*** Delta\n* Epsilon\n";
+	let notes = parse(content);
+
+	assert_eq!(titles(&notes), vec!["Alpha", "Epsilon"]);
+	assert_eq!(titles(&notes[0].children), vec!["Beta", "Gamma"]);
+	assert_eq!(notes[0].children[0].level, 3);
+	assert_eq!(notes[0].children[1].level, 2);
+	assert!(notes[0].children[0].children.is_empty());
+	assert_eq!(titles(&notes[0].children[1].children), vec!["Delta"]);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_document_starting_deeper_than_later_headings() {
+	// A deeper first heading stays top-level; the shallower one after it is its sibling
+	let notes = parse("** Orphan\n*** Child\n* Top\n");
+
+	assert_eq!(titles(&notes), vec!["Orphan", "Top"]);
+	assert_eq!(titles(&notes[0].children), vec!["Child"]);
+}
+
+#[test]
+fn test_clock_and_planning_inside_blocks_stay_content() {
+	let content = r#"* How to clock
+# A comment line
+#+BEGIN_EXAMPLE
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00
+:END:
+DEADLINE: <2024-01-20 Sat>
+#+END_EXAMPLE
+#+begin_src org
+SCHEDULED: <2024-01-21 Sun>
+#+end_src
+"#;
+
+	let (notes, warnings) = OrgParser::new(content).parse_with_diagnostics();
+	let note = &notes[0];
+
+	assert!(note.logbook.is_none());
+	assert!(note.planning.is_none());
+	assert!(note.drawers.is_empty());
+	assert!(warnings.is_empty());
+	assert!(note.content.contains("CLOCK: [2024-01-15 Mon 09:00]"));
+	assert!(note.content.contains("SCHEDULED: <2024-01-21 Sun>"));
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_find_by_title_and_path() {
+	let content = r#"* Project
+** TODO Subtask
+*** Notes
+* Archive
+** Notes
+"#;
+	let notes = parse(content);
+
+	let matches = find_by_title(&notes, "Notes");
+	assert_eq!(matches.len(), 2);
+	assert_eq!(matches[0].level, 3);
+	assert_eq!(matches[1].level, 2);
+	assert_eq!(
+		find_by_title(&notes, "Subtask")[0].status.as_deref(),
+		Some("TODO")
+	);
+
+	let found = find_by_path(&notes, &["Project", "Subtask", "Notes"]).unwrap();
+	assert_eq!(found.level, 3);
+	assert_eq!(
+		find_by_path(&notes, &["Archive", "Notes"]).unwrap().level,
+		2
+	);
+
+	assert!(find_by_path(&notes, &["Project", "Missing"]).is_none());
+	assert!(find_by_path(&notes, &["Subtask"]).is_none());
+	assert!(find_by_path(&notes, &[]).is_none());
+}
+
+#[test]
+fn test_find_clock_overlaps() {
+	let content = r#"* Writing
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00
+CLOCK: [2024-01-15 Mon 11:00]--[2024-01-15 Mon 12:00] =>  1:00
+:END:
+** Meeting
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:30]--[2024-01-15 Mon 10:30] =>  1:00
+:END:
+* Review
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 12:00]--[2024-01-15 Mon 13:00] =>  1:00
+CLOCK: [2024-01-15 Mon 14:00]
+:END:
+"#;
+
+	let notes = parse(content);
+	let at = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+
+	let overlaps = find_clock_overlaps(&notes, at("2024-01-15 14:30"));
+	assert_eq!(overlaps.len(), 1);
+	assert_eq!(overlaps[0].first.note.title, "Writing");
+	assert_eq!(overlaps[0].second.note.title, "Meeting");
+	assert!(overlaps[0].second.entry.raw.contains("09:30"));
+
+	// A running clock is open up to "now"
+	let running = parse(
+		"* Late\n:LOGBOOK:\nCLOCK: [2024-01-15 Mon 16:00]--[2024-01-15 Mon 17:00] =>  1:00\n:END:\n",
+	);
+	let mut notes = notes;
+	notes.extend(running);
+	assert_eq!(find_clock_overlaps(&notes, at("2024-01-15 15:59")).len(), 1);
+	let overlaps = find_clock_overlaps(&notes, at("2024-01-15 16:30"));
+	assert_eq!(overlaps.len(), 2);
+	assert_eq!(overlaps[1].first.note.title, "Review");
+	assert_eq!(overlaps[1].second.note.title, "Late");
+}
+
+#[test]
+fn test_clock_line_with_active_brackets() {
+	let parser = OrgParser::new("");
+	let mut entry = parser
+		.parse_clock_line("CLOCK: <2024-01-15 Mon 09:00>--<2024-01-15 Mon 10:30> =>  1:30")
+		.unwrap();
+
+	assert_eq!(entry.start.hour, Some(9));
+	assert_eq!(entry.end.as_ref().unwrap().minute, Some(30));
+	assert_eq!(entry.parse_duration_minutes(), Some(90));
+	assert!(entry.is_valid());
+
+	// Rewritten clocks use org's inactive brackets
+	entry.recompute_duration();
+	assert_eq!(
+		entry.raw,
+		"CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:30] =>  1:30"
+	);
+
+	let running = parser
+		.parse_clock_line("CLOCK: <2024-01-15 Mon 11:00>")
+		.unwrap();
+	assert!(running.end.is_none());
+	assert_eq!(running.start.to_inactive_raw(), "[2024-01-15 Mon 11:00]");
+}
+
+#[test]
+fn test_round_clock_durations() {
+	let up = Rounding {
+		step: 15,
+		mode: RoundMode::Up,
+	};
+	let down = Rounding {
+		step: 15,
+		mode: RoundMode::Down,
+	};
+	let nearest = Rounding {
+		step: 15,
+		mode: RoundMode::Nearest,
+	};
+
+	assert_eq!(up.apply(127), 135); // 2:07 -> 2:15
+	assert_eq!(down.apply(127), 120); // 2:07 -> 2:00
+	assert_eq!(nearest.apply(127), 120);
+	assert_eq!(nearest.apply(128), 135);
+	assert_eq!(up.apply(120), 120);
+	assert_eq!(Rounding::default().apply(127), 127);
+
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 11:07] =>  2:07
+CLOCK: [2024-01-16 Tue 09:00]--[2024-01-16 Tue 09:30] =>  0:30
+:END:
+"#;
+	let notes = parse(content);
+	let logbook = notes[0].logbook.as_ref().unwrap();
+	assert_eq!(logbook.total_minutes(), 157);
+	assert_eq!(logbook.minutes_in(&DateRange::default(), &up), 165);
+
+	// Only written back when asked
+	assert_eq!(serialize(&notes), content);
+	let options = SerializeOptions {
+		round_durations: up,
+		..SerializeOptions::default()
+	};
+	assert_eq!(
+		serialize_with_options(&notes, &options),
+		content.replace("11:07] =>  2:07", "11:07] =>  2:15")
+	);
+}
+
+#[test]
+fn test_clock_totals_by_week_and_month() {
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2024-01-31 Wed 09:00]--[2024-01-31 Wed 10:00] =>  1:00
+CLOCK: [2024-02-01 Thu 09:00]--[2024-02-01 Thu 09:30] =>  0:30
+CLOCK: [2024-02-05 Mon 09:00]--[2024-02-05 Mon 11:00] =>  2:00
+:END:
+** Subtask
+:LOGBOOK:
+CLOCK: [2024-12-30 Mon 09:00]--[2024-12-30 Mon 09:15] =>  0:15
+CLOCK: [2024-01-29 Mon 09:00]--[2024-01-29 Mon 09:10] =>  0:10
+:END:
+"#;
+
+	let notes = parse(content);
+	let totals = |period| {
+		clock_totals_by_period(&notes, period, &DateRange::default(), &Rounding::default())
+	};
 
-		assert_eq!(timestamp.to_date_string(), "2024-01-15");
-		assert_eq!(timestamp.to_datetime_string(), "2024-01-15 14:30");
+	assert_eq!(
+		totals(ClockPeriod::Week),
+		vec![
+			("2024-W05".to_string(), 100),
+			("2024-W06".to_string(), 120),
+			("2025-W01".to_string(), 15),
+		]
+	);
+	assert_eq!(
+		totals(ClockPeriod::Month),
+		vec![
+			("2024-01".to_string(), 70),
+			("2024-02".to_string(), 150),
+			("2024-12".to_string(), 15),
+		]
+	);
+	assert_eq!(totals(ClockPeriod::Day).len(), 5);
+	assert_eq!(totals(ClockPeriod::Day)[0], ("2024-01-29".to_string(), 10));
+}
+
+#[test]
+fn test_clock_entries_crossing_midnight_are_split_by_day() {
+	let content = r#"* Task
+:LOGBOOK:
+CLOCK: [2024-01-31 Wed 23:00]--[2024-02-01 Thu 01:00] =>  2:00
+CLOCK: [2024-02-01 Thu 22:30]--[2024-02-03 Sat 00:15] => 25:45
+CLOCK: [2024-02-03 Sat 09:00]--[2024-02-03 Sat 09:30] =>  0:30
+:END:
+"#;
+	let notes = parse(content);
+	let entries = &notes[0].logbook.as_ref().unwrap().clock_entries;
+	let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+	assert_eq!(
+		entries[0].minutes_by_day(),
+		vec![(date("2024-01-31"), 60), (date("2024-02-01"), 60)]
+	);
+	assert_eq!(
+		entries[1].minutes_by_day(),
+		vec![
+			(date("2024-02-01"), 90),
+			(date("2024-02-02"), 1440),
+			(date("2024-02-03"), 15),
+		]
+	);
+	assert_eq!(entries[2].minutes_by_day(), vec![(date("2024-02-03"), 30)]);
+
+	let totals = |period| {
+		clock_totals_by_period(&notes, period, &DateRange::default(), &Rounding::default())
+	};
+	assert_eq!(
+		totals(ClockPeriod::Day),
+		vec![
+			("2024-01-31".to_string(), 60),
+			("2024-02-01".to_string(), 150),
+			("2024-02-02".to_string(), 1440),
+			("2024-02-03".to_string(), 45),
+		]
+	);
+	assert_eq!(
+		totals(ClockPeriod::Month),
+		vec![("2024-01".to_string(), 60), ("2024-02".to_string(), 1635)]
+	);
+	// The stored entries are written back as they were
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_renumber_levels_after_demoting_subtree() {
+	let mut notes = parse("* Project\n* Moved\n** Step\n*** Detail\n");
+
+	// Demote "Moved" under "Project" without touching its levels
+	let moved = notes.remove(1);
+	notes[0].children.push(moved);
+	assert!(serialize(&notes).contains("\n* Moved\n"));
+
+	renumber_levels(&mut notes);
+	let moved = &notes[0].children[0];
+	assert_eq!(moved.level, 2);
+	assert_eq!(moved.children[0].level, 3);
+	assert_eq!(moved.children[0].children[0].level, 4);
+	assert_eq!(
+		serialize(&notes),
+		"* Project\n** Moved\n*** Step\n**** Detail\n"
+	);
+}
+
+#[test]
+fn test_planning_keywords_sharing_a_line() {
+	let content = r#"* TODO Task
+DEADLINE: <2024-01-20 Sat> SCHEDULED: <2024-01-15 Mon 09:00>
+Body text.
+* DONE Finished
+CLOSED: [2024-01-10 Wed 17:00] SCHEDULED: <2024-01-09 Tue>
+* Not planning
+Was SCHEDULED: <2024-01-15 Mon> earlier.
+"#;
+
+	let notes = parse(content);
+	let planning = notes[0].planning.as_ref().unwrap();
+	assert_eq!(planning.deadline.as_ref().unwrap().day, 20);
+	assert_eq!(planning.scheduled.as_ref().unwrap().hour, Some(9));
+	assert_eq!(notes[0].content, "Body text.");
+	assert_eq!(
+		notes[1]
+			.planning
+			.as_ref()
+			.unwrap()
+			.closed
+			.as_ref()
+			.unwrap()
+			.raw,
+		"[2024-01-10 Wed 17:00]"
+	);
+	assert!(notes[2].planning.is_none());
+	assert_eq!(serialize(&notes), content);
+
+	// Clearing one keyword keeps the other on its line
+	let mut notes = notes;
+	notes[0].planning.as_mut().unwrap().deadline = None;
+	assert!(
+		serialize(&notes)
+			.starts_with("* TODO Task\nSCHEDULED: <2024-01-15 Mon 09:00>\nBody text.\n")
+	);
+}
+
+#[test]
+fn test_note_builder() {
+	let parser = OrgParser::new("");
+	let timestamp = |text: &str| text.parse::<OrgTimestamp>().unwrap();
+
+	let note = OrgNote::builder(1, "Write report")
+		.status("TODO")
+		.priority('A')
+		.tag("work")
+		.tag("work")
+		.tag("writing")
+		.deadline(timestamp("<2024-01-31 Wed>"))
+		.clock(
+			parser
+				.parse_clock_line("CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00")
+				.unwrap(),
+		)
+		.content("See [[https://example.com][the brief]].")
+		.child(
+			OrgNote::builder(1, "Outline")
+				.child(OrgNote::builder(1, "Intro").build())
+				.build(),
+		)
+		.build();
+
+	assert_eq!(note.labels, vec!["work", "writing"]);
+	let planning = note.planning.as_ref().unwrap();
+	assert!(planning.scheduled.is_none() && planning.closed.is_none());
+	assert_eq!(note.logbook.as_ref().unwrap().total_minutes(), 60);
+	assert_eq!(note.links[0].target, "https://example.com");
+	assert_eq!(note.children[0].level, 2);
+	assert_eq!(note.children[0].children[0].level, 3);
+	assert!(OrgNote::builder(2, "Bare").build().planning.is_none());
+
+	assert_eq!(
+		serialize(&[note]),
+		"* TODO [#A] Write report :work:writing:\nDEADLINE: <2024-01-31 Wed>\n:LOGBOOK:\nCLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00\n:END:\nSee [[https://example.com][the brief]].\n\n** Outline\n\n*** Intro\n\n"
+	);
+}
+
+#[test]
+fn test_timestamp_display_and_from_str() {
+	let timestamp: OrgTimestamp = "<2024-01-01 Mon 10:00>".parse().unwrap();
+	assert_eq!((timestamp.year, timestamp.hour), (2024, Some(10)));
+	assert_eq!(timestamp.to_string(), "<2024-01-01 Mon 10:00>");
+
+	// Inactive brackets and missing day names come out in the canonical form
+	let inactive: OrgTimestamp = "[2024-01-15 Mon]".parse().unwrap();
+	assert_eq!(inactive.to_string(), "<2024-01-15 Mon>");
+	let mut bare = inactive.clone();
+	bare.day_name = None;
+	assert_eq!(bare.to_string(), "<2024-01-15 Mon>");
+
+	for text in ["<2023-03-29 Ср>", "<2024-02-29 Thu 23:59>"] {
+		let parsed: OrgTimestamp = text.parse().unwrap();
+		assert_eq!(parsed.to_string(), text);
+		assert_eq!(
+			parsed.to_string().parse::<OrgTimestamp>().unwrap().raw,
+			text
+		);
 	}
 
-	#[test]
-	fn test_duration_parsing() {
-		let clock_entry = OrgClockEntry {
-			start: OrgTimestamp {
-				year: 2024,
-				month: 1,
-				day: 1,
-				hour: Some(9),
-				minute: Some(0),
-				day_name: Some("Mon".to_string()),
-				raw: "[2024-01-01 Mon 09:00]".to_string(),
-			},
-			end: None,
-			duration: Some("2:30".to_string()),
-			raw: "CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 11:30] =>  2:30".to_string(),
-		};
+	assert_eq!(
+		"not a date".parse::<OrgTimestamp>().unwrap_err(),
+		"invalid timestamp 'not a date'"
+	);
+	assert!("<2024-01>".parse::<OrgTimestamp>().is_err());
+}
+
+#[test]
+fn test_timestamp_ordering() {
+	let at = |text: &str| text.parse::<OrgTimestamp>().unwrap();
+
+	// The same instant is equal whatever the brackets or day name
+	assert_eq!(at("<2024-01-15 Mon 09:00>"), at("[2024-01-15 Пн 09:00]"));
+
+	// A date-only timestamp sorts before every time on the same day
+	assert!(at("<2024-01-15 Mon>") < at("<2024-01-15 Mon 00:00>"));
+	assert!(at("<2024-01-15 Mon>") > at("<2024-01-14 Sun 23:59>"));
+	assert_ne!(at("<2024-01-15 Mon>"), at("<2024-01-15 Mon 00:00>"));
+	assert!(at("<2024-01-15 Mon 09:30>") > at("<2024-01-15 Mon 09:05>"));
+
+	let mut deadlines = [
+		at("<2024-01-15 Mon 17:00>"),
+		at("<2024-01-16 Tue>"),
+		at("<2024-01-15 Mon>"),
+		at("<2024-01-15 Mon 08:00>"),
+	];
+	deadlines.sort();
+	let raws: Vec<&str> = deadlines.iter().map(|t| t.raw.as_str()).collect();
+	assert_eq!(
+		raws,
+		vec![
+			"<2024-01-15 Mon>",
+			"<2024-01-15 Mon 08:00>",
+			"<2024-01-15 Mon 17:00>",
+			"<2024-01-16 Tue>"
+		]
+	);
+	assert_eq!(deadlines.iter().min().unwrap().raw, "<2024-01-15 Mon>");
+}
+
+#[test]
+fn test_iter_notes_matches_flattened_order() {
+	let content =
+		"* Work\n** Design\n*** Sketch\n** Build :ARCHIVE:\n*** Old step\n* Home\n** Garden\n";
+	let notes = parse(content);
+
+	let iterated: Vec<(usize, &str)> = iter_notes(&notes)
+		.map(|(depth, note)| (depth, note.title.as_str()))
+		.collect();
+	assert_eq!(
+		iterated,
+		vec![
+			(0, "Work"),
+			(1, "Design"),
+			(2, "Sketch"),
+			(1, "Build"),
+			(2, "Old step"),
+			(0, "Home"),
+			(1, "Garden"),
+		]
+	);
+
+	let flat = flatten_notes(&notes);
+	assert!(
+		iter_notes(&notes)
+			.zip(&flat)
+			.all(|((_, note), flat)| note.title == flat.note.title)
+	);
+	assert_eq!(iter_notes(&notes).count(), flat.len());
+	assert_eq!(flat[4].parent_index, Some(3));
+	assert_eq!(flat[5].parent_index, None);
+
+	let unarchived: Vec<&str> = iter_notes(&notes)
+		.skip_subtree_if(OrgNote::is_archived)
+		.map(|(_, note)| note.title.as_str())
+		.collect();
+	assert_eq!(
+		unarchived,
+		vec!["Work", "Design", "Sketch", "Home", "Garden"]
+	);
+	assert_eq!(iter_notes(&[]).next().map(|(_, note)| note.level), None);
+}
+
+#[test]
+fn test_notes_get_distinct_ids() {
+	let notes = parse("* One\n** Two\n* Three\n");
+	let mut ids: Vec<u64> = iter_notes(&notes).map(|(_, note)| note.id).collect();
+	ids.extend(parse("* One\n").iter().map(|note| note.id));
+	ids.push(OrgNote::new(1, "New".to_string()).id);
+
+	let count = ids.len();
+	ids.sort();
+	ids.dedup();
+	assert_eq!(ids.len(), count);
+
+	// Copies of a note, e.g. from filtering, keep its id
+	let filtered = filter_notes(&notes, &|note| note.title == "Two");
+	assert_eq!(filtered[0].children[0].id, notes[0].children[0].id);
+}
+
+fn lint_rules(content: &str, now: &str) -> Vec<(String, LintRule)> {
+	let now = NaiveDateTime::parse_from_str(now, "%Y-%m-%d %H:%M").unwrap();
+	lint_notes(&parse(content), &Keywords::default(), now, 12)
+		.into_iter()
+		.map(|finding| (finding.note.title.clone(), finding.rule))
+		.collect()
+}
+
+#[test]
+fn test_lint_done_without_closed() {
+	let content = "* DONE Open ended\n* DONE Closed properly\nCLOSED: [2024-01-02 Tue 10:00]\n* TODO Not done yet\n";
+	assert_eq!(
+		lint_rules(content, "2024-01-03 09:00"),
+		vec![("Open ended".to_string(), LintRule::DoneWithoutClosed)]
+	);
+}
+
+#[test]
+fn test_lint_overdue_deadline() {
+	let content = r#"* TODO Yesterday
+DEADLINE: <2024-01-02 Tue>
+* TODO Today
+DEADLINE: <2024-01-03 Wed>
+* TODO This morning
+DEADLINE: <2024-01-03 Wed 08:00>
+* TODO Tonight
+DEADLINE: <2024-01-03 Wed 20:00>
+* DONE Finished late
+DEADLINE: <2024-01-01 Mon>
+CLOSED: [2024-01-02 Tue 10:00]
+"#;
+	assert_eq!(
+		lint_rules(content, "2024-01-03 09:00"),
+		vec![
+			("Yesterday".to_string(), LintRule::OverdueDeadline),
+			("This morning".to_string(), LintRule::OverdueDeadline),
+		]
+	);
+}
+
+#[test]
+fn test_lint_stale_clock() {
+	let content = r#"* Forgotten
+:LOGBOOK:
+CLOCK: [2024-01-02 Tue 08:00]
+:END:
+* Recent
+:LOGBOOK:
+CLOCK: [2024-01-02 Tue 20:00]
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+:END:
+"#;
+	assert_eq!(
+		lint_rules(content, "2024-01-03 07:00"),
+		vec![("Forgotten".to_string(), LintRule::StaleClock)]
+	);
+}
+
+#[test]
+fn test_lint_invalid_date() {
+	let content = r#"* No such day
+SCHEDULED: <2024-02-30 Fri>
+* No such hour
+DEADLINE: <2024-02-01 Thu 25:00>
+* Leap day
+SCHEDULED: <2024-02-29 Thu>
+"#;
+	assert_eq!(
+		lint_rules(content, "2024-01-01 09:00"),
+		vec![
+			("No such day".to_string(), LintRule::InvalidDate),
+			("No such hour".to_string(), LintRule::InvalidDate),
+		]
+	);
+}
+
+#[test]
+fn test_lint_duplicate_id() {
+	let content = r#"* First
+:PROPERTIES:
+:ID: abc
+:END:
+** Second
+:properties:
+:id: abc
+:END:
+* Third
+:PROPERTIES:
+:ID: def
+:END:
+"#;
+	let notes = parse(content);
+	assert_eq!(notes[0].children[0].property("ID"), Some("abc"));
+	assert_eq!(
+		lint_rules(content, "2024-01-01 09:00"),
+		vec![("Second".to_string(), LintRule::DuplicateId)]
+	);
+}
+
+#[test]
+fn test_notes_record_their_source_line() {
+	let content = r#"#+TITLE: Plans
+
+* TODO First
+SCHEDULED: <2024-01-20 Sat>
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+:END:
+Body.
+** Second
+* Third
+"#;
+	let notes = parse(content);
+	let lines: Vec<Option<usize>> = iter_notes(&notes)
+		.map(|(_, note)| note.source_line)
+		.collect();
+	assert_eq!(lines, vec![Some(3), Some(9), Some(10)]);
+	assert_eq!(OrgNote::new(1, "New".to_string()).source_line, None);
+}
+
+#[test]
+fn test_archive_done_subtree_under_archive_heading() {
+	let content = r#"* Project
+** DONE Ship it
+CLOSED: [2024-01-02 Tue 10:00]
+*** Notes
+** TODO Next step
+* Archive
+** DONE Older
+"#;
+	let mut notes = parse(content);
+	let id = notes[0].children[0].id;
+	archive_to_heading(&mut notes, id).unwrap();
+
+	assert_eq!(titles(&notes[0].children), vec!["Next step"]);
+	let archive = &notes[1];
+	assert_eq!(archive.title, DEFAULT_ARCHIVE_HEADING);
+	assert_eq!(titles(&archive.children), vec!["Older", "Ship it"]);
+	let archived = &archive.children[1];
+	assert_eq!((archived.level, archived.children[0].level), (2, 3));
+	assert!(
+		serialize(&notes).ends_with("** DONE Ship it\nCLOSED: [2024-01-02 Tue 10:00]\n*** Notes\n")
+	);
+
+	// Archiving the archive heading, or something already in it, is refused
+	let archived_id = archived.id;
+	assert!(archive_to_heading(&mut notes, archived_id).is_err());
+	let archive_id = notes[1].id;
+	assert!(archive_to_heading(&mut notes, archive_id).is_err());
+}
+
+#[test]
+fn test_archive_uses_archive_property_and_creates_heading() {
+	let content = r#"* Work
+:PROPERTIES:
+:ARCHIVE: ::* Done Work
+:END:
+** DONE Report
+* DONE Elsewhere
+:PROPERTIES:
+:ARCHIVE: %s_archive::
+:END:
+"#;
+	let mut notes = parse(content);
+	let report = notes[0].children[0].id;
+	archive_to_heading(&mut notes, report).unwrap();
+	assert!(notes[0].children.is_empty());
+	let created = notes.last().unwrap();
+	assert_eq!((created.level, created.title.as_str()), (1, "Done Work"));
+	assert_eq!(titles(&created.children), vec!["Report"]);
+
+	let elsewhere = notes[1].id;
+	assert!(archive_to_heading(&mut notes, elsewhere).is_err());
+	assert_eq!(notes.len(), 3);
+}
+
+#[test]
+fn test_effort_minutes() {
+	let effort = |value: &str| {
+		let content = format!("* Task\n:PROPERTIES:\n:Effort: {}\n:END:\n", value);
+		parse(&content)[0].effort_minutes()
+	};
+	assert_eq!(effort("1:30"), Some(90));
+	assert_eq!(effort("0:05"), Some(5));
+	assert_eq!(effort("45"), Some(45));
+	assert_eq!(effort("1:75"), None);
+	assert_eq!(effort("soon"), None);
+	assert_eq!(parse("* Task\n")[0].effort_minutes(), None);
+}
+
+#[test]
+fn test_compare_efforts_flags_over_estimate() {
+	let content = r#"* Over
+:PROPERTIES:
+:EFFORT: 1:00
+:END:
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+:END:
+** Subtask
+:LOGBOOK:
+CLOCK: [2024-01-02 Tue 09:00]--[2024-01-02 Tue 09:30] =>  0:30
+:END:
+* Under
+:PROPERTIES:
+:EFFORT: 2:00
+:END:
+:LOGBOOK:
+CLOCK: [2024-01-03 Wed 09:00]--[2024-01-03 Wed 10:30] =>  1:30
+:END:
+* No estimate
+:LOGBOOK:
+CLOCK: [2024-01-04 Thu 09:00]--[2024-01-04 Thu 12:00] =>  3:00
+:END:
+"#;
+	let notes = parse(content);
+	let efforts = compare_efforts(&notes, &DateRange::default(), &Rounding::default());
+	let summary: Vec<(&str, u32, u32, bool)> = efforts
+		.iter()
+		.map(|effort| {
+			(
+				effort.note.title.as_str(),
+				effort.tracked,
+				effort.estimated,
+				effort.is_over(),
+			)
+		})
+		.collect();
+	assert_eq!(
+		summary,
+		vec![("Over", 90, 60, true), ("Under", 90, 120, false)]
+	);
+}
+
+#[test]
+fn test_inline_clocks_stay_inline() {
+	let content = r#"* Task
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+Notes.
+* Drawer
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 11:00]--[2024-01-01 Mon 11:30] =>  0:30
+:END:
+CLOCK: [2024-01-01 Mon 12:00]--[2024-01-01 Mon 12:15] =>  0:15
+"#;
+	let mut notes = parse(content);
+	assert_eq!(serialize(&notes), content);
+
+	let logbook = notes[0].logbook.as_ref().unwrap();
+	assert!(logbook.inline);
+	assert_eq!(logbook.clock_entries.len(), 1);
+	assert_eq!(notes[0].content, "Notes.");
+	// With a drawer, bare CLOCK lines are left as content
+	assert!(!notes[1].logbook.as_ref().unwrap().inline);
+	assert_eq!(notes[1].logbook.as_ref().unwrap().clock_entries.len(), 1);
+
+	let parser = OrgParser::new("");
+	let entry = parser
+		.parse_clock_line("CLOCK: [2024-01-02 Tue 09:00]")
+		.unwrap();
+	notes[0].logbook.as_mut().unwrap().clock_entries.push(entry);
+	assert!(serialize(&notes).starts_with(
+		"* Task\nCLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00\nCLOCK: [2024-01-02 Tue 09:00]\nNotes.\n"
+	));
+}
+
+#[test]
+fn test_next_and_prev_todo_wrap_around() {
+	let content = r#"* TODO First
+** Notes
+** DONE Finished
+* Plain
+** TODO Nested
+* IN-PROGRESS Last
+"#;
+	let notes = parse(content);
+	let id_of = |title: &str| {
+		iter_notes(&notes)
+			.find(|(_, note)| note.title == title)
+			.unwrap()
+			.1
+			.id
+	};
+	let title_of = |id: Option<u64>| {
+		iter_notes(&notes)
+			.find(|(_, note)| Some(note.id) == id)
+			.map(|(_, note)| note.title.as_str())
+	};
+
+	assert_eq!(
+		title_of(next_todo(&notes, &Keywords::default(), id_of("First"))),
+		Some("Nested")
+	);
+	assert_eq!(
+		title_of(next_todo(&notes, &Keywords::default(), id_of("Finished"))),
+		Some("Nested")
+	);
+	assert_eq!(
+		title_of(next_todo(&notes, &Keywords::default(), id_of("Nested"))),
+		Some("Last")
+	);
+	assert_eq!(
+		title_of(next_todo(&notes, &Keywords::default(), id_of("Last"))),
+		Some("First")
+	);
+
+	assert_eq!(
+		title_of(prev_todo(&notes, &Keywords::default(), id_of("Nested"))),
+		Some("First")
+	);
+	assert_eq!(
+		title_of(prev_todo(&notes, &Keywords::default(), id_of("First"))),
+		Some("Last")
+	);
+	assert_eq!(
+		title_of(prev_todo(&notes, &Keywords::default(), id_of("Plain"))),
+		Some("First")
+	);
+
+	// Unknown ids start from the top, or the bottom going backwards
+	assert_eq!(
+		title_of(next_todo(&notes, &Keywords::default(), 0)),
+		Some("First")
+	);
+	assert_eq!(
+		title_of(prev_todo(&notes, &Keywords::default(), 0)),
+		Some("Last")
+	);
+
+	// A lone match is found again from itself
+	let only_done = |note: &crate::OrgNote| note.status.as_deref() == Some("DONE");
+	let finished = id_of("Finished");
+	assert_eq!(next_matching(&notes, finished, only_done), Some(finished));
+	assert_eq!(
+		next_todo(&parse("* Plain\n"), &Keywords::default(), 0),
+		None
+	);
+	// Only the configured active keywords count
+	let keywords = Keywords {
+		active: vec!["IN-PROGRESS".to_string()],
+		done: vec!["DONE".to_string()],
+	};
+	assert_eq!(
+		title_of(next_todo(&notes, &keywords, id_of("First"))),
+		Some("Last")
+	);
+	assert_eq!(
+		title_of(next_todo(&notes, &keywords, id_of("Last"))),
+		Some("Last")
+	);
+}
+
+#[test]
+fn test_ordered_children_block_later_tasks() {
+	let content = r#"* Release
+:PROPERTIES:
+:ORDERED:  t
+:END:
+** DONE Freeze
+** Notes
+** TODO Test
+** TODO Tag
+*** TODO Write changelog
+** TODO Announce
+* Chores
+** TODO Dishes
+** TODO Laundry
+"#;
+	let mut notes = parse(content);
+	let id_of = |notes: &[crate::OrgNote], title: &str| {
+		iter_notes(notes)
+			.find(|(_, note)| note.title == title)
+			.unwrap()
+			.1
+			.id
+	};
+	let title_of = |notes: &[crate::OrgNote], id: Option<u64>| {
+		iter_notes(notes)
+			.find(|(_, note)| Some(note.id) == id)
+			.map(|(_, note)| note.title.clone())
+	};
+
+	assert!(notes[0].is_ordered());
+	assert!(!notes[1].is_ordered());
+	let blocked = blocked_by_order(&notes, &Keywords::default());
+	let blocked_titles: Vec<&str> = iter_notes(&notes)
+		.filter(|(_, note)| blocked.contains(&note.id))
+		.map(|(_, note)| note.title.as_str())
+		.collect();
+	assert_eq!(blocked_titles, vec!["Tag", "Write changelog", "Announce"]);
+
+	// Test is next, and after it the jump skips to the unordered chores
+	let next = next_todo(&notes, &Keywords::default(), id_of(&notes, "Release"));
+	assert_eq!(title_of(&notes, next).as_deref(), Some("Test"));
+	let next = next_todo(&notes, &Keywords::default(), id_of(&notes, "Test"));
+	assert_eq!(title_of(&notes, next).as_deref(), Some("Dishes"));
+	let prev = prev_todo(&notes, &Keywords::default(), id_of(&notes, "Dishes"));
+	assert_eq!(title_of(&notes, prev).as_deref(), Some("Test"));
+
+	// Once Test is done, Tag is up next
+	notes[0].children[2].status = Some("DONE".to_string());
+	let next = next_todo(&notes, &Keywords::default(), id_of(&notes, "Release"));
+	assert_eq!(title_of(&notes, next).as_deref(), Some("Tag"));
+	let next = next_todo(&notes, &Keywords::default(), id_of(&notes, "Tag"));
+	assert_eq!(title_of(&notes, next).as_deref(), Some("Write changelog"));
+	let next = next_todo(
+		&notes,
+		&Keywords::default(),
+		id_of(&notes, "Write changelog"),
+	);
+	assert_eq!(title_of(&notes, next).as_deref(), Some("Dishes"));
+
+	let unordered = parse("* Plan\n:PROPERTIES:\n:ORDERED: nil\n:END:\n** TODO a\n** TODO b\n");
+	assert!(!unordered[0].is_ordered());
+	assert!(blocked_by_order(&unordered, &Keywords::default()).is_empty());
+}
+
+#[test]
+fn test_drawerless_clock_lines_under_heading() {
+	let content = r#"* Task
+SCHEDULED: <2024-01-20 Sat>
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+CLOCK: [2024-01-02 Tue 09:00]--[2024-01-02 Tue 09:30] =>  0:30
+Text.
+CLOCK: [2024-01-03 Wed 09:00]--[2024-01-03 Wed 09:10] =>  0:10
+"#;
+	let notes = parse(content);
+	let logbook = notes[0].logbook.as_ref().unwrap();
+	assert!(logbook.inline);
+	assert_eq!(logbook.clock_entries.len(), 2);
+	assert_eq!(logbook.clock_entries[1].duration.as_deref(), Some("0:30"));
+	assert_eq!(
+		logbook.minutes_in(&DateRange::default(), &Rounding::default()),
+		90
+	);
+	// A CLOCK line after the text is part of it
+	assert_eq!(
+		notes[0].content,
+		"Text.\nCLOCK: [2024-01-03 Wed 09:00]--[2024-01-03 Wed 09:10] =>  0:10"
+	);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_clock_out_all_closes_running_clocks() {
+	let content = "* TODO Write report\n:LOGBOOK:\nCLOCK: [2024-03-01 Fri 09:00]\nCLOCK: [2024-02-29 Thu 10:00]--[2024-02-29 Thu 11:00] =>  1:00\n:END:\n** Draft outline\n:LOGBOOK:\nCLOCK: [2024-03-01 Fri 10:15]\n:END:\n";
+	let mut notes = parse(content);
+	let now = NaiveDate::from_ymd_opt(2024, 3, 1)
+		.unwrap()
+		.and_hms_opt(11, 30, 0)
+		.unwrap();
+
+	assert_eq!(clock_out_all(&mut notes, now), 2);
 
-		assert_eq!(clock_entry.parse_duration_minutes(), Some(150)); // 2:30 = 150 minutes
-		assert_eq!(clock_entry.format_duration(), "2:30 (150 minutes)");
+	let parent = &notes[0].logbook.as_ref().unwrap().clock_entries;
+	assert_eq!(parent[0].duration.as_deref(), Some("2:30"));
+	assert_eq!(
+		parent[0].raw,
+		"CLOCK: [2024-03-01 Fri 09:00]--[2024-03-01 Fri 11:30] =>  2:30"
+	);
+	assert_eq!(parent[1].duration.as_deref(), Some("1:00"));
+	let child = &notes[0].children[0].logbook.as_ref().unwrap().clock_entries;
+	assert_eq!(child[0].duration.as_deref(), Some("1:15"));
+	assert!(child[0].end.is_some());
+
+	assert_eq!(clock_out_all(&mut notes, now), 0);
+}
+
+#[test]
+fn test_clock_totals_by_tag() {
+	let content = "* Client work :acme:billable:\n:LOGBOOK:\nCLOCK: [2024-03-01 Fri 09:00]--[2024-03-01 Fri 10:30] =>  1:30\nCLOCK: [2024-03-04 Mon 09:00]\n:END:\n** Call :acme:\n:LOGBOOK:\nCLOCK: [2024-03-02 Sat 14:00]--[2024-03-02 Sat 14:20] =>  0:20\n:END:\n* Untagged chores\n:LOGBOOK:\nCLOCK: [2024-03-01 Fri 12:00]--[2024-03-01 Fri 13:00] =>  1:00\n:END:\n* Old stuff :acme:ARCHIVE:\n:LOGBOOK:\nCLOCK: [2024-02-01 Thu 12:00]--[2024-02-01 Thu 13:00] =>  1:00\n:END:\n";
+	let notes = parse(content);
+
+	assert_eq!(
+		clock_totals_by_tag(&notes, &DateRange::default(), &Rounding::default()),
+		vec![("acme".to_string(), 110), ("billable".to_string(), 90)]
+	);
+
+	let since_saturday = DateRange {
+		since: NaiveDate::from_ymd_opt(2024, 3, 2),
+		until: None,
+	};
+	assert_eq!(
+		clock_totals_by_tag(&notes, &since_saturday, &Rounding::default()),
+		vec![("acme".to_string(), 20)]
+	);
+
+	let repeated = parse(
+		"* Review :acme:ops:acme:\n:LOGBOOK:\nCLOCK: [2024-03-01 Fri 09:00]--[2024-03-01 Fri 09:45] =>  0:45\n:END:\n",
+	);
+	assert_eq!(
+		clock_totals_by_tag(&repeated, &DateRange::default(), &Rounding::default()),
+		vec![("acme".to_string(), 45), ("ops".to_string(), 45)]
+	);
+}
+
+#[test]
+fn test_parse_table_with_header_separator() {
+	let content = "* Budget\nBefore the table.\n| Item | Cost |\n|------+------|\n| Tea  |    4 |\nAfter it.\n";
+	let notes = parse(content);
+
+	assert_eq!(
+		notes[0].tables,
+		vec![OrgTable {
+			rows: vec![
+				vec!["Item".to_string(), "Cost".to_string()],
+				vec!["Tea".to_string(), "4".to_string()],
+			],
+			separators: vec![1],
+		}]
+	);
+	assert_eq!(
+		notes[0].content,
+		"Before the table.\n| Item | Cost |\n|------+------|\n| Tea  |    4 |\nAfter it."
+	);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_tables_to_markdown() {
+	let tables = extract_tables("| a | b |\n| c |\n\n|---|\n| x | y |");
+	assert_eq!(tables.len(), 2);
+	assert_eq!(tables[0].separators, Vec::<usize>::new());
+	assert_eq!(tables[1].separators, vec![0]);
+	assert_eq!(
+		tables[0].to_markdown(),
+		"| a | b |\n| --- | --- |\n| c |  |"
+	);
+
+	let notes = parse("* Budget :money:\nCosts:\n| Item | Cost |\n|------+------|\n| Tea  | 4 |\n");
+	assert_eq!(
+		to_markdown(&notes),
+		"# Budget `:money:`\n\nCosts:\n| Item | Cost |\n| --- | --- |\n| Tea | 4 |\n\n"
+	);
+}
+
+#[test]
+fn test_align_ragged_table() {
+	let mut table =
+		extract_tables("|Item|Cost|\n|-\n| Green tea | 4 |\n|Cake|12.5\n| Spare |").remove(0);
+	align_table(&mut table);
+	assert_eq!(
+		table.to_org(),
+		"| Item      | Cost |\n|-----------+------|\n| Green tea |    4 |\n| Cake      | 12.5 |\n| Spare     |      |"
+	);
+
+	let content = "* Budget\n  |Item|Cost|\n  |-\n  |Tea|4|\nDone.\n";
+	let notes = parse(content);
+	let options = SerializeOptions {
+		align_tables: true,
+		..SerializeOptions::default()
+	};
+	assert_eq!(
+		serialize_with_options(&notes, &options),
+		"* Budget\n  | Item | Cost |\n  |------+------|\n  | Tea  |    4 |\nDone.\n"
+	);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_source_block_keeps_star_lines() {
+	let content = "* Snippets\n#+BEGIN_SRC org\n* TODO Not a heading\n  CLOCK: [2024-03-01 Fri 09:00]--[2024-03-01 Fri 10:00] =>  1:00\n\t** indented\n#+END_SRC\n** Real child\n#+begin_src sh\n* also code\n#+end_src\n* Next\n";
+	let notes = parse(content);
+
+	assert_eq!(titles(&notes), vec!["Snippets", "Next"]);
+	assert_eq!(titles(&notes[0].children), vec!["Real child"]);
+	assert!(notes[0].logbook.is_none());
+	assert_eq!(
+		notes[0].content,
+		"#+BEGIN_SRC org\n* TODO Not a heading\n  CLOCK: [2024-03-01 Fri 09:00]--[2024-03-01 Fri 10:00] =>  1:00\n\t** indented\n#+END_SRC"
+	);
+	assert_eq!(
+		notes[0].children[0].content,
+		"#+begin_src sh\n* also code\n#+end_src"
+	);
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_pipes_in_source_block_are_not_tables() {
+	let notes = parse("* Shell\n#+BEGIN_SRC sh\n|ls | wc -l|\n#+END_SRC\n|a|b|\n");
+	assert_eq!(notes[0].tables.len(), 1);
+	assert_eq!(notes[0].tables[0].rows, vec![vec!["a", "b"]]);
+
+	let options = SerializeOptions {
+		align_tables: true,
+		..SerializeOptions::default()
+	};
+	assert_eq!(
+		serialize_with_options(&notes, &options),
+		"* Shell\n#+BEGIN_SRC sh\n|ls | wc -l|\n#+END_SRC\n| a | b |\n"
+	);
+}
+
+/// A heading of a generated document, see [`org_document`].
+#[derive(Debug, Clone)]
+struct GenHeading {
+	level: usize,
+	status: Option<&'static str>,
+	priority: Option<char>,
+	title: String,
+	tags: Vec<String>,
+	scheduled: Option<NaiveDateTime>,
+	deadline: Option<NaiveDate>,
+	clocks: Vec<(NaiveDateTime, i64)>,
+	content: Vec<String>,
+}
+
+impl GenHeading {
+	fn to_org(&self) -> String {
+		let mut heading = "*".repeat(self.level);
+		heading.extend(self.status.map(|status| format!(" {}", status)));
+		heading.extend(self.priority.map(|letter| format!(" [#{}]", letter)));
+		heading.push_str(&format!(" {}", self.title));
+		if !self.tags.is_empty() {
+			heading.push_str(&format!(" :{}:", self.tags.join(":")));
+		}
+
+		let mut lines = vec![heading];
+		let mut planning = Vec::new();
+		planning.extend(
+			self.scheduled
+				.map(|at| at.format("SCHEDULED: <%Y-%m-%d %a %H:%M>").to_string()),
+		);
+		planning.extend(
+			self.deadline
+				.map(|date| date.format("DEADLINE: <%Y-%m-%d %a>").to_string()),
+		);
+		if !planning.is_empty() {
+			lines.push(planning.join(" "));
+		}
+		if !self.clocks.is_empty() {
+			lines.push(":LOGBOOK:".to_string());
+			for &(start, minutes) in &self.clocks {
+				let end = start + Duration::minutes(minutes);
+				lines.push(format!(
+					"CLOCK: {}--{} =>  {}:{:02}",
+					start.format("[%Y-%m-%d %a %H:%M]"),
+					end.format("[%Y-%m-%d %a %H:%M]"),
+					minutes / 60,
+					minutes % 60
+				));
+			}
+			lines.push(":END:".to_string());
+		}
+		lines.extend(self.content.iter().cloned());
+		lines.join("\n")
 	}
+}
+
+fn gen_date() -> impl Strategy<Value = NaiveDate> {
+	(2020i32..2030, 1u32..=12, 1u32..=28)
+		.prop_map(|(year, month, day)| NaiveDate::from_ymd_opt(year, month, day).unwrap())
+}
 
+fn gen_datetime() -> impl Strategy<Value = NaiveDateTime> {
+	(gen_date(), 0u32..24, 0u32..60)
+		.prop_map(|(date, hour, minute)| date.and_hms_opt(hour, minute, 0).unwrap())
+}
+
+/// Lowercase words, so the first one is never taken for a status keyword.
+fn gen_words(max: usize) -> impl Strategy<Value = String> {
+	prop::collection::vec("[a-z]{1,8}", 1..=max).prop_map(|words| words.join(" "))
+}
+
+fn gen_heading() -> impl Strategy<Value = GenHeading> {
+	(
+		1usize..=4,
+		prop::option::of(prop::sample::select(vec!["TODO", "NEXT", "DONE"])),
+		prop::option::of(prop::sample::select(vec!['A', 'B', 'C'])),
+		gen_words(4),
+		prop::collection::vec("[a-z][a-z0-9_]{0,5}", 0..3),
+		prop::option::of(gen_datetime()),
+		prop::option::of(gen_date()),
+		prop::collection::vec((gen_datetime(), 0i64..600), 0..3),
+		prop::collection::vec(prop_oneof![Just(String::new()), gen_words(6)], 0..3),
+	)
+		.prop_map(
+			|(level, status, priority, title, tags, scheduled, deadline, clocks, content)| {
+				GenHeading {
+					level,
+					status,
+					priority,
+					title,
+					tags,
+					scheduled,
+					deadline,
+					clocks,
+					content,
+				}
+			},
+		)
+}
+
+/// Random but valid org documents, one heading after another. Shrinking drops
+/// headings and simplifies the remaining ones.
+fn org_document() -> impl Strategy<Value = String> {
+	prop::collection::vec(gen_heading(), 0..8).prop_map(|headings| {
+		headings
+			.iter()
+			.map(|heading| format!("{}\n", heading.to_org()))
+			.collect()
+	})
+}
+
+/// The parsed fields of every note, in tree order, to compare two parses by.
+fn structure(notes: &[OrgNote]) -> Vec<String> {
+	iter_notes(notes)
+		.map(|(depth, note)| {
+			let planning = note.planning.clone().unwrap_or_default();
+			let raw = |ts: &Option<OrgTimestamp>| ts.as_ref().map(|ts| ts.raw.clone());
+			format!(
+				"{} {} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+				depth,
+				note.level,
+				note.status,
+				note.priority,
+				note.title,
+				note.labels,
+				note.content,
+				raw(&planning.scheduled),
+				raw(&planning.deadline),
+				note.logbook.as_ref().map(|logbook| logbook
+					.clock_entries
+					.iter()
+					.map(|entry| (entry.raw.clone(), entry.duration.clone()))
+					.collect::<Vec<_>>()),
+				note.children.len()
+			)
+		})
+		.collect()
+}
+
+proptest! {
 	#[test]
-	fn test_parse_empty_content() {
-		let mut parser = OrgParser::new("");
-		let notes = parser.parse();
-		assert_eq!(notes.len(), 0);
+	fn prop_parse_serialize_round_trip(document in org_document()) {
+		let notes = parse(&document);
+		let serialized = serialize(&notes);
+		let reparsed = parse(&serialized);
+
+		prop_assert_eq!(structure(&notes), structure(&reparsed));
+		prop_assert_eq!(serialize(&reparsed), serialized.clone());
+		prop_assert_eq!(serialized, document);
 	}
+}
 
-	#[test]
-	fn test_parse_no_headings() {
-		let content = "Just some text\nwithout any headings\nat all.";
-		let mut parser = OrgParser::new(content);
-		let notes = parser.parse();
-		assert_eq!(notes.len(), 0);
+#[test]
+fn test_tab_indented_heading_and_tab_content() {
+	let content = "\t* TODO Tabbed\theading\t:work:\n\tfirst\tline\n  \tmixed indent\n\t** Child\n";
+	let notes = parse(content);
+
+	assert_eq!(notes[0].status.as_deref(), Some("TODO"));
+	assert_eq!(notes[0].title, "Tabbed heading");
+	assert_eq!(notes[0].labels, vec!["work"]);
+	assert_eq!(notes[0].content, "\tfirst\tline\n  \tmixed indent");
+	assert_eq!(titles(&notes[0].children), vec!["Child"]);
+	assert_eq!(
+		serialize(&notes),
+		"* TODO Tabbed heading :work:\n\tfirst\tline\n  \tmixed indent\n** Child\n"
+	);
+}
+
+#[test]
+fn test_prune_depth_drops_deeper_notes() {
+	let content = "* Project\n** Phase one\n*** Step a\n*** Step b\n** Phase two\n* Errands\n";
+	let mut notes = parse(content);
+	prune_depth(&mut notes, 2, false);
+
+	assert_eq!(titles(&notes[0].children), vec!["Phase one", "Phase two"]);
+	assert!(notes[0].children[0].children.is_empty());
+	assert_eq!(notes[0].children[0].children_count, None);
+	assert_eq!(
+		serialize(&notes),
+		"* Project\n** Phase one\n** Phase two\n* Errands\n"
+	);
+
+	let mut notes = parse(content);
+	prune_depth(&mut notes, 2, true);
+	assert_eq!(notes[0].children[0].children_count, Some(2));
+	assert_eq!(notes[0].children[1].children_count, None);
+	assert_eq!(notes[0].children_count, None);
+	let json = serde_json::to_value(&notes).unwrap();
+	assert_eq!(json[0]["children"][0]["children_count"], 2);
+	assert!(json[0].get("children_count").is_none());
+}
+
+#[test]
+fn test_repeater_advances_timestamps() {
+	let now = NaiveDate::from_ymd_opt(2024, 3, 20)
+		.unwrap()
+		.and_hms_opt(12, 0, 0)
+		.unwrap();
+	let advanced = |raw: &str| {
+		let mut timestamp: OrgTimestamp = raw.parse().unwrap();
+		assert!(timestamp.advance_repeater(now), "{} did not repeat", raw);
+		timestamp
+	};
+
+	let weekly = advanced("<2024-03-01 Fri 10:00 +1w>");
+	assert_eq!(weekly.raw, "<2024-03-08 Fri 10:00 +1w>");
+	assert_eq!((weekly.day, weekly.hour), (8, Some(10)));
+	assert_eq!(
+		advanced("<2024-02-28 Wed +2d -1d>").raw,
+		"<2024-03-01 Fri +2d -1d>"
+	);
+	assert_eq!(advanced("<2024-01-31 Wed +1m>").raw, "<2024-02-29 Thu +1m>");
+	assert_eq!(
+		advanced("<2024-03-01 Fri ++1w>").raw,
+		"<2024-03-22 Fri ++1w>"
+	);
+	assert_eq!(
+		advanced("<2024-03-01 Fri .+2d>").raw,
+		"<2024-03-22 Fri .+2d>"
+	);
+
+	let mut plain: OrgTimestamp = "<2024-03-01 Fri>".parse().unwrap();
+	assert!(!plain.advance_repeater(now));
+	assert_eq!(plain.raw, "<2024-03-01 Fri>");
+}
+
+#[test]
+fn test_repeat_task_moves_planning_and_logs() {
+	let now = NaiveDate::from_ymd_opt(2024, 3, 4)
+		.unwrap()
+		.and_hms_opt(18, 30, 0)
+		.unwrap();
+	let mut notes = parse(
+		"* TODO Water plants\nSCHEDULED: <2024-03-04 Mon +1w> DEADLINE: <2024-03-05 Tue +1m>\nText\n",
+	);
+	assert!(notes[0].repeat_task(now));
+	assert_eq!(
+		serialize(&notes),
+		"* TODO Water plants\nSCHEDULED: <2024-03-11 Mon +1w> DEADLINE: <2024-04-05 Fri +1m>\n:PROPERTIES:\n:LAST_REPEAT: [2024-03-04 Mon 18:30]\n:END:\nText\n"
+	);
+
+	let mut once = parse("* TODO Call\nSCHEDULED: <2024-03-04 Mon>\n");
+	assert!(!once[0].repeat_task(now));
+	assert!(once[0].drawers.is_empty());
+}
+
+#[test]
+fn test_repeater_styles() {
+	assert_eq!(
+		"++2d".parse::<Repeater>(),
+		Ok(Repeater {
+			style: RepeatStyle::CatchUp,
+			count: 2,
+			unit: RepeatUnit::Day,
+		})
+	);
+	assert_eq!(
+		".+1m".parse::<Repeater>().map(|r| (r.style, r.unit)),
+		Ok((RepeatStyle::Restart, RepeatUnit::Month))
+	);
+	assert_eq!(
+		"+1w".parse::<Repeater>().map(|r| r.style),
+		Ok(RepeatStyle::Cumulate)
+	);
+	assert!("+0d".parse::<Repeater>().is_err());
+	assert!("+1x".parse::<Repeater>().is_err());
+	assert!("1w".parse::<Repeater>().is_err());
+
+	// Done on Wednesday 2024-03-20 at noon, three weeks after a Friday start
+	let at = |day: u32, hour: u32| {
+		NaiveDate::from_ymd_opt(2024, 3, day)
+			.unwrap()
+			.and_hms_opt(hour, 0, 0)
+			.unwrap()
+	};
+	let now = at(20, 12);
+	let start = at(1, 9);
+	let next = |cookie: &str, has_time: bool| {
+		cookie
+			.parse::<Repeater>()
+			.unwrap()
+			.next_after(start, now, has_time)
+	};
+
+	assert_eq!(next("+1w", true), Some(at(8, 9)));
+	assert_eq!(next("++1w", true), Some(at(22, 9)));
+	assert_eq!(next(".+1w", true), Some(at(27, 9)));
+	assert_eq!(next("++1d", true), Some(at(21, 9)));
+	assert_eq!(next(".+3h", true), Some(at(20, 15)));
+
+	// On the same day, `++` only counts a timed repeat as passed after its time
+	let morning = at(20, 9);
+	let daily: Repeater = "++1d".parse().unwrap();
+	assert_eq!(daily.next_after(at(19, 9), morning, true), Some(at(21, 9)));
+	assert_eq!(
+		daily.next_after(at(19, 9), at(20, 8), true),
+		Some(at(20, 9))
+	);
+	assert_eq!(
+		daily.next_after(at(19, 0), at(20, 8), false),
+		Some(at(21, 0))
+	);
+
+	let mut timestamp: OrgTimestamp = "<2024-03-01 Fri 09:00 ++1w>".parse().unwrap();
+	assert_eq!(timestamp.repeater().map(|r| r.count), Some(1));
+	assert!(timestamp.advance_repeater(now));
+	assert_eq!(timestamp.raw, "<2024-03-22 Fri 09:00 ++1w>");
+}
+
+#[test]
+fn test_render_tree_indents_children() {
+	let notes = parse(
+		"* TODO [#A] Project :work:\nSCHEDULED: <2024-01-20 Sat 09:00> DEADLINE: <2024-01-31 Wed>\n** Phase one\n*** DONE Step\n** Phase two\n* Errands\n",
+	);
+	assert_eq!(
+		render_tree(&notes),
+		"TODO [#A] Project :work: (scheduled 2024-01-20 09:00, deadline 2024-01-31)\n\
+		 ├── Phase one\n\
+		 │   └── DONE Step\n\
+		 └── Phase two\n\
+		 Errands\n"
+	);
+}
+
+#[test]
+fn test_timestamp_to_raw_for_both_kinds() {
+	let at = NaiveDate::from_ymd_opt(2024, 1, 20)
+		.unwrap()
+		.and_hms_opt(9, 5, 0)
+		.unwrap();
+
+	let active = OrgTimestamp::from_datetime(at, TimestampKind::Active);
+	assert_eq!(active.raw, "<2024-01-20 Sat 09:05>");
+	assert_eq!(
+		active.to_raw(TimestampKind::Inactive),
+		"[2024-01-20 Sat 09:05]"
+	);
+	assert_eq!(active, "<2024-01-20 Sat 09:05>".parse().unwrap());
+
+	let day = OrgTimestamp::from_date(at.date(), TimestampKind::Inactive);
+	assert_eq!(day.raw, "[2024-01-20 Sat]");
+	assert_eq!(day.to_raw(TimestampKind::Active), "<2024-01-20 Sat>");
+
+	// The weekday comes from the date, not from what was written
+	let parsed: OrgTimestamp = "<2023-03-29 Ср +1w>".parse().unwrap();
+	assert_eq!(parsed.to_raw(TimestampKind::Active), "<2023-03-29 Wed>");
+	let wrong_day: OrgTimestamp = "[2024-01-20 Mon 10:00]".parse().unwrap();
+	assert_eq!(
+		wrong_day.to_raw(TimestampKind::Inactive),
+		"[2024-01-20 Sat 10:00]"
+	);
+}
+
+#[test]
+fn test_indented_body_round_trips_byte_for_byte() {
+	let content = "* TODO Indented\n  SCHEDULED: <2024-01-20 Sat>\n  :PROPERTIES:\n  :ID: abc\n  :END:\n  :LOGBOOK:\n  CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00\n  :END:\n  Two-space content  \n\ttab content\n    - list item\n      continued\n   \n** Child\n   deep text\n";
+	let notes = parse(content);
+
+	assert_eq!(notes[0].body_indent, "  ");
+	assert_eq!(
+		notes[0].content,
+		"  Two-space content  \n\ttab content\n    - list item\n      continued\n   "
+	);
+	assert_eq!(notes[0].children[0].content, "   deep text");
+	assert_eq!(serialize(&notes), content);
+
+	let options = SerializeOptions {
+		clock_indent: Some(String::new()),
+		..SerializeOptions::default()
+	};
+	assert!(
+		serialize_with_options(&notes, &options)
+			.contains("  :LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]")
+	);
+}
+
+#[test]
+fn test_parse_relative_date() {
+	// A Wednesday
+	let now = NaiveDateTime::parse_from_str("2024-01-17 15:30", "%Y-%m-%d %H:%M").unwrap();
+	let date = |text: &str| parse_relative_date(text, now).map(|date| date.to_string());
+
+	assert_eq!(date("today").as_deref(), Some("2024-01-17"));
+	assert_eq!(date(" Tomorrow ").as_deref(), Some("2024-01-18"));
+	assert_eq!(date("yesterday").as_deref(), Some("2024-01-16"));
+	assert_eq!(date("+3d").as_deref(), Some("2024-01-20"));
+	assert_eq!(date("+3").as_deref(), Some("2024-01-20"));
+	assert_eq!(date("-1w").as_deref(), Some("2024-01-10"));
+	assert_eq!(date("+1m").as_deref(), Some("2024-02-17"));
+	assert_eq!(date("+1y").as_deref(), Some("2025-01-17"));
+	assert_eq!(date("mon").as_deref(), Some("2024-01-22"));
+	assert_eq!(date("Friday").as_deref(), Some("2024-01-19"));
+	assert_eq!(date("wed").as_deref(), Some("2024-01-17"));
+
+	for invalid in ["", "+", "+3x", "someday", "3d", "+-3d"] {
+		assert_eq!(date(invalid), None, "{:?}", invalid);
+	}
+}
+
+#[test]
+fn test_parse_longer_clock_durations() {
+	let minutes = |duration: &str| {
+		OrgParser::new("")
+			.parse_clock_line(&format!(
+				"CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:05] => {}",
+				duration
+			))
+			.unwrap()
+			.parse_duration_minutes()
+	};
+
+	assert_eq!(minutes("1:05"), Some(65));
+	assert_eq!(minutes("01:05"), Some(65));
+	assert_eq!(minutes("1:5"), Some(65));
+	assert_eq!(minutes("1:05:00"), Some(65));
+	assert_eq!(minutes("1:05:59"), Some(65));
+	assert_eq!(minutes("48:00"), Some(2880));
+	assert_eq!(minutes("27:30"), Some(1650));
+	assert_eq!(minutes("1d 3:00"), Some(1620));
+	assert_eq!(minutes("2d"), Some(2880));
+
+	for invalid in ["", "1", "1:05:00:00", "1:xx", "d 1:00", "1d x"] {
+		assert_eq!(minutes(invalid), None, "{:?}", invalid);
+	}
+}
+
+#[test]
+fn test_total_time_formats() {
+	let content = r#"* Long project
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-17 Wed 09:00] => 48:00
+CLOCK: [2024-01-18 Thu 09:00]--[2024-01-18 Thu 11:00] =>  2:00
+:END:
+"#;
+	let notes = parse(content);
+	let logbook = notes[0].logbook.as_ref().unwrap();
+
+	assert_eq!(logbook.format_total_time(), "50h 0m");
+	assert_eq!(logbook.format_total_time_as(TimeFormat::Days), "2d 2h 0m");
+	assert_eq!(logbook.format_total_time_as(TimeFormat::Clock), "50:00");
+
+	// Days only show up once there is a whole one
+	assert_eq!(TimeFormat::Days.format(330), "5h 30m");
+	assert_eq!(TimeFormat::Days.format(24 * 60 + 5), "1d 0h 5m");
+	assert_eq!(TimeFormat::Clock.format(65), "1:05");
+	assert_eq!("days".parse(), Ok(TimeFormat::Days));
+	assert!("weeks".parse::<TimeFormat>().is_err());
+}
+
+#[test]
+fn test_special_character_tags_round_trip() {
+	let content = r#"* Call mom :@home:
+* Review specs :proj_x:
+* Odds and ends :%misc:
+* TODO :@work:
+* Plan trip :@home:proj_x:%misc:
+** Email about :@work: draft
+*** Notes :a_b@c%d:
+"#;
+	let notes = parse(content);
+
+	let labels: Vec<(&str, Vec<&str>)> = iter_notes(&notes)
+		.map(|(_, note)| {
+			(
+				note.title.as_str(),
+				note.labels.iter().map(String::as_str).collect(),
+			)
+		})
+		.collect();
+	assert_eq!(
+		labels,
+		vec![
+			("Call mom", vec!["@home"]),
+			("Review specs", vec!["proj_x"]),
+			("Odds and ends", vec!["%misc"]),
+			("", vec!["@work"]),
+			("Plan trip", vec!["@home", "proj_x", "%misc"]),
+			// A tag group only counts at the end of the heading
+			("Email about :@work: draft", vec![]),
+			("Notes", vec!["a_b@c%d"]),
+		]
+	);
+	assert_eq!(notes[3].status.as_deref(), Some("TODO"));
+	assert_eq!(serialize(&notes), content);
+}
+
+#[test]
+fn test_grep_notes_in_titles_and_content() {
+	let content = r#"* TODO Buy milk
+Oat milk, not the other one
+* Work
+** Call the dairy
+Ask about MILK prices before the quarterly review, which is coming up far too soon
+"#;
+	let notes = parse(content);
+	let grep = |pattern: &str| {
+		grep_notes(&notes, &Regex::new(pattern).unwrap())
+			.into_iter()
+			.map(|found| (found.note.title.as_str(), found.in_title, found.snippet))
+			.collect::<Vec<_>>()
+	};
+
+	// A title match wins over one in the content
+	assert_eq!(
+		grep("(?i)milk"),
+		vec![
+			("Buy milk", true, "Buy milk".to_string()),
+			(
+				"Call the dairy",
+				false,
+				"Ask about MILK prices before the quarterly r…".to_string()
+			),
+		]
+	);
+	assert_eq!(
+		grep("MILK"),
+		vec![(
+			"Call the dairy",
+			false,
+			"Ask about MILK prices before the quarterly r…".to_string()
+		)]
+	);
+	assert_eq!(
+		grep("coming"),
+		vec![(
+			"Call the dairy",
+			false,
+			"…he quarterly review, which is coming up far too soon".to_string()
+		)]
+	);
+	assert!(grep("cheese").is_empty());
+}
+
+#[test]
+fn test_query_negation_and_or_groups() {
+	let content = r#"* Work :work:
+** TODO Report
+** NEXT Slides
+** DONE Budget
+* Home
+** TODO Laundry
+** WAITING Plumber :work:
+"#;
+	let notes = parse(content);
+	let query = |text: &str| {
+		let query: Query = text.parse().unwrap();
+		iter_notes(&query_notes(&notes, &query))
+			.map(|(_, note)| note.title.clone())
+			.collect::<Vec<_>>()
+	};
+
+	// Either status matches
+	assert_eq!(
+		query("status:TODO|status:NEXT"),
+		vec!["Work", "Report", "Slides", "Home", "Laundry"]
+	);
+	// Negation excludes inherited tags as well as a note's own
+	assert_eq!(query("status:TODO,!tag:work"), vec!["Home", "Laundry"]);
+	assert_eq!(query("!tag:work"), vec!["Home", "Laundry"]);
+	// `|` binds tighter than `,`
+	assert_eq!(
+		query("status:TODO|status:WAITING,tag:work"),
+		vec!["Work", "Report", "Home", "Plumber"]
+	);
+	assert_eq!(query("!!tag:work,status:DONE"), vec!["Work", "Budget"]);
+
+	assert_eq!(
+		"!tag:a|status:B".parse::<Query>(),
+		Ok(Query::All(vec![Query::Any(vec![
+			Query::Not(Box::new(Query::Tag("a".to_string()))),
+			Query::Status("B".to_string()),
+		])]))
+	);
+	for invalid in [
+		"",
+		"tag",
+		"tag:",
+		"colour:red",
+		"status:TODO,",
+		"priority:AB",
+	] {
+		assert!(invalid.parse::<Query>().is_err(), "{:?}", invalid);
+	}
+}
+
+#[test]
+fn test_keyword_lines_round_trip_verbatim() {
+	let content = r#"#+TITLE: Keywords
+#+startup: overview
+
+* Report
+#+CAPTION: Quarterly numbers
+#+ATTR_HTML: :width 100% :class  wide
+#+NAME:   tbl-q
+| q | n |
+|---+---|
+| 1 |  10 |
+#+TBLFM: $2=$1*10
+  #+attr_latex: :float nil
+#+begin_src python :results output
+print("x")
+#+END_SRC
+#+OPTIONS: toc:nil   
+"#;
+	let notes = parse(content);
+	assert_eq!(
+		preamble(content),
+		"#+TITLE: Keywords\n#+startup: overview\n\n"
+	);
+	assert_eq!(
+		serialize_document(preamble(content), &notes, &SerializeOptions::default()),
+		content
+	);
+
+	// Aligning the table leaves the surrounding `#+` lines alone
+	let options = SerializeOptions {
+		align_tables: true,
+		..SerializeOptions::default()
+	};
+	let aligned = serialize_document(preamble(content), &notes, &options);
+	for line in content
+		.lines()
+		.filter(|line| line.trim_start().starts_with("#+"))
+	{
+		assert!(aligned.lines().any(|aligned| aligned == line), "{:?}", line);
+	}
+	assert!(aligned.contains("| 1 | 10 |"));
+
+	// A preamble without a final newline still ends before the first heading
+	assert_eq!(
+		serialize_document("#+TITLE: x", &notes, &SerializeOptions::default())
+			.lines()
+			.nth(1),
+		Some("* Report")
+	);
+	assert_eq!(preamble("* Only\n#+TITLE: not a preamble\n"), "");
+}
+
+#[test]
+fn test_serialize_indentation_and_spacing_options() {
+	let content = "* TODO Plan :work:\n  SCHEDULED: <2024-01-01 Mon>\n  :PROPERTIES:\n  :ID: 1\n  :END:\n  Outline\n    - detail\n\n\n** Draft\nbody\n:LOGBOOK:\nCLOCK: [2024-01-02 Tue 09:00]--[2024-01-02 Tue 10:00] =>  1:00\n:END:\n* Done\n";
+	let notes = parse(content);
+	assert_eq!(
+		serialize_with_options(&notes, &SerializeOptions::default()),
+		content
+	);
+
+	let org_indent = SerializeOptions {
+		odd_levels: true,
+		indent_body: Some(true),
+		blank_line_after_notes: Some(true),
+		..SerializeOptions::default()
+	};
+	assert_eq!(
+		serialize_with_options(&notes, &org_indent),
+		"* TODO Plan :work:\n  SCHEDULED: <2024-01-01 Mon>\n  :PROPERTIES:\n  :ID: 1\n  :END:\n  Outline\n    - detail\n\n*** Draft\n    body\n    :LOGBOOK:\n    CLOCK: [2024-01-02 Tue 09:00]--[2024-01-02 Tue 10:00] =>  1:00\n    :END:\n\n* Done\n\n"
+	);
+	// Odd levels follow the depth, so an odd-levels file keeps its stars
+	let odd = parse(&serialize_with_options(&notes, &org_indent));
+	assert_eq!(odd[0].children[0].level, 3);
+	assert_eq!(
+		serialize_with_options(&odd, &org_indent),
+		serialize_with_options(&notes, &org_indent)
+	);
+
+	let spaced = parse(&serialize_with_options(&notes, &org_indent));
+	assert!(!spaced[0].structural_eq(&notes[0]));
+	assert_eq!(spaced[0].children[0].level, 3);
+
+	let flush = SerializeOptions {
+		indent_body: Some(false),
+		blank_line_after_notes: Some(false),
+		..SerializeOptions::default()
+	};
+	assert_eq!(
+		serialize_with_options(&notes, &flush),
+		"* TODO Plan :work:\nSCHEDULED: <2024-01-01 Mon>\n:PROPERTIES:\n:ID: 1\n:END:\nOutline\n  - detail\n** Draft\nbody\n:LOGBOOK:\nCLOCK: [2024-01-02 Tue 09:00]--[2024-01-02 Tue 10:00] =>  1:00\n:END:\n* Done\n"
+	); // Only the formatting changed
+	let reparsed = parse(&serialize_with_options(&notes, &flush));
+	assert!(reparsed.iter().zip(&notes).all(|(a, b)| a.structural_eq(b)));
+}
+
+#[test]
+fn test_structural_eq_ignores_formatting() {
+	let tidy = parse("* TODO [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Child\n");
+	let messy = parse(
+		"*  TODO  [#A]   Task\t:a: :b:\n    SCHEDULED:   [2024-01-05 Thu 09:00]\n  Some notes.\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00\n:END:\n\n** Child\n",
+	);
+	assert!(tidy[0].structural_eq(&messy[0]));
+	assert!(messy[0].structural_eq(&tidy[0]));
+
+	let mut raw_changed =
+		parse("* TODO [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Child\n");
+	let scheduled = raw_changed[0]
+		.planning
+		.as_mut()
+		.unwrap()
+		.scheduled
+		.as_mut()
+		.unwrap();
+	scheduled.raw = "<2024-01-05 Friday 9:00>".to_string();
+	assert!(raw_changed[0].structural_eq(&tidy[0]));
+
+	for other in [
+		"* TODO [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:30>\n** Child\n",
+		"* TODO [#A] Task :a:b:\nDEADLINE: <2024-01-05 Fri 09:00>\n** Child\n",
+		"* TODO [#A] Task :b:a:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Child\n",
+		"* TODO [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Kid\n",
+		"* TODO [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n*** Child\n",
+		"* TODO [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Child\n** Child\n",
+		"* DONE [#A] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Child\n",
+		"* TODO [#B] Task :a:b:\nSCHEDULED: <2024-01-05 Fri 09:00>\n** Child\n",
+	] {
+		assert!(!parse(other)[0].structural_eq(&tidy[0]), "{:?}", other);
 	}
 }
@@ -0,0 +1,1848 @@
+use crate::{
+	Action, App, CaptureTemplate, ClockReport, Debounce, EditMode, FieldRef, Focus, Keymap,
+	LoopEvent, NowAction, ReloadDecision, RunStatus, SavePreview, StatusStyles, add_tags,
+	apply_status_colors, capture_note, changed_on_disk, clamp_scroll, cli, collect_time_stats,
+	commit_edit, content_hash, cursor_offset, date_column, emit_output, expand_tabs,
+	handle_left_panel_input, handle_reload_prompt, handle_right_panel_input,
+	handle_save_preview_input, handle_tag_edit_input, heading_prefix, load_config, needs_redraw,
+	next_overdue, note_fields, now_action_for_field, org_files_in, output_is_watched,
+	parse_status_color, parse_timestamp_input, print_notes, relative_date_label, reload_decision,
+	remove_tag, resolve_goto_line, resolve_goto_target, resolve_goto_title, run,
+	running_elapsed_text, save_diff, set_focused_field_to_now, start_editing, start_planning_edit,
+	timeline_bar, ui, wrapped_line_count,
+};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::Terminal;
+use ratatui::backend::TestBackend;
+use ratatui::style::Color;
+use rorg::{
+	Keywords, OrgNote, OrgParser, OrgTimestamp, SerializeOptions, TimestampKind, iter_notes,
+	next_todo, parse, prev_todo, serialize, serialize_with_options,
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_wrapped_line_count() {
+	assert_eq!(wrapped_line_count("", 10), 0);
+	assert_eq!(wrapped_line_count("short", 10), 1);
+	assert_eq!(wrapped_line_count("one\ntwo\n\nfour", 10), 4);
+	assert_eq!(wrapped_line_count("aaaa bbbb cccc", 9), 2);
+	assert_eq!(wrapped_line_count("abcdefghijklmnopqrstuvwxy", 10), 3);
+}
+
+#[test]
+fn test_clamp_scroll() {
+	// Content fits in the viewport: no scrolling possible
+	assert_eq!(clamp_scroll(0, 5, 10), 0);
+	assert_eq!(clamp_scroll(3, 5, 10), 0);
+
+	// Content taller than the viewport: stop once the last line is visible
+	assert_eq!(clamp_scroll(0, 30, 10), 0);
+	assert_eq!(clamp_scroll(15, 30, 10), 15);
+	assert_eq!(clamp_scroll(20, 30, 10), 20);
+	assert_eq!(clamp_scroll(25, 30, 10), 20);
+	assert_eq!(clamp_scroll(u16::MAX, 30, 10), 20);
+}
+
+fn sample_tree() -> Vec<OrgNote> {
+	let content = r#"* First
+** Child A
+*** Grandchild
+** Child B
+* Second"#;
+	OrgParser::new(content).parse()
+}
+
+#[test]
+fn test_insert_sibling() {
+	let mut notes = sample_tree();
+
+	// Sibling of "Child A" goes after its whole subtree, before "Child B"
+	let idx = App::insert_sibling(&mut notes, 1, OrgNote::new(1, "New".to_string()));
+	assert_eq!(idx, Some(3));
+	let children = &notes[0].children;
+	assert_eq!(children.len(), 3);
+	assert_eq!(children[1].title, "New");
+	assert_eq!(children[1].level, 2);
+	assert_eq!(children[2].title, "Child B");
+
+	// Sibling of the last top-level note is appended at the top level
+	let idx = App::insert_sibling(&mut notes, 5, OrgNote::new(1, "Last".to_string()));
+	assert_eq!(idx, Some(6));
+	assert_eq!(notes.len(), 3);
+	assert_eq!(notes[2].title, "Last");
+	assert_eq!(notes[2].level, 1);
+
+	assert_eq!(
+		App::insert_sibling(&mut notes, 42, OrgNote::new(1, "x".to_string())),
+		None
+	);
+}
+
+#[test]
+fn test_insert_child() {
+	let mut notes = sample_tree();
+
+	// Child of "First" is appended after "Child B"
+	let idx = App::insert_child(&mut notes, 0, OrgNote::new(1, "New".to_string()));
+	assert_eq!(idx, Some(4));
+	assert_eq!(notes[0].children.len(), 3);
+	assert_eq!(notes[0].children[2].title, "New");
+	assert_eq!(notes[0].children[2].level, 2);
+
+	// Child of a leaf note creates its first child
+	let idx = App::insert_child(&mut notes, 2, OrgNote::new(1, "Deep".to_string()));
+	assert_eq!(idx, Some(3));
+	let grandchild = &notes[0].children[0].children[0];
+	assert_eq!(grandchild.children.len(), 1);
+	assert_eq!(grandchild.children[0].title, "Deep");
+	assert_eq!(grandchild.children[0].level, 4);
+
+	assert_eq!(
+		App::insert_child(&mut notes, 42, OrgNote::new(1, "x".to_string())),
+		None
+	);
+}
+
+fn app_from(content: &str) -> App {
+	App::new(OrgParser::new(content).parse(), "test.org".to_string())
+}
+
+#[test]
+fn test_commit_creates_and_clears_planning_fields() {
+	let modes = [
+		(EditMode::Scheduled, "<2024-02-01 Thu 09:00>"),
+		(EditMode::Deadline, "<2024-02-10 Sat>"),
+		(EditMode::Closed, "[2024-02-05 Mon 17:30]"),
+	];
+
+	for (mode, raw) in modes {
+		let mut app = app_from("* TODO Task\nBody");
+		assert!(app.notes[0].planning.is_none());
+
+		// Creating a missing field
+		start_planning_edit(&mut app, mode.clone());
+		assert_eq!(app.edit_mode, mode);
+		assert!(app.edit_buffer.is_empty());
+		app.edit_buffer = raw.to_string();
+		commit_edit(&mut app);
+
+		let planning = app.notes[0].planning.as_ref().unwrap();
+		let field = match mode {
+			EditMode::Scheduled => &planning.scheduled,
+			EditMode::Deadline => &planning.deadline,
+			_ => &planning.closed,
+		};
+		assert_eq!(field.as_ref().unwrap().raw, raw);
+		assert!(app.modified);
+		assert_eq!(app.selected_field_idx, 2); // status, title, then the new field
+
+		// Clearing it again with an empty buffer
+		start_planning_edit(&mut app, mode.clone());
+		assert_eq!(app.edit_buffer, raw);
+		app.edit_buffer.clear();
+		commit_edit(&mut app);
+		assert!(app.notes[0].planning.is_none());
+		assert_eq!(app.edit_mode, EditMode::None);
+	}
+}
+
+#[test]
+fn test_commit_clearing_one_planning_field_keeps_others() {
+	let mut app = app_from("* TODO Task\nSCHEDULED: <2024-02-01 Thu>\nDEADLINE: <2024-02-10 Sat>");
+
+	start_planning_edit(&mut app, EditMode::Scheduled);
+	app.edit_buffer.clear();
+	commit_edit(&mut app);
+
+	let planning = app.notes[0].planning.as_ref().unwrap();
+	assert!(planning.scheduled.is_none());
+	assert_eq!(planning.deadline.as_ref().unwrap().day, 10);
+}
+
+#[test]
+fn test_commit_rejects_invalid_planning_timestamp() {
+	let mut app = app_from("* TODO Task");
+
+	start_planning_edit(&mut app, EditMode::Deadline);
+	app.edit_buffer = "next week".to_string();
+	commit_edit(&mut app);
+
+	assert!(app.notes[0].planning.is_none());
+	assert!(!app.modified);
+	assert!(app.status_message.starts_with("Invalid timestamp"));
+}
+
+#[test]
+fn test_commit_clock_entry_rederives_duration() {
+	let mut app = app_from(
+		"* DONE Task\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 12:00] =>  3:00\n:END:\nBody",
+	);
+
+	// status, title, then the clock entry
+	app.selected_field_idx = 2;
+	start_editing(&mut app);
+	assert_eq!(app.edit_mode, EditMode::ClockEntry(0));
+
+	// Move the end time but leave the stale duration text in place
+	app.edit_buffer = "CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:30] =>  3:00".to_string();
+	commit_edit(&mut app);
+
+	let note = &app.notes[0];
+	let entry = &note.logbook.as_ref().unwrap().clock_entries[0];
+	assert_eq!(entry.end.as_ref().unwrap().minute, Some(30));
+	assert_eq!(entry.duration, Some("1:30".to_string()));
+	assert_eq!(
+		entry.raw,
+		"CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:30] =>  1:30"
+	);
+	assert_eq!(note.content, "Body");
+}
+
+#[test]
+fn test_commit_content_rederives_links_and_tables() {
+	let mut app = app_from("* Task\nSee [[https://old.example]]");
+	assert_eq!(app.notes[0].links.len(), 1);
+
+	app.edit_mode = EditMode::Content;
+	app.edit_buffer = "See [[https://new.example][new]]\n| a | b |".to_string();
+	commit_edit(&mut app);
+
+	let note = &app.notes[0];
+	assert_eq!(note.links.len(), 1);
+	assert_eq!(note.links[0].target, "https://new.example");
+	assert_eq!(note.links[0].description.as_deref(), Some("new"));
+	assert_eq!(note.tables.len(), 1);
+}
+
+#[test]
+fn test_commit_clock_entry_rejects_invalid_input() {
+	let original = "CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 12:00] =>  3:00";
+	let mut app = app_from(&format!("* DONE Task\n:LOGBOOK:\n{}\n:END:", original));
+
+	app.selected_field_idx = 2;
+	start_editing(&mut app);
+	app.edit_buffer = "not a clock line".to_string();
+	commit_edit(&mut app);
+
+	let entry = &app.notes[0].logbook.as_ref().unwrap().clock_entries[0];
+	assert_eq!(entry.raw, original);
+	assert!(!app.modified);
+	assert!(app.status_message.starts_with("Invalid clock entry"));
+
+	start_editing(&mut app);
+	app.edit_buffer = "CLOCK: [2024-01-01 Mon 12:00]--[2024-01-01 Mon 09:00]".to_string();
+	commit_edit(&mut app);
+	assert_eq!(
+		app.notes[0].logbook.as_ref().unwrap().clock_entries[0].raw,
+		original
+	);
+	assert_eq!(app.status_message, "Clock entry ends before it starts");
+}
+
+#[test]
+fn test_now_action_for_field() {
+	let content = r#"* TODO Task :work:
+SCHEDULED: <2024-01-20 Sat 09:00>
+CLOSED: [2024-01-21 Sun 10:00]
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+CLOCK: [2024-01-02 Tue 09:00]
+:END:
+Body"#;
+	let notes = OrgParser::new(content).parse();
+	let note = &notes[0];
+
+	// status, title, labels
+	assert_eq!(now_action_for_field(note, 0), None);
+	assert_eq!(now_action_for_field(note, 1), None);
+	assert_eq!(now_action_for_field(note, 2), None);
+	assert_eq!(
+		now_action_for_field(note, 3),
+		Some(NowAction::SetPlanning("scheduled"))
+	);
+	assert_eq!(
+		now_action_for_field(note, 4),
+		Some(NowAction::SetPlanning("closed"))
+	);
+	assert_eq!(now_action_for_field(note, 5), Some(NowAction::ClockOut(0)));
+	assert_eq!(now_action_for_field(note, 6), Some(NowAction::ClockOut(1)));
+	// content
+	assert_eq!(now_action_for_field(note, 7), None);
+}
+
+#[test]
+fn test_set_focused_clock_entry_to_now() {
+	let mut app = app_from(
+		"* TODO Task\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00\nCLOCK: [2024-01-02 Tue 09:00]\n:END:",
+	);
+
+	// Closed entries are left alone
+	app.selected_field_idx = 2;
+	set_focused_field_to_now(&mut app);
+	assert_eq!(app.status_message, "Clock entry is not running");
+	assert!(!app.modified);
+
+	// The running entry is clocked out
+	app.selected_field_idx = 3;
+	set_focused_field_to_now(&mut app);
+	let entry = &app.notes[0].logbook.as_ref().unwrap().clock_entries[1];
+	assert!(entry.end.is_some());
+	assert!(entry.duration.is_some());
+	assert!(entry.raw.starts_with("CLOCK: [2024-01-02 Tue 09:00]--["));
+	assert!(app.modified);
+}
+
+#[test]
+fn test_set_current_time_closes_with_an_inactive_timestamp() {
+	let mut app = app_from("* TODO Task");
+	app.set_current_time("scheduled");
+	app.set_current_time("closed");
+
+	let planning = app.notes[0].planning.as_ref().unwrap();
+	assert!(planning.scheduled.as_ref().unwrap().raw.starts_with('<'));
+	assert!(planning.closed.as_ref().unwrap().raw.starts_with('['));
+	assert!(app.modified);
+}
+
+#[test]
+fn test_note_fields_fully_populated() {
+	let content = r#"* TODO Task :work:home:
+SCHEDULED: <2024-01-20 Sat 09:00>
+DEADLINE: <2024-01-31 Wed>
+CLOSED: [2024-01-21 Sun 10:00]
+:LOGBOOK:
+CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00
+CLOCK: [2024-01-02 Tue 09:00]
+:END:
+Body"#;
+	let notes = OrgParser::new(content).parse();
+
+	assert_eq!(
+		note_fields(&notes[0]),
+		vec![
+			FieldRef::Status,
+			FieldRef::Title,
+			FieldRef::Labels,
+			FieldRef::Scheduled,
+			FieldRef::Deadline,
+			FieldRef::Closed,
+			FieldRef::ClockEntry(0),
+			FieldRef::ClockEntry(1),
+			FieldRef::Content,
+		]
+	);
+}
+
+#[test]
+fn test_note_fields_minimal() {
+	let note = OrgNote::new(1, "Plain".to_string());
+	assert_eq!(note_fields(&note), vec![FieldRef::Title, FieldRef::Content]);
+}
+
+#[test]
+fn test_summary_skips_archived_subtrees() {
+	let content = r#"* TODO Current
+:LOGBOOK:
+CLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00
+:END:
+* Old project :ARCHIVE:
+:LOGBOOK:
+CLOCK: [2023-01-15 Sun 09:00]--[2023-01-15 Sun 12:00] =>  3:00
+:END:
+** DONE Archived task
+** TODO Forgotten task
+"#;
+
+	let notes = parse(content);
+	assert!(notes[1].is_archived());
+	assert_eq!(notes[1].children.len(), 2);
+	assert_eq!(serialize(&notes), content);
+
+	let (mut minutes, mut completed, mut active, mut scheduled, mut overdue) = (0, 0, 0, 0, 0);
+	collect_time_stats(
+		&notes,
+		&ClockReport::default(),
+		&mut minutes,
+		&mut completed,
+		&mut active,
+		&mut scheduled,
+		&mut overdue,
+	);
+	assert_eq!(minutes, 60);
+	assert_eq!(completed, 0);
+	assert_eq!(active, 1);
+}
+
+#[test]
+fn test_summary_counts_configured_keywords() {
+	let notes = parse(
+		"* TODO Write\nDEADLINE: <2024-01-10 Wed>\n* WAITING Reply\nDEADLINE: <2024-01-12 Fri>\n\
+		 * DONE Ship\nDEADLINE: <2024-01-05 Fri>\n* CANCELLED Drop\n",
+	);
+	let count = |report: &ClockReport| {
+		let (mut minutes, mut completed, mut active, mut scheduled, mut overdue) = (0, 0, 0, 0, 0);
+		collect_time_stats(
+			&notes,
+			report,
+			&mut minutes,
+			&mut completed,
+			&mut active,
+			&mut scheduled,
+			&mut overdue,
+		);
+		(completed, active, overdue)
+	};
+
+	// CANCELLED is a done keyword by default, WAITING is unknown, and the
+	// finished task's missed deadline isn't overdue
+	assert_eq!(count(&ClockReport::default()), (2, 1, 1));
+
+	let mut report = ClockReport::default();
+	let warnings = load_config(
+		"[keywords]\nactive = [\"TODO\", \"WAITING\"]\ndone = [\"DONE\", \"CANCELLED\"]\n",
+		&mut Keymap::default(),
+		&mut StatusStyles::default(),
+		&mut HashMap::new(),
+		&mut report.keywords,
+	);
+	assert!(warnings.is_empty());
+	assert_eq!(count(&report), (2, 2, 2));
+}
+
+#[test]
+fn test_completion_follows_configured_keywords() {
+	let mut app = app_from("* WAITING Reply\n* TODO Write\n");
+	app.keywords = Keywords {
+		active: vec!["WAITING".to_string(), "TODO".to_string()],
+		done: vec!["DONE".to_string(), "CANCELLED".to_string()],
+	};
+
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 1);
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 0);
+
+	// Cancelling a running task counts as finishing it
+	app.clock_in();
+	app.selected_field_idx = 0;
+	start_editing(&mut app);
+	app.edit_buffer = "CANCELLED".to_string();
+	commit_edit(&mut app);
+	assert!(!app.selected_clock_running());
+	assert_eq!(
+		next_todo(&app.notes, &app.keywords, 0),
+		Some(app.notes[1].id)
+	);
+}
+
+#[test]
+fn test_marking_done_clocks_out_running_entry() {
+	let mut app = app_from("* TODO Task\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]\n:END:\n");
+
+	app.selected_field_idx = 0;
+	start_editing(&mut app);
+	assert_eq!(app.edit_mode, EditMode::Status);
+	app.edit_buffer = "DONE".to_string();
+	commit_edit(&mut app);
+
+	let note = &app.notes[0];
+	assert_eq!(note.status.as_deref(), Some("DONE"));
+	let entry = &note.logbook.as_ref().unwrap().clock_entries[0];
+	assert!(entry.end.is_some());
+	assert!(entry.duration.is_some());
+	assert!(entry.raw.starts_with("CLOCK: [2024-01-01 Mon 09:00]--["));
+}
+
+#[test]
+fn test_running_elapsed_text() {
+	let entry = OrgParser::new("")
+		.parse_clock_line("CLOCK: [2024-01-01 Mon 09:00]")
+		.unwrap();
+	let at = |time: &str| {
+		NaiveDateTime::parse_from_str(&format!("2024-01-01 {}", time), "%Y-%m-%d %H:%M").unwrap()
+	};
+
+	assert_eq!(
+		running_elapsed_text(&entry, at("10:23")),
+		Some("1h 23m".to_string())
+	);
+	assert_eq!(
+		running_elapsed_text(&entry, at("09:05")),
+		Some("0h 5m".to_string())
+	);
+	// A clock that starts in the future counts as not running yet
+	assert_eq!(
+		running_elapsed_text(&entry, at("08:00")),
+		Some("0h 0m".to_string())
+	);
+}
+
+#[test]
+fn test_needs_redraw_on_tick_only_for_running_clock() {
+	let mut app = app_from(
+		"* Idle\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00\n:END:\n\
+		 * Busy\n:LOGBOOK:\nCLOCK: [2024-01-01 Mon 11:00]\n:END:\n",
+	);
+
+	assert!(needs_redraw(&app, LoopEvent::Input));
+	assert!(!needs_redraw(&app, LoopEvent::Tick));
+
+	app.selected_note_idx = 1;
+	assert!(needs_redraw(&app, LoopEvent::Tick));
+}
+
+#[test]
+fn test_clock_lines_share_indentation_after_clock_in() {
+	let mut app = app_from(
+		"* Task\n:LOGBOOK:\n    CLOCK: [2024-01-01 Mon 09:00]--[2024-01-01 Mon 10:00] =>  1:00\n:END:\n",
+	);
+	app.clock_in();
+
+	let options = SerializeOptions {
+		clock_indent: Some("  ".to_string()),
+		..SerializeOptions::default()
+	};
+	let indented = serialize_with_options(&app.notes, &options);
+	let clock_lines: Vec<&str> = indented
+		.lines()
+		.filter(|line| line.trim_start().starts_with("CLOCK:"))
+		.collect();
+	assert_eq!(clock_lines.len(), 2);
+	assert!(clock_lines.iter().all(|line| line.starts_with("  CLOCK:")));
+
+	let flush = serialize(&app.notes);
+	assert!(
+		flush
+			.lines()
+			.filter(|line| line.contains("CLOCK:"))
+			.all(|line| line.starts_with("CLOCK:"))
+	);
+}
+
+#[test]
+fn test_resolve_goto_target() {
+	let notes = parse("* Work\n** ProjectX\n*** Design\n*** Build\n* Home\n");
+
+	assert_eq!(resolve_goto_target(&notes, "Work/ProjectX/Design"), Some(2));
+	assert_eq!(resolve_goto_target(&notes, " Home "), Some(4));
+	assert_eq!(resolve_goto_target(&notes, "Work/ProjectX/Build/"), Some(3));
+	assert_eq!(resolve_goto_target(&notes, "Work/Design"), None);
+	assert_eq!(resolve_goto_target(&notes, ""), None);
+
+	// Numbers are 1-based positions in the heading list
+	assert_eq!(resolve_goto_target(&notes, "2"), Some(1));
+	assert_eq!(resolve_goto_target(&notes, "0"), None);
+	assert_eq!(resolve_goto_target(&notes, "6"), None);
+}
+
+#[test]
+fn test_goto_prompt_selects_heading_or_reports_miss() {
+	let mut app = app_from("* Work\n** ProjectX\n* Home\n");
+
+	app.edit_mode = EditMode::GotoPath;
+	app.edit_buffer = "Work/ProjectX".to_string();
+	commit_edit(&mut app);
+	assert_eq!(app.selected_note_idx, 1);
+	assert_eq!(app.edit_mode, EditMode::None);
+
+	app.edit_mode = EditMode::GotoPath;
+	app.edit_buffer = "Nowhere".to_string();
+	commit_edit(&mut app);
+	assert_eq!(app.selected_note_idx, 1);
+	assert_eq!(app.status_message, "Not found: Nowhere");
+}
+
+#[test]
+fn test_collapse_all_and_expand_all() {
+	let mut app = app_from("* Work\n** Design\n*** Sketch\n** Build\n* Home\n** Garden\n* Inbox\n");
+	let visible = |app: &App| {
+		app.flat_notes
+			.iter()
+			.map(|(idx, _)| *idx)
+			.collect::<Vec<_>>()
+	};
+
+	// Select "Sketch", which collapsing hides
+	app.select_note(2);
+	app.collapse_all();
+	assert_eq!(app.collapsed, [0, 4].into_iter().collect());
+	assert_eq!(visible(&app), vec![0, 4, 6]);
+	assert_eq!(app.selected_note_idx, 0);
+	assert_eq!(app.list_state.selected(), Some(0));
+	assert!(app.flat_notes[0].1.ends_with("Work ..."));
+
+	// Moving down skips the hidden children
+	handle_left_panel_input(&mut app, KeyCode::Down);
+	assert_eq!(app.selected_note_idx, 4);
+
+	app.expand_all();
+	assert!(app.collapsed.is_empty());
+	assert_eq!(visible(&app), (0..7).collect::<Vec<_>>());
+	assert_eq!(app.selected_note_idx, 4);
+	assert_eq!(app.list_state.selected(), Some(4));
+}
+
+#[test]
+fn test_folds_follow_inserted_and_deleted_notes() {
+	let mut app = app_from("* Work\n** Design\n* Home\n** Garden\n");
+	app.select_note(2);
+	app.toggle_fold();
+	assert_eq!(app.collapsed, [2].into_iter().collect());
+
+	// A new sibling of "Work" lands before "Home", shifting its fold
+	app.select_note(0);
+	app.add_note(false);
+	assert_eq!(app.collapsed, [3].into_iter().collect());
+
+	// Deleting "Work" and its child shifts it back
+	app.select_note(0);
+	app.delete_selected_note();
+	assert_eq!(app.collapsed, [1].into_iter().collect());
+	assert_eq!(app.flat_notes.len(), 2);
+}
+
+#[test]
+fn test_status_styles() {
+	let mut styles = StatusStyles::default();
+	let color =
+		|styles: &StatusStyles, keyword: &str| styles.style_for(keyword, &Keywords::default()).fg;
+
+	assert_eq!(color(&styles, "TODO"), Some(Color::Red));
+	assert_eq!(color(&styles, "DONE"), Some(Color::Green));
+	assert_eq!(color(&styles, "IN-PROGRESS"), Some(Color::Yellow));
+	assert_eq!(color(&styles, "WAITING"), Some(Color::Red));
+
+	styles.set("TODO", Color::Magenta);
+	styles.set("WAITING", Color::Blue);
+	assert_eq!(color(&styles, "TODO"), Some(Color::Magenta));
+	assert_eq!(color(&styles, "WAITING"), Some(Color::Blue));
+	assert_eq!(color(&styles, "DONE"), Some(Color::Green));
+
+	// Configured done keywords are colored as done
+	let keywords = Keywords {
+		active: vec!["TODO".to_string()],
+		done: vec!["SHIPPED".to_string()],
+	};
+	assert_eq!(
+		styles.style_for("SHIPPED", &keywords).fg,
+		Some(Color::Green)
+	);
+	assert_eq!(styles.style_for("DONE", &keywords).fg, Some(Color::Red));
+
+	assert_eq!(
+		parse_status_color("NEXT=blue"),
+		Ok(("NEXT".to_string(), Color::Blue))
+	);
+	assert!(parse_status_color("NEXT").is_err());
+	assert!(parse_status_color("NEXT=notacolor").is_err());
+
+	// --status-color beats the config file
+	let mut styles = StatusStyles::default();
+	let warnings = load_config(
+		"[status_colors]\nNEXT = \"red\"\nTODO = \"magenta\"\n",
+		&mut Keymap::default(),
+		&mut styles,
+		&mut HashMap::new(),
+		&mut Keywords::default(),
+	);
+	assert!(warnings.is_empty());
+	let matches = cli()
+		.try_get_matches_from(["rorg", "plans.org", "--status-color", "NEXT=blue"])
+		.unwrap();
+	apply_status_colors(&mut styles, &matches);
+	assert_eq!(color(&styles, "NEXT"), Some(Color::Blue));
+	assert_eq!(color(&styles, "TODO"), Some(Color::Magenta));
+}
+
+#[test]
+fn test_keymap_defaults_and_rebinding() {
+	let mut keymap = Keymap::default();
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('i'), KeyModifiers::NONE),
+		Some(Action::ClockIn)
+	);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('N'), KeyModifiers::SHIFT),
+		Some(Action::AddChild)
+	);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('s'), KeyModifiers::CONTROL),
+		Some(Action::Save)
+	);
+
+	keymap.bind("clock_in", "I").unwrap();
+	assert_eq!(keymap.resolve(KeyCode::Char('i'), KeyModifiers::NONE), None);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('I'), KeyModifiers::SHIFT),
+		Some(Action::ClockIn)
+	);
+	assert!(keymap.bind("fly", "f").is_err());
+	assert!(keymap.bind("quit", "ctrl+").is_err());
+
+	// Taking another action's key is refused, and both keep their keys
+	assert_eq!(
+		keymap.bind("save", "q"),
+		Err("'q' is already bound to quit".to_string())
+	);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('q'), KeyModifiers::NONE),
+		Some(Action::Quit)
+	);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('s'), KeyModifiers::CONTROL),
+		Some(Action::Save)
+	);
+	// Rebinding an action to its own key is fine
+	keymap.bind("quit", "q").unwrap();
+}
+
+#[test]
+fn test_load_config() {
+	let mut keymap = Keymap::default();
+	let mut styles = StatusStyles::default();
+	let warnings = load_config(
+		r#"
+[keys]
+clock_out = "O"
+save = "ctrl+w"
+teleport = "t"
+
+[status_colors]
+NEXT = "blue"
+"#,
+		&mut keymap,
+		&mut styles,
+		&mut HashMap::new(),
+		&mut Keywords::default(),
+	);
+
+	assert_eq!(warnings, vec!["keys.teleport: unknown action 'teleport'"]);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('O'), KeyModifiers::SHIFT),
+		Some(Action::ClockOut)
+	);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('w'), KeyModifiers::CONTROL),
+		Some(Action::Save)
+	);
+	// Unmapped actions keep their defaults
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('q'), KeyModifiers::NONE),
+		Some(Action::Quit)
+	);
+	assert_eq!(
+		styles.style_for("NEXT", &Keywords::default()).fg,
+		Some(Color::Blue)
+	);
+
+	let mut keymap = Keymap::default();
+	let warnings = load_config(
+		"[keys]\nsave = \"q\"\n",
+		&mut keymap,
+		&mut styles,
+		&mut HashMap::new(),
+		&mut Keywords::default(),
+	);
+	assert_eq!(warnings, vec!["keys.save: 'q' is already bound to quit"]);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('s'), KeyModifiers::CONTROL),
+		Some(Action::Save)
+	);
+
+	let mut keymap = Keymap::default();
+	let warnings = load_config(
+		"[keys\nquit = ",
+		&mut keymap,
+		&mut styles,
+		&mut HashMap::new(),
+		&mut Keywords::default(),
+	);
+	assert_eq!(warnings.len(), 1);
+	assert!(warnings[0].starts_with("invalid config"));
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('q'), KeyModifiers::NONE),
+		Some(Action::Quit)
+	);
+}
+
+#[test]
+fn test_vim_keymap() {
+	let keymap = Keymap::vim();
+	let resolve = |c| keymap.resolve(KeyCode::Char(c), KeyModifiers::NONE);
+
+	assert_eq!(resolve('j'), Some(Action::MoveDown));
+	assert_eq!(resolve('k'), Some(Action::MoveUp));
+	assert_eq!(resolve('h'), Some(Action::FocusLeft));
+	assert_eq!(resolve('l'), Some(Action::FocusRight));
+	assert_eq!(resolve('s'), Some(Action::ScheduleNow));
+	assert_eq!(resolve('d'), Some(Action::DeadlineNow));
+	assert_eq!(resolve('i'), Some(Action::ClockIn));
+
+	// The default keymap has no hjkl navigation
+	let default = Keymap::default();
+	assert_eq!(
+		default.resolve(KeyCode::Char('k'), KeyModifiers::NONE),
+		Some(Action::ScheduleNow)
+	);
+	assert_eq!(
+		default.resolve(KeyCode::Char('j'), KeyModifiers::NONE),
+		None
+	);
+
+	let mut keymap = Keymap::default();
+	load_config(
+		"vim = true",
+		&mut keymap,
+		&mut StatusStyles::default(),
+		&mut HashMap::new(),
+		&mut Keywords::default(),
+	);
+	assert_eq!(
+		keymap.resolve(KeyCode::Char('j'), KeyModifiers::NONE),
+		Some(Action::MoveDown)
+	);
+}
+
+#[test]
+fn test_add_and_remove_tags() {
+	let mut labels = vec!["work".to_string()];
+
+	assert_eq!(add_tags(&mut labels, "urgent"), Ok(1));
+	assert_eq!(add_tags(&mut labels, "work"), Ok(0));
+	assert_eq!(add_tags(&mut labels, ":home:work: @phone"), Ok(2));
+	assert_eq!(labels, vec!["work", "urgent", "home", "@phone"]);
+
+	assert!(add_tags(&mut labels, "ok bad-tag").is_err());
+	assert_eq!(labels.len(), 4);
+
+	assert_eq!(remove_tag(&mut labels, 1), Some("urgent".to_string()));
+	assert_eq!(remove_tag(&mut labels, 5), None);
+	assert_eq!(labels, vec!["work", "home", "@phone"]);
+}
+
+#[test]
+fn test_tag_edit_mode() {
+	let mut app = app_from("* Task :work:home:\n");
+	app.selected_field_idx = note_fields(&app.notes[0])
+		.iter()
+		.position(|field| *field == FieldRef::Labels)
+		.unwrap();
+	start_editing(&mut app);
+	assert_eq!(app.edit_mode, EditMode::Labels);
+	assert!(app.edit_buffer.is_empty());
+
+	app.edit_buffer = "urgent".to_string();
+	handle_tag_edit_input(&mut app, KeyCode::Enter);
+	assert_eq!(app.notes[0].labels, vec!["work", "home", "urgent"]);
+	assert_eq!(app.edit_mode, EditMode::Labels);
+
+	// Left from nothing selected picks the last tag
+	handle_tag_edit_input(&mut app, KeyCode::Left);
+	handle_tag_edit_input(&mut app, KeyCode::Left);
+	handle_tag_edit_input(&mut app, KeyCode::Delete);
+	assert_eq!(app.notes[0].labels, vec!["work", "urgent"]);
+	assert_eq!(app.tag_cursor, Some(1));
+
+	handle_tag_edit_input(&mut app, KeyCode::Enter);
+	assert_eq!(app.edit_mode, EditMode::None);
+	assert!(app.modified);
+
+	// Esc throws away everything added, removed or typed since the edit began
+	let mut app = app_from("* Task :work:home:\n");
+	app.selected_field_idx = note_fields(&app.notes[0])
+		.iter()
+		.position(|field| *field == FieldRef::Labels)
+		.unwrap();
+	start_editing(&mut app);
+	app.edit_buffer = "urgent".to_string();
+	handle_tag_edit_input(&mut app, KeyCode::Enter);
+	handle_tag_edit_input(&mut app, KeyCode::Left);
+	handle_tag_edit_input(&mut app, KeyCode::Delete);
+	app.edit_buffer = "typed".to_string();
+	handle_tag_edit_input(&mut app, KeyCode::Esc);
+	assert_eq!(app.notes[0].labels, vec!["work", "home"]);
+	assert_eq!(app.edit_mode, EditMode::None);
+	assert!(app.edit_buffer.is_empty());
+	assert!(!app.modified);
+}
+
+#[test]
+fn test_list_shows_stars_from_level_not_depth() {
+	let mut app = app_from("* Work\n** Design\n");
+	// Edits can leave a child with a level that doesn't match its depth
+	app.notes[0]
+		.children
+		.push(OrgNote::new(1, "Misplaced".to_string()));
+	app.notes[0].children[0]
+		.children
+		.push(OrgNote::new(0, "No level".to_string()));
+	app.refresh_flat_notes();
+
+	let displays: Vec<&str> = app.flat_notes.iter().map(|(_, d)| d.as_str()).collect();
+	assert_eq!(
+		displays,
+		vec!["* Work", "  ** Design", "    * No level", "  * Misplaced"]
+	);
+	assert_eq!(heading_prefix(&app.notes[0].children[1], 1), "  * ");
+}
+
+#[test]
+fn test_save_diff_is_empty_for_unchanged_file() {
+	let content = "* TODO [#A] Task :work:\nSCHEDULED: <2024-01-15 Mon>\n:LOGBOOK:\nCLOCK: [2024-01-15 Mon 09:00]--[2024-01-15 Mon 10:00] =>  1:00\n:END:\nSome notes.\n** Child\n";
+	assert_eq!(
+		save_diff(content, &serialize(&parse(content)), "test.org"),
+		""
+	);
+
+	let mut app = app_from(content);
+	app.notes[0].title = "Renamed".to_string();
+	let diff = save_diff(content, &serialize(&app.notes), "test.org");
+	let changed: Vec<&str> = diff
+		.lines()
+		.filter(|line| {
+			line.starts_with(['+', '-']) && !line.starts_with("+++") && !line.starts_with("---")
+		})
+		.collect();
+	assert_eq!(
+		changed,
+		vec!["-* TODO [#A] Task :work:", "+* TODO [#A] Renamed :work:"]
+	);
+}
+
+#[test]
+fn test_save_preview_cancel_keeps_file() {
+	let mut app = app_from("* Task\n");
+	app.notes[0].title = "Renamed".to_string();
+	app.save_preview = Some(SavePreview {
+		lines: vec!["-* Task".to_string(), "+* Renamed".to_string()],
+		scroll: 0,
+	});
+
+	handle_save_preview_input(&mut app, KeyCode::Down, None);
+	assert_eq!(app.save_preview.as_ref().unwrap().scroll, 1);
+	handle_save_preview_input(&mut app, KeyCode::Down, None);
+	assert_eq!(app.save_preview.as_ref().unwrap().scroll, 1);
+
+	handle_save_preview_input(&mut app, KeyCode::Char('q'), Some(Action::Quit));
+	assert!(app.save_preview.is_none());
+	assert_eq!(app.status_message, "Save cancelled");
+}
+
+#[test]
+fn test_selection_follows_note_id_through_tree_edits() {
+	let mut app = app_from("* Work\n** Design\n** Build\n* Home\n** Garden\n* Inbox\n");
+	let selected_title = |app: &App| app.get_selected_note().unwrap().title.clone();
+
+	// Deleting a subtree selects the note that followed it
+	app.select_note(3);
+	let home_id = app.get_selected_note().unwrap().id;
+	app.delete_selected_note();
+	assert_eq!(selected_title(&app), "Inbox");
+	assert_eq!(app.selected_note_idx, 3);
+	assert!(iter_notes(&app.notes).all(|(_, note)| note.id != home_id));
+
+	// Deleting the last note selects the one before it
+	app.delete_selected_note();
+	assert_eq!(selected_title(&app), "Build");
+
+	// Inserting notes above the selection keeps the same note selected
+	app.notes.insert(0, OrgNote::new(1, "Top".to_string()));
+	app.notes[1]
+		.children
+		.insert(0, OrgNote::new(2, "Plan".to_string()));
+	app.refresh_flat_notes();
+	assert_eq!(selected_title(&app), "Build");
+	assert_eq!(app.selected_note_idx, 4);
+	assert_eq!(app.list_state.selected(), Some(4));
+}
+
+#[test]
+fn test_cursor_offset_counts_display_columns() {
+	assert_eq!(cursor_offset(""), (0, 0));
+	assert_eq!(cursor_offset("abc"), (3, 0));
+	// Two-byte Cyrillic letters take one column each
+	assert_eq!(cursor_offset("2023-03-29 Ср"), (13, 0));
+	// Wide characters take two columns, combining marks none
+	assert_eq!(cursor_offset("日本"), (4, 0));
+	assert_eq!(cursor_offset("e\u{301}"), (1, 0));
+	assert_eq!(cursor_offset("first line\nб日"), (3, 1));
+	// A trailing newline puts the cursor at the start of the next line
+	assert_eq!(cursor_offset("abc\n"), (0, 1));
+
+	assert_eq!(wrapped_line_count("日本語日本語", 10), 2);
+	assert_eq!(wrapped_line_count("Ср Ср Ср", 8), 1);
+}
+
+#[test]
+fn test_emit_output_to_file_matches_stdout_bytes() {
+	let notes = sample_tree();
+	let mut stdout_bytes = Vec::new();
+	print_notes(&mut stdout_bytes, &notes, "json").unwrap();
+
+	let path = std::env::temp_dir().join(format!("rorg-output-{}.json", std::process::id()));
+	emit_output(&stdout_bytes, Some(&path), false).unwrap();
+	assert_eq!(std::fs::read(&path).unwrap(), stdout_bytes);
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_emit_output_backup_keeps_original() {
+	let path = std::env::temp_dir().join(format!("rorg-backup-{}.org", std::process::id()));
+	let backup_path = path.with_extension("org.bak");
+	std::fs::write(&path, "* Original\n").unwrap();
+
+	emit_output(b"* Normalized\n", Some(&path), true).unwrap();
+	assert_eq!(std::fs::read_to_string(&path).unwrap(), "* Normalized\n");
+	assert_eq!(
+		std::fs::read_to_string(&backup_path).unwrap(),
+		"* Original\n"
+	);
+	std::fs::remove_file(&path).unwrap();
+	std::fs::remove_file(&backup_path).unwrap();
+}
+
+#[test]
+fn test_run_status_for_empty_and_matching_results() {
+	let dir = std::env::temp_dir();
+	let input = dir.join(format!("rorg-status-{}.org", std::process::id()));
+	let output = dir.join(format!("rorg-status-{}.yaml", std::process::id()));
+	let run_with = |args: &[&str]| {
+		let mut argv = vec!["rorg", input.to_str().unwrap(), "--no-tui", "-o"];
+		argv.push(output.to_str().unwrap());
+		argv.extend(args);
+		run(&cli().try_get_matches_from(argv).unwrap())
+	};
+
+	std::fs::write(&input, "* TODO Open\n* DONE Finished\n").unwrap();
+	assert_eq!(run_with(&["--todo-only"]), Ok(RunStatus::Success));
+	assert_eq!(run_with(&["--links"]), Ok(RunStatus::NoMatches));
+	assert_eq!(run_with(&["--check"]), Ok(RunStatus::Problems));
+	assert_eq!(
+		std::fs::read_to_string(&output).unwrap(),
+		format!(
+			"{}:2: * DONE Finished: done task has no CLOSED timestamp\n1 problems found\n",
+			input.display()
+		)
+	);
+
+	std::fs::write(&input, "* DONE Finished\nCLOSED: [2024-01-02 Tue 10:00]\n").unwrap();
+	assert_eq!(run_with(&["--todo-only"]), Ok(RunStatus::NoMatches));
+	assert_eq!(run_with(&["--check"]), Ok(RunStatus::Success));
+
+	std::fs::remove_file(&input).unwrap();
+	std::fs::remove_file(&output).unwrap();
+	assert!(run_with(&[]).is_err());
+}
+
+#[test]
+fn test_directory_input_combines_files_and_records_sources() {
+	let dir = std::env::temp_dir().join(format!("rorg-dir-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(dir.join("projects")).unwrap();
+	std::fs::create_dir_all(dir.join("archive")).unwrap();
+	std::fs::write(dir.join("inbox.org"), "* TODO Inbox item\n").unwrap();
+	std::fs::write(dir.join("projects/garden.org"), "* Garden\n** TODO Weed\n").unwrap();
+	std::fs::write(dir.join("archive/old.org"), "* Old\n").unwrap();
+	std::fs::write(dir.join("readme.txt"), "* Not org\n").unwrap();
+
+	let output = std::env::temp_dir().join(format!("rorg-dir-{}.json", std::process::id()));
+	let argv = [
+		"rorg",
+		dir.to_str().unwrap(),
+		"--exclude",
+		"archive",
+		"-f",
+		"json",
+		"-o",
+		output.to_str().unwrap(),
+	];
+	assert_eq!(
+		run(&cli().try_get_matches_from(argv).unwrap()),
+		Ok(RunStatus::Success)
+	);
+
+	let notes: Vec<OrgNote> =
+		serde_json::from_str(&std::fs::read_to_string(&output).unwrap()).unwrap();
+	let sources: Vec<(&str, &str)> = iter_notes(&notes)
+		.map(|(_, note)| (note.title.as_str(), note.source_file.as_deref().unwrap()))
+		.collect();
+	let inbox = dir.join("inbox.org").display().to_string();
+	let garden = dir.join("projects/garden.org").display().to_string();
+	assert_eq!(
+		sources,
+		vec![
+			("Inbox item", inbox.as_str()),
+			("Garden", garden.as_str()),
+			("Weed", garden.as_str()),
+		]
+	);
+
+	// Subdirectories are always read, so --recursive changes nothing
+	let walked = std::fs::read_to_string(&output).unwrap();
+	let mut recursive = argv.to_vec();
+	recursive.push("--recursive");
+	assert_eq!(
+		run(&cli().try_get_matches_from(recursive).unwrap()),
+		Ok(RunStatus::Success)
+	);
+	assert_eq!(std::fs::read_to_string(&output).unwrap(), walked);
+
+	std::fs::remove_dir_all(&dir).unwrap();
+	std::fs::remove_file(&output).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directory_input_reads_symlink_loops_once() {
+	let dir = std::env::temp_dir().join(format!("rorg-loop-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(dir.join("notes")).unwrap();
+	std::fs::write(dir.join("notes/inbox.org"), "* Inbox\n").unwrap();
+	std::os::unix::fs::symlink(&dir, dir.join("notes/back")).unwrap();
+
+	assert_eq!(
+		org_files_in(&dir, &[]).unwrap(),
+		vec![dir.join("notes/inbox.org")]
+	);
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_capture_template_instantiation() {
+	let mut templates = HashMap::new();
+	let warnings = load_config(
+		r#"
+[templates.errand]
+title = "Buy milk"
+status = "TODO"
+priority = "B"
+tags = ["home", "shop"]
+scheduled = "today"
+deadline = "<2024-03-01 Fri 18:00>"
+parent = "Home"
+save = true
+"#,
+		&mut Keymap::default(),
+		&mut StatusStyles::default(),
+		&mut templates,
+		&mut Keywords::default(),
+	);
+	assert!(warnings.is_empty());
+	let template = &templates["errand"];
+	assert!(template.save);
+
+	let now = NaiveDateTime::parse_from_str("2024-02-27 09:30", "%Y-%m-%d %H:%M").unwrap();
+	let note = template.instantiate(now).unwrap();
+	assert_eq!(note.heading_text(), "TODO [#B] Buy milk");
+	assert_eq!(note.labels, vec!["home", "shop"]);
+	let planning = note.planning.as_ref().unwrap();
+	assert_eq!(planning.scheduled.as_ref().unwrap().raw, "<2024-02-27 Tue>");
+	assert_eq!(
+		planning.deadline.as_ref().unwrap().to_string(),
+		"<2024-03-01 Fri 18:00>"
+	);
+
+	let now_template = CaptureTemplate {
+		scheduled: Some("now".to_string()),
+		..CaptureTemplate::default()
+	};
+	let note = now_template.instantiate(now).unwrap();
+	assert_eq!(note.title, "New Note");
+	assert_eq!(note.status, None);
+	let scheduled = note.planning.unwrap().scheduled.unwrap();
+	assert_eq!(scheduled.raw, "<2024-02-27 Tue 09:30>");
+
+	let bad = CaptureTemplate {
+		deadline: Some("tomorrow-ish".to_string()),
+		..CaptureTemplate::default()
+	};
+	assert!(bad.instantiate(now).is_err());
+}
+
+#[test]
+fn test_capture_note_files_under_parent() {
+	let now = NaiveDateTime::parse_from_str("2024-02-27 09:30", "%Y-%m-%d %H:%M").unwrap();
+	let mut notes = sample_tree();
+	let template = CaptureTemplate {
+		title: "Captured".to_string(),
+		parent: Some("First/Child A".to_string()),
+		..CaptureTemplate::default()
+	};
+	assert_eq!(capture_note(&mut notes, &template, now), Ok(3));
+	let captured = &notes[0].children[0].children[1];
+	assert_eq!((captured.level, captured.title.as_str()), (3, "Captured"));
+
+	let top_level = CaptureTemplate::default();
+	assert_eq!(capture_note(&mut notes, &top_level, now), Ok(6));
+	assert_eq!(notes.last().unwrap().level, 1);
+
+	let missing = CaptureTemplate {
+		parent: Some("Nowhere".to_string()),
+		..CaptureTemplate::default()
+	};
+	assert!(capture_note(&mut notes, &missing, now).is_err());
+	assert_eq!(iter_notes(&notes).count(), 7);
+}
+
+#[test]
+fn test_jump_to_next_todo_and_overdue() {
+	let mut app = app_from(
+		r#"* Project
+** DONE Setup
+** TODO Write tests
+DEADLINE: <2999-01-01 Tue>
+* Errands
+** TODO Post letter
+DEADLINE: <2020-01-01 Wed>
+* IN-PROGRESS Review
+"#,
+	);
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 2);
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 4);
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 5);
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 2);
+	app.jump_to_next(prev_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 5);
+
+	// A jump into a folded subtree unfolds it
+	app.select_note(3);
+	app.toggle_fold();
+	app.select_note(0);
+	app.jump_to_next(next_overdue, "No overdue deadlines");
+	assert_eq!(app.selected_note_idx, 4);
+	assert_eq!(app.list_state.selected(), Some(4));
+	app.jump_to_next(next_overdue, "No overdue deadlines");
+	assert_eq!(app.selected_note_idx, 4);
+
+	let mut app = app_from("* DONE Finished\n* Plain note\n");
+	app.jump_to_next(next_todo, "No open tasks");
+	assert_eq!(app.selected_note_idx, 0);
+	assert_eq!(app.status_message, "No open tasks");
+}
+
+#[test]
+fn test_relative_date_label() {
+	let now = NaiveDateTime::parse_from_str("2024-05-29 14:00", "%Y-%m-%d %H:%M").unwrap();
+	let label = |text: &str| relative_date_label(&text.parse::<OrgTimestamp>().unwrap(), now);
+
+	// Future
+	assert_eq!(label("<2024-06-01 Sat>").as_deref(), Some("in 3 days"));
+	assert_eq!(label("<2024-05-30 Thu 09:00>").as_deref(), Some("in 1 day"));
+	assert_eq!(
+		label("<2024-05-29 Wed 15:30>").as_deref(),
+		Some("in 1h 30m")
+	);
+	// Today
+	assert_eq!(label("<2024-05-29 Wed>").as_deref(), Some("today"));
+	assert_eq!(label("<2024-05-29 Wed 14:00>").as_deref(), Some("now"));
+	// Past
+	assert_eq!(
+		label("<2024-05-29 Wed 13:15>").as_deref(),
+		Some("0h 45m ago")
+	);
+	assert_eq!(
+		label("<2024-05-28 Tue 23:00>").as_deref(),
+		Some("1 day ago")
+	);
+	assert_eq!(label("[2024-05-01 Wed]").as_deref(), Some("28 days ago"));
+	// Not a real date
+	assert_eq!(label("<2024-02-30 Fri>"), None);
+}
+
+#[test]
+fn test_tabs_expand_to_tab_stops_for_display() {
+	assert_eq!(expand_tabs("\tindented"), "        indented");
+	assert_eq!(expand_tabs("ab\tc\n\td"), "ab      c\n        d");
+	assert_eq!(expand_tabs("日本\tx"), "日本    x");
+	assert_eq!(cursor_offset("a\tb"), (9, 0));
+	assert_eq!(cursor_offset("line\n\t"), (8, 1));
+
+	let mut app = app_from("* Tabbed\n\tindented\tline\n");
+	app.select_note(0);
+	assert_eq!(app.get_selected_note().unwrap().content, "\tindented\tline");
+}
+
+#[test]
+fn test_marking_repeating_task_done_keeps_it_open() {
+	let mut app = app_from("* NEXT Review\nSCHEDULED: <2024-01-01 Mon +1w>\n");
+
+	app.selected_field_idx = 0;
+	start_editing(&mut app);
+	app.edit_buffer = "DONE".to_string();
+	commit_edit(&mut app);
+
+	let note = &app.notes[0];
+	assert_eq!(note.status.as_deref(), Some("NEXT"));
+	let scheduled = note.planning.as_ref().unwrap().scheduled.as_ref().unwrap();
+	assert_eq!(scheduled.raw, "<2024-01-08 Mon +1w>");
+	assert!(note.property("LAST_REPEAT").is_some());
+	assert!(app.modified);
+}
+
+#[test]
+fn test_empty_and_blank_files_start_without_selection() {
+	for content in ["", "  \n\n\t\n"] {
+		let mut app = app_from(content);
+		assert!(app.notes.is_empty());
+		assert_eq!(app.list_state.selected(), None);
+		assert!(app.get_selected_note().is_none());
+		assert!(app.status_message.starts_with("No notes yet"));
+
+		for key in [
+			KeyCode::Up,
+			KeyCode::Down,
+			KeyCode::Char('z'),
+			KeyCode::Char('E'),
+		] {
+			handle_left_panel_input(&mut app, key);
+		}
+		app.focus = Focus::Right;
+		for key in [KeyCode::Up, KeyCode::Down, KeyCode::PageDown] {
+			handle_right_panel_input(&mut app, key);
+		}
+		start_editing(&mut app);
+		commit_edit(&mut app);
+		app.clock_in();
+		app.clock_out();
+		app.clock_out_everywhere();
+		app.set_current_time("scheduled");
+		set_focused_field_to_now(&mut app);
+		app.archive_selected_note();
+		app.delete_selected_note();
+		app.jump_to_next(next_todo, "No unfinished tasks");
+		assert!(app.notes.is_empty());
+		assert!(!app.modified);
+
+		let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+		app.show_tag_totals = true;
+		terminal.draw(|f| ui(f, &mut app)).unwrap();
+		let screen: String = terminal
+			.backend()
+			.buffer()
+			.content()
+			.iter()
+			.map(|cell| cell.symbol.as_str())
+			.collect();
+		assert!(screen.contains("No notes"));
+		assert!(screen.contains("Metadata"));
+		assert!(screen.contains("Content"));
+
+		app.add_note(true);
+		assert_eq!(app.notes.len(), 1);
+		assert_eq!(app.list_state.selected(), Some(0));
+	}
+}
+
+#[test]
+fn test_cli_modes_accept_empty_input() {
+	let dir = std::env::temp_dir();
+	let input = dir.join(format!("rorg-empty-{}.org", std::process::id()));
+	let output = dir.join(format!("rorg-empty-{}.out", std::process::id()));
+	let run_with = |args: &[&str]| {
+		let mut argv = vec!["rorg", input.to_str().unwrap(), "--no-tui", "-o"];
+		argv.push(output.to_str().unwrap());
+		argv.extend(args);
+		let status = run(&cli().try_get_matches_from(argv).unwrap());
+		(status, std::fs::read_to_string(&output).unwrap())
+	};
+
+	for content in ["", " \n\t\n\n"] {
+		std::fs::write(&input, content).unwrap();
+		assert_eq!(
+			run_with(&[]),
+			(Ok(RunStatus::NoMatches), "[]\n\n".to_string())
+		);
+		assert_eq!(
+			run_with(&["--format", "json"]),
+			(Ok(RunStatus::NoMatches), "[]\n".to_string())
+		);
+		for format in ["org", "markdown", "tree"] {
+			assert_eq!(
+				run_with(&["--format", format]),
+				(Ok(RunStatus::NoMatches), String::new())
+			);
+		}
+		let (status, summary) = run_with(&["--summary"]);
+		assert_eq!(status, Ok(RunStatus::NoMatches));
+		assert!(summary.contains("Total tracked time: 0h 0m"));
+		assert!(
+			run_with(&["--wordcount"])
+				.1
+				.contains("Total: 0 words, 0 characters")
+		);
+		assert_eq!(run_with(&["--links"]).0, Ok(RunStatus::NoMatches));
+		assert_eq!(run_with(&["--check-overlaps"]).0, Ok(RunStatus::NoMatches));
+		assert_eq!(run_with(&["--check"]).0, Ok(RunStatus::Success));
+		assert_eq!(
+			run_with(&["--flat", "--max-depth", "1"]).0,
+			Ok(RunStatus::NoMatches)
+		);
+	}
+
+	std::fs::remove_file(&input).unwrap();
+	std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn test_first_and_last_slice_top_level_notes() {
+	let dir = std::env::temp_dir();
+	let input = dir.join(format!("rorg-slice-{}.org", std::process::id()));
+	let output = dir.join(format!("rorg-slice-{}.out", std::process::id()));
+	std::fs::write(
+		&input,
+		"* one\n** one child\n* two\n* three\n** three child\n* four\n",
+	)
+	.unwrap();
+	let run_with = |args: &[&str]| {
+		let mut argv = vec![
+			"rorg",
+			input.to_str().unwrap(),
+			"--no-tui",
+			"--format",
+			"org",
+			"-o",
+		];
+		argv.push(output.to_str().unwrap());
+		argv.extend(args);
+		run(&cli().try_get_matches_from(argv).unwrap()).unwrap();
+		std::fs::read_to_string(&output).unwrap()
+	};
+
+	assert_eq!(run_with(&["--first", "2"]), "* one\n** one child\n* two\n");
+	assert_eq!(
+		run_with(&["--last", "2"]),
+		"* three\n** three child\n* four\n"
+	);
+	assert_eq!(run_with(&["--last", "10"]).lines().count(), 6);
+	assert_eq!(run_with(&["--first", "0"]), "");
+
+	let both = cli().try_get_matches_from([
+		"rorg",
+		input.to_str().unwrap(),
+		"--first",
+		"1",
+		"--last",
+		"1",
+	]);
+	assert_eq!(
+		both.unwrap_err().kind(),
+		clap::error::ErrorKind::ArgumentConflict
+	);
+
+	std::fs::remove_file(&input).unwrap();
+	std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn test_debounce_waits_for_changes_to_settle() {
+	let start = Instant::now();
+	let at = |ms| start + Duration::from_millis(ms);
+	let mut debounce = Debounce::new(Duration::from_millis(200));
+
+	assert!(!debounce.settled(at(0)));
+	debounce.changed(at(0));
+	debounce.changed(at(150));
+	assert!(!debounce.settled(at(300)));
+	debounce.changed(at(320));
+	assert!(!debounce.settled(at(500)));
+	assert!(debounce.settled(at(520)));
+	// One reload per burst
+	assert!(!debounce.settled(at(900)));
+}
+
+#[test]
+fn test_watch_rejects_writing_the_watched_file() {
+	for flag in ["--capture=todo", "--clock-out-all"] {
+		let matches =
+			cli().try_get_matches_from(["rorg", "plans.org", "--no-tui", "--watch", flag]);
+		assert_eq!(
+			matches.unwrap_err().kind(),
+			clap::error::ErrorKind::ArgumentConflict
+		);
+	}
+
+	let input = std::env::temp_dir().join(format!("rorg-watch-{}.org", std::process::id()));
+	std::fs::write(&input, "* a\n").unwrap();
+	let input_str = input.to_str().unwrap();
+	let dotted = input
+		.parent()
+		.unwrap()
+		.join(".")
+		.join(input.file_name().unwrap());
+	let watch = |output: &str| {
+		output_is_watched(
+			&cli()
+				.try_get_matches_from(["rorg", input_str, "--no-tui", "--watch", "-o", output])
+				.unwrap(),
+		)
+	};
+	assert!(watch(input_str));
+	assert!(watch(dotted.to_str().unwrap()));
+	assert!(!watch("elsewhere.org"));
+	std::fs::remove_file(&input).unwrap();
+
+	// With a directory, any output below it would be read back in
+	let dir = std::env::temp_dir().join(format!("rorg-watch-dir-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("sub")).unwrap();
+	let dir_str = dir.to_str().unwrap();
+	let watch_dir = |output: &std::path::Path| {
+		output_is_watched(
+			&cli()
+				.try_get_matches_from(["rorg", dir_str, "--watch", "-o", output.to_str().unwrap()])
+				.unwrap(),
+		)
+	};
+	assert!(watch_dir(&dir.join("out.org")));
+	assert!(watch_dir(&dir.join("sub/../sub/out.org")));
+	assert!(!watch_dir(&std::env::temp_dir().join("rorg-watch-out.org")));
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reload_decision_and_reload() {
+	assert_eq!(
+		reload_decision("* a\n", "* a\n", true),
+		ReloadDecision::Ignore
+	);
+	assert_eq!(
+		reload_decision("* b\n", "* a\n", false),
+		ReloadDecision::Reload
+	);
+	assert_eq!(reload_decision("* b\n", "* a\n", true), ReloadDecision::Ask);
+
+	let path = std::env::temp_dir().join(format!("rorg-reload-{}.org", std::process::id()));
+	std::fs::write(&path, "* one\n* two\n** nested\n").unwrap();
+	let mut app = App::new(
+		parse("* one\n* two\n** nested\n"),
+		path.to_str().unwrap().to_string(),
+	);
+	app.select_note(2);
+
+	// The file still holds what the TUI would save, as right after saving
+	let before = app.status_message.clone();
+	app.file_changed();
+	assert_eq!(app.status_message, before);
+
+	// The selected note is followed to its new position
+	std::fs::write(&path, "* zero\n* one\n* two\n** first\n** nested\n").unwrap();
+	app.file_changed();
+	assert_eq!(app.notes.len(), 3);
+	assert_eq!(app.get_selected_note().unwrap().title, "nested");
+	assert_eq!(app.selected_note_idx, 4);
+	assert!(!app.modified);
+
+	app.add_note(false);
+	assert!(app.modified);
+	std::fs::write(&path, "* elsewhere\n").unwrap();
+	app.file_changed();
+	assert!(app.reload_pending);
+	assert_eq!(iter_notes(&app.notes).count(), 6);
+	handle_reload_prompt(&mut app, KeyCode::Esc);
+	assert!(!app.reload_pending);
+	assert_eq!(iter_notes(&app.notes).count(), 6);
+
+	app.reload_pending = true;
+	handle_reload_prompt(&mut app, KeyCode::Char('r'));
+	assert_eq!(app.notes.len(), 1);
+	assert_eq!(app.notes[0].title, "elsewhere");
+	assert!(!app.modified);
+
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_changed_on_disk() {
+	let loaded = Some(content_hash("* one\n"));
+
+	assert!(!changed_on_disk(loaded, Some("* one\n")));
+	assert!(changed_on_disk(loaded, Some("* one\n* two\n")));
+	assert!(!changed_on_disk(loaded, None));
+	assert!(!changed_on_disk(None, Some("* one\n")));
+}
+
+#[test]
+fn test_save_asks_before_overwriting_outside_edits() {
+	let path = std::env::temp_dir().join(format!("rorg-conflict-{}.org", std::process::id()));
+	std::fs::write(&path, "* one\n").unwrap();
+	let mut app = App::new(parse("* one\n"), path.to_str().unwrap().to_string());
+	app.disk_hash = Some(content_hash("* one\n"));
+
+	app.add_note(false);
+	app.save();
+	assert!(app.save_preview.is_none());
+	assert_eq!(
+		std::fs::read_to_string(&path).unwrap(),
+		serialize(&app.notes)
+	);
+
+	// Someone else edits the file, then the TUI saves again
+	let theirs = format!("{}* theirs\n", serialize(&app.notes));
+	std::fs::write(&path, &theirs).unwrap();
+	app.add_note(false);
+	app.save();
+	assert!(app.save_preview.is_some());
+	assert!(app.status_message.contains("changed on disk"));
+	assert!(app.modified);
+	handle_save_preview_input(&mut app, KeyCode::Esc, None);
+	assert_eq!(std::fs::read_to_string(&path).unwrap(), theirs);
+
+	app.save();
+	handle_save_preview_input(&mut app, KeyCode::Enter, None);
+	assert!(!app.modified);
+	assert_eq!(
+		std::fs::read_to_string(&path).unwrap(),
+		serialize(&app.notes)
+	);
+
+	// Once overwritten, the TUI's own save is what it compares against
+	app.add_note(false);
+	app.save();
+	assert!(app.save_preview.is_none());
+	assert!(!app.modified);
+
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_save_reports_write_errors() {
+	let path = std::env::temp_dir()
+		.join(format!("rorg-missing-{}", std::process::id()))
+		.join("notes.org");
+	let mut app = App::new(parse("* one\n"), path.to_str().unwrap().to_string());
+	app.add_note(false);
+
+	app.save();
+	assert!(app.status_message.starts_with("Error saving"));
+	assert!(app.modified);
+
+	app.save_preview = Some(SavePreview {
+		lines: vec!["+* two".to_string()],
+		scroll: 0,
+	});
+	handle_save_preview_input(&mut app, KeyCode::Enter, None);
+	assert!(app.save_preview.is_none());
+	assert!(app.status_message.starts_with("Error saving"));
+	assert!(app.modified);
+}
+
+#[test]
+fn test_timestamp_input_accepts_relative_dates() {
+	let now = NaiveDateTime::parse_from_str("2024-01-17 15:30", "%Y-%m-%d %H:%M").unwrap();
+	let raw = |text: &str, kind| parse_timestamp_input(text, kind, now).map(|t| t.raw);
+
+	assert_eq!(
+		raw("+3d", TimestampKind::Active).as_deref(),
+		Some("<2024-01-20 Sat>")
+	);
+	assert_eq!(
+		raw("mon", TimestampKind::Inactive).as_deref(),
+		Some("[2024-01-22 Mon]")
+	);
+	assert_eq!(
+		raw(" <2024-03-01 Fri 09:00> ", TimestampKind::Inactive).as_deref(),
+		Some("<2024-03-01 Fri 09:00>")
+	);
+	assert_eq!(raw("someday", TimestampKind::Active), None);
+
+	let mut app = app_from("* TODO Task\n");
+	start_planning_edit(&mut app, EditMode::Deadline);
+	app.edit_buffer = "today".to_string();
+	commit_edit(&mut app);
+	let deadline = app.notes[0].planning.as_ref().unwrap().deadline.as_ref();
+	assert_eq!(
+		deadline.and_then(OrgTimestamp::to_naive_date),
+		Some(Local::now().date_naive())
+	);
+}
+
+#[test]
+fn test_grep_lists_matching_headings() {
+	let dir = std::env::temp_dir();
+	let input = dir.join(format!("rorg-grep-{}.org", std::process::id()));
+	let output = dir.join(format!("rorg-grep-{}.out", std::process::id()));
+	std::fs::write(
+		&input,
+		"* TODO Buy milk\n* Work\n** Call the dairy\nAsk about MILK prices\n",
+	)
+	.unwrap();
+	let name = input.to_str().unwrap();
+	let grep = |args: &[&str]| {
+		let mut argv = vec!["rorg", name, "--no-tui", "-o", output.to_str().unwrap()];
+		argv.extend(args);
+		let status = run(&cli().try_get_matches_from(argv).unwrap());
+		(status, std::fs::read_to_string(&output).unwrap())
+	};
+
+	assert_eq!(
+		grep(&["--grep", "milk"]),
+		(
+			Ok(RunStatus::Success),
+			format!(
+				"{0}:1: TODO Buy milk\n{0}:3: Call the dairy\n    Ask about MILK prices\n",
+				name
+			)
+		)
+	);
+	assert_eq!(
+		grep(&["--grep", "MILK", "--case-sensitive"]),
+		(
+			Ok(RunStatus::Success),
+			format!("{}:3: Call the dairy\n    Ask about MILK prices\n", name)
+		)
+	);
+	assert_eq!(
+		grep(&["--grep", "cheese"]),
+		(Ok(RunStatus::NoMatches), String::new())
+	);
+	assert!(
+		cli()
+			.try_get_matches_from(["rorg", name, "--case-sensitive"])
+			.is_err()
+	);
+
+	std::fs::remove_file(&input).unwrap();
+	std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn test_save_keeps_text_before_first_heading() {
+	let path = std::env::temp_dir().join(format!("rorg-preamble-{}.org", std::process::id()));
+	let content = "#+TITLE: Plans\n#+startup: overview\n* one\n";
+	std::fs::write(&path, content).unwrap();
+	let mut app = App::new(parse(content), path.to_str().unwrap().to_string());
+	app.preamble = rorg::preamble(content).to_string();
+
+	app.add_note(false);
+	app.save();
+	let saved = std::fs::read_to_string(&path).unwrap();
+	assert!(saved.starts_with("#+TITLE: Plans\n#+startup: overview\n* one\n"));
+	assert_eq!(parse(&saved).len(), 2);
+
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_shallow_output_counts_children() {
+	let dir = std::env::temp_dir();
+	let input = dir.join(format!("rorg-shallow-{}.org", std::process::id()));
+	let output = dir.join(format!("rorg-shallow-{}.json", std::process::id()));
+	std::fs::write(&input, "* one\n** child\n*** grandchild\n** child\n* two\n").unwrap();
+	let run_with = |args: &[&str]| {
+		let mut argv = vec![
+			"rorg",
+			input.to_str().unwrap(),
+			"--no-tui",
+			"--format",
+			"json",
+			"--shallow",
+			"-o",
+		];
+		argv.push(output.to_str().unwrap());
+		argv.extend(args);
+		run(&cli().try_get_matches_from(argv).unwrap()).unwrap();
+		let json = std::fs::read_to_string(&output).unwrap();
+		serde_json::from_str::<serde_json::Value>(&json).unwrap()
+	};
+
+	let notes = run_with(&[]);
+	let notes = notes.as_array().unwrap();
+	assert_eq!(notes.len(), 2);
+	assert_eq!(notes[0]["title"], "one");
+	assert_eq!(notes[0]["children_count"], 2);
+	assert_eq!(notes[1]["children_count"], 0);
+	assert!(notes.iter().all(|note| note.get("children").is_none()));
+
+	// Pruned children are still counted
+	let pruned = run_with(&["--max-depth", "1", "--count-pruned"]);
+	assert_eq!(pruned[0]["children_count"], 2);
+
+	assert_eq!(
+		cli()
+			.try_get_matches_from(["rorg", "--shallow", "--flat"])
+			.unwrap_err()
+			.kind(),
+		clap::error::ErrorKind::ArgumentConflict
+	);
+}
+
+#[test]
+fn test_timeline_columns_and_bars() {
+	let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+	let day = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+	assert_eq!(date_column(day(4), today, 7), Some(0));
+	assert_eq!(date_column(day(10), today, 7), Some(6));
+	assert_eq!(date_column(day(11), today, 7), None);
+	assert_eq!(date_column(day(3), today, 7), None);
+
+	let notes = parse(
+		"* TODO Both\nSCHEDULED: <2024-03-05 Tue> DEADLINE: <2024-03-08 Fri>\n* Scheduled\nSCHEDULED: <2024-03-06 Wed>\n* Deadline\nDEADLINE: <2024-03-04 Mon>\n* Started earlier\nSCHEDULED: <2024-02-20 Tue> DEADLINE: <2024-03-20 Wed>\n* Later\nSCHEDULED: <2024-03-11 Mon>\n* Past\nDEADLINE: <2024-03-01 Fri>\n* Undated\n",
+	);
+	let bars: Vec<Option<(usize, usize)>> = notes
+		.iter()
+		.map(|note| timeline_bar(note, today, 7))
+		.collect();
+	assert_eq!(
+		bars,
+		vec![
+			Some((1, 4)),
+			Some((2, 2)),
+			Some((0, 0)),
+			Some((0, 6)),
+			None,
+			None,
+			None
+		]
+	);
+
+	let mut app = App::new(notes, "timeline.org".to_string());
+	app.show_timeline = true;
+	let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+	terminal.draw(|f| ui(f, &mut app)).unwrap();
+	let screen: String = terminal
+		.backend()
+		.buffer()
+		.content()
+		.iter()
+		.map(|cell| cell.symbol.as_str())
+		.collect();
+	assert!(screen.contains("Timeline, next 14 days"));
+	assert!(!screen.contains("Metadata"));
+}
+
+#[test]
+fn test_resolve_startup_heading() {
+	let content = "#+TITLE: Plans\n* Work\n** Report\nDraft it.\n* Home\n** Report\n";
+	let notes = OrgParser::new(content).parse();
+
+	assert_eq!(resolve_goto_title(&notes, "Report"), Some(1));
+	assert_eq!(resolve_goto_title(&notes, " Home "), Some(2));
+	assert_eq!(resolve_goto_title(&notes, "Home/Report"), Some(3));
+	assert_eq!(resolve_goto_title(&notes, "4"), Some(3));
+	assert_eq!(resolve_goto_title(&notes, "Garden"), None);
+
+	assert_eq!(resolve_goto_line(&notes, 1), None);
+	assert_eq!(resolve_goto_line(&notes, 2), Some(0));
+	assert_eq!(resolve_goto_line(&notes, 4), Some(1));
+	assert_eq!(resolve_goto_line(&notes, 5), Some(2));
+	assert_eq!(resolve_goto_line(&notes, 60), Some(3));
+
+	let mut app = App::new(notes, "plans.org".to_string());
+	app.select_note(resolve_goto_title(&app.notes, "Report").unwrap());
+	assert_eq!(app.get_selected_note().unwrap().title, "Report");
+	assert_eq!(app.list_state.selected(), Some(1));
+
+	assert!(
+		cli()
+			.try_get_matches_from(["rorg", "--goto", "Work", "--goto-line", "2"])
+			.is_err()
+	);
+}